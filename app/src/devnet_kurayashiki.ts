@@ -46,6 +46,12 @@ let usdc_token = devusdc_mint;
 let wsol_token = wrapped_sol_mint;
 let wallet_usdc = null;
 let price_info = null;
+let config = null;
+let oracle_sources = null;
+let pool_twap = null;
+let price_history = null;
+let treasury_stats = null;
+let pool_cursor = null;
 
 async function initialize() {
     const transaction = new Transaction();
@@ -73,15 +79,140 @@ async function initialize() {
     await connection.confirmTransaction(tx, commitment);
 }
 
+async function initialize_config() {
+  const transaction = new Transaction();
+  const ix = program.instruction.initializeConfig({
+      accounts: {
+          creator: wallet.publicKey,
+          config: config,
+          systemProgram: SystemProgram.programId,
+      },
+      signers: [wallet],
+  });
+  transaction.add(ix);
+
+  const tx = await connection.sendTransaction(
+      transaction,
+      [wallet],
+  );
+  console.log("\ttx signature", tx);
+  await connection.confirmTransaction(tx, commitment);
+}
+
+async function initialize_oracle_sources() {
+  const transaction = new Transaction();
+  const ix = program.instruction.initializeOracleSources({
+      accounts: {
+          creator: wallet.publicKey,
+          oracleSources: oracle_sources,
+          systemProgram: SystemProgram.programId,
+      },
+      signers: [wallet],
+  });
+  transaction.add(ix);
+
+  const tx = await connection.sendTransaction(
+      transaction,
+      [wallet],
+  );
+  console.log("\ttx signature", tx);
+  await connection.confirmTransaction(tx, commitment);
+}
+
+async function initialize_price_history() {
+  const transaction = new Transaction();
+  const ix = program.instruction.initializePriceHistory({
+      accounts: {
+          creator: wallet.publicKey,
+          priceHistory: price_history,
+          systemProgram: SystemProgram.programId,
+      },
+      signers: [wallet],
+  });
+  transaction.add(ix);
+
+  const tx = await connection.sendTransaction(
+      transaction,
+      [wallet],
+  );
+  console.log("\ttx signature", tx);
+  await connection.confirmTransaction(tx, commitment);
+}
+
+async function initialize_treasury_stats() {
+  const transaction = new Transaction();
+  const ix = program.instruction.initializeTreasuryStats({
+      accounts: {
+          creator: wallet.publicKey,
+          treasuryStats: treasury_stats,
+          usdcMint: usdc_token,
+          systemProgram: SystemProgram.programId,
+      },
+      signers: [wallet],
+  });
+  transaction.add(ix);
+
+  const tx = await connection.sendTransaction(
+      transaction,
+      [wallet],
+  );
+  console.log("\ttx signature", tx);
+  await connection.confirmTransaction(tx, commitment);
+}
+
+async function initialize_pool_cursor(num_pools) {
+  const transaction = new Transaction();
+  const ix = program.instruction.initializePoolCursor(
+      num_pools, {
+      accounts: {
+          creator: wallet.publicKey,
+          poolCursor: pool_cursor,
+          systemProgram: SystemProgram.programId,
+      },
+      signers: [wallet],
+  });
+  transaction.add(ix);
+
+  const tx = await connection.sendTransaction(
+      transaction,
+      [wallet],
+  );
+  console.log("\ttx signature", tx);
+  await connection.confirmTransaction(tx, commitment);
+}
+
+// round-robin index for neutralize
+async function get_next_pool_index() {
+  const pool_cursor_account_data = await program.account.poolCursor.fetch(pool_cursor);
+  return pool_cursor_account_data.nextIndex;
+}
+
+// latest entries first
+async function get_price_history() {
+  const price_history_account_data = await program.account.priceHistory.fetch(price_history);
+  const sequence = price_history_account_data.sequence.toNumber();
+  const entries = price_history_account_data.entries;
+  const history = [];
+  for (let s = sequence; s > 0 && s > sequence - entries.length; s--) {
+    history.push(entries[(s - 1) % entries.length]);
+  }
+  return history;
+}
+
 async function update_price() {
   const transaction = new Transaction();
   const ix = program.instruction.updatePrice({
       accounts: {
           creator: wallet.publicKey,
           priceInfo: price_info,
-          pythSolUsdcPrice: pyth_sol_usdc_price,
+          oracleSources: oracle_sources,
+          poolTwap: pool_twap,
+          priceHistory: price_history,
           clock: SYSVAR_CLOCK_PUBKEY,
       },
+      remainingAccounts: [
+          { pubkey: pyth_sol_usdc_price, isWritable: false, isSigner: false },
+      ],
       signers: [wallet],
   });
   transaction.add(ix);
@@ -96,6 +227,7 @@ async function update_price() {
 
 async function create_pool(index) {
   const [pool_deposit_sol, pool_deposit_usdc] = await get_pool_deposit_address(index);
+  const pool_stats = await get_pool_stats_address(index);
   console.log("\tindex", index);
   console.log("\tpool_deposit_sol", pool_deposit_sol.toBase58());
   console.log("\tpool_deposit_usdc", pool_deposit_usdc.toBase58());
@@ -107,6 +239,7 @@ async function create_pool(index) {
           creator: wallet.publicKey,
           poolDepositSol: pool_deposit_sol,
           poolDepositUsdc: pool_deposit_usdc,
+          poolStats: pool_stats,
           usdcMint: usdc_token,
           systemProgram: SystemProgram.programId,
           tokenProgram: TOKEN_PROGRAM_ID,
@@ -151,31 +284,111 @@ async function get_pool_deposit_address(index) {
   return [pool_deposit_sol, pool_deposit_usdc];
 }
 
+async function get_pool_stats_address(index) {
+  const [pool_stats,] = await PublicKey.findProgramAddress(
+    [
+      Uint8Array.from(Buffer.from("kurayashiki")),
+      Uint8Array.from(Buffer.from("nano_swap")),
+      Uint8Array.from(Buffer.from("pool_stats")),
+      toUint8Array(index),
+      wallet.publicKey.toBytes(),
+    ],
+    program.programId);
+  return pool_stats;
+}
+
+// for pools created before PoolStats was introduced
+async function initialize_pool_stats(index) {
+  const pool_stats = await get_pool_stats_address(index);
+  console.log("\tpool_stats", pool_stats.toBase58());
+
+  const transaction = new Transaction();
+  const ix = program.instruction.initializePoolStats(
+      index, {
+      accounts: {
+          creator: wallet.publicKey,
+          poolStats: pool_stats,
+          systemProgram: SystemProgram.programId,
+      },
+      signers: [wallet],
+  });
+  transaction.add(ix);
+
+  const tx = await connection.sendTransaction(
+      transaction,
+      [wallet],
+  );
+  console.log("\ttx signature", tx);
+  await connection.confirmTransaction(tx, commitment);
+}
+
+async function get_pool_target_address(index) {
+  const [pool_target,] = await PublicKey.findProgramAddress(
+    [
+      Uint8Array.from(Buffer.from("kurayashiki")),
+      Uint8Array.from(Buffer.from("nano_swap")),
+      Uint8Array.from(Buffer.from("pool_target")),
+      toUint8Array(index),
+      wallet.publicKey.toBytes(),
+    ],
+    program.programId);
+  return pool_target;
+}
+
+// SOL balance maintained by distribute_to_pool (0.1 SOL if not initialized)
+async function initialize_pool_target(index, target_lamports, low_water_lamports) {
+  const pool_target = await get_pool_target_address(index);
+  console.log("\tpool_target", pool_target.toBase58());
+
+  const transaction = new Transaction();
+  const ix = program.instruction.initializePoolTarget(
+      index, BN(target_lamports), BN(low_water_lamports), {
+      accounts: {
+          creator: wallet.publicKey,
+          poolTarget: pool_target,
+          systemProgram: SystemProgram.programId,
+      },
+      signers: [wallet],
+  });
+  transaction.add(ix);
+
+  const tx = await connection.sendTransaction(
+      transaction,
+      [wallet],
+  );
+  console.log("\ttx signature", tx);
+  await connection.confirmTransaction(tx, commitment);
+}
+
 async function neutralize(index) {
   // PDA
   const [pool_deposit_sol, pool_deposit_usdc] = await get_pool_deposit_address(index);
+  const pool_stats = await get_pool_stats_address(index);
   console.log("\tindex", index);
   console.log("\tpool_deposit_sol", pool_deposit_sol.toBase58());
   console.log("\tpool_deposit_usdc", pool_deposit_usdc.toBase58());
 
   const pre_tx_lamports = (await connection.getAccountInfo(wallet.publicKey)).lamports;
-  const price = await get_kurayashiki_price();
+  // accept the current price only
+  const max_price = await get_kurayashiki_price();
 
   console.log("\tpre_tx_lamports", pre_tx_lamports);
-  console.log("\tprice (kurayashiki, usdc/sol", price.toNumber());
+  console.log("\tmax_price (kurayashiki, usdc/sol", max_price.toNumber());
 
   const transaction = new Transaction();
   const ix = program.instruction.neutralize(
       index,
-      price,
+      max_price,
       BN(pre_tx_lamports), {
       accounts: {
           creator: wallet.publicKey,
           priceInfo: price_info,
+          priceHistory: price_history,
           poolDepositSol: pool_deposit_sol,
           poolDepositUsdc: pool_deposit_usdc,          
           user: wallet.publicKey,
           userUsdc: wallet_usdc,
+          poolStats: pool_stats,
           usdcMint: usdc_token,
           systemProgram: SystemProgram.programId,
           tokenProgram: TOKEN_PROGRAM_ID,
@@ -198,6 +411,10 @@ async function collect_from_pool(index1, index2, index3, index4) {
   const [pool_deposit_sol2, pool_deposit_usdc2] = await get_pool_deposit_address(index2);
   const [pool_deposit_sol3, pool_deposit_usdc3] = await get_pool_deposit_address(index3);
   const [pool_deposit_sol4, pool_deposit_usdc4] = await get_pool_deposit_address(index4);
+  const pool_stats1 = await get_pool_stats_address(index1);
+  const pool_stats2 = await get_pool_stats_address(index2);
+  const pool_stats3 = await get_pool_stats_address(index3);
+  const pool_stats4 = await get_pool_stats_address(index4);
 
   console.log("pool_deposit_sols", pool_deposit_sol1.toBase58(), pool_deposit_sol2.toBase58(), pool_deposit_sol3.toBase58(), pool_deposit_sol4.toBase58());
   console.log("pool_deposit_usdcs", pool_deposit_usdc1.toBase58(), pool_deposit_usdc2.toBase58(), pool_deposit_usdc3.toBase58(), pool_deposit_usdc4.toBase58());
@@ -218,6 +435,12 @@ async function collect_from_pool(index1, index2, index3, index4) {
           poolDepositUsdc3: pool_deposit_usdc3,          
           poolDepositSol4: pool_deposit_sol4,
           poolDepositUsdc4: pool_deposit_usdc4,          
+          poolStats1: pool_stats1,
+          poolStats2: pool_stats2,
+          poolStats3: pool_stats3,
+          poolStats4: pool_stats4,
+
+          treasuryStats: treasury_stats,
 
           usdcMint: devusdc_mint,
           tokenProgram: TOKEN_PROGRAM_ID,
@@ -255,8 +478,19 @@ async function distribute_to_pool(index1, index2, index3, index4) {
           poolDepositSol2: pool_deposit_sol2,
           poolDepositSol3: pool_deposit_sol3,
           poolDepositSol4: pool_deposit_sol4,
+          poolTarget1: await get_pool_target_address(index1),
+          poolTarget2: await get_pool_target_address(index2),
+          poolTarget3: await get_pool_target_address(index3),
+          poolTarget4: await get_pool_target_address(index4),
 
+          depositWsol: deposit_wsol,
+          temporaryDepositWsol: temporary_deposit_wsol,
+          treasuryStats: treasury_stats,
+
+          wsolMint: wsol_token,
           systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rent: SYSVAR_RENT_PUBKEY,
       },
       signers: [],
   });
@@ -273,28 +507,34 @@ async function distribute_to_pool(index1, index2, index3, index4) {
 
 async function convert_to_sol() {
   const transaction = new Transaction();
-  const ix = program.instruction.convertToSol({
+  // empty route: swap with the configured pool directly (no per-hop / overall minimum)
+  const ix = program.instruction.convertToSol([], BN(0), {
       accounts: {
           creator: wallet.publicKey,
+          config: config,
           depositSol: deposit_sol,
-          depositUsdc: deposit_usdc,
+          depositToken: deposit_usdc,
           temporaryDepositWsol: temporary_deposit_wsol,
-
-          orcaSwapProgram: ORCA_TOKEN_SWAP_ID_DEVNET,
-          orcaAddress: orca_address,
-          orcaAuthority: orca_authority,
-          orcaPoolWsol: orca_token_A_deposit,
-          orcaPoolUsdc: orca_token_B_deposit,
-          orcaPoolTokenMint: orca_pool_token_mint,
-          orcaFeeAccount: orca_fee_account,
+          depositWsol: deposit_wsol,
+          poolTwap: pool_twap,
+          treasuryStats: treasury_stats,
 
           wsolMint: wrapped_sol_mint,
-          usdcMint: devusdc_mint,
 
           systemProgram: SystemProgram.programId,
           tokenProgram: TOKEN_PROGRAM_ID,
           rent: SYSVAR_RENT_PUBKEY,
+          clock: SYSVAR_CLOCK_PUBKEY,
       },
+      remainingAccounts: [
+          { pubkey: ORCA_TOKEN_SWAP_ID_DEVNET, isWritable: false, isSigner: false },
+          { pubkey: orca_address, isWritable: false, isSigner: false },
+          { pubkey: orca_authority, isWritable: false, isSigner: false },
+          { pubkey: orca_token_B_deposit, isWritable: true, isSigner: false },
+          { pubkey: orca_token_A_deposit, isWritable: true, isSigner: false },
+          { pubkey: orca_pool_token_mint, isWritable: true, isSigner: false },
+          { pubkey: orca_fee_account, isWritable: true, isSigner: false },
+      ],
       signers: [],
   });
   transaction.add(ix);
@@ -347,6 +587,14 @@ async function main() {
         ],
         program.programId);
       console.log("\ttemporary_deposit_wsol", temporary_deposit_wsol.toBase58());
+
+      deposit_wsol = await Token.getAssociatedTokenAddress(
+        ASSOCIATED_TOKEN_PROGRAM_ID,
+        TOKEN_PROGRAM_ID,
+        wsol_token,
+        deposit_sol,
+        true); // true if authority is PDA
+      console.log("\tdeposit_wsol", deposit_wsol.toBase58());
       
       [price_info,] = await PublicKey.findProgramAddress(
         [
@@ -357,6 +605,66 @@ async function main() {
         ],
         program.programId);
       console.log("\tprice_info", price_info.toBase58());
+
+      [config,] = await PublicKey.findProgramAddress(
+        [
+          Uint8Array.from(Buffer.from("kurayashiki")),
+          Uint8Array.from(Buffer.from("nano_swap")),
+          Uint8Array.from(Buffer.from("config")),
+          wallet.publicKey.toBytes(),
+        ],
+        program.programId);
+      console.log("\tconfig", config.toBase58());
+
+      [oracle_sources,] = await PublicKey.findProgramAddress(
+        [
+          Uint8Array.from(Buffer.from("kurayashiki")),
+          Uint8Array.from(Buffer.from("nano_swap")),
+          Uint8Array.from(Buffer.from("oracle_sources")),
+          wallet.publicKey.toBytes(),
+        ],
+        program.programId);
+      console.log("\toracle_sources", oracle_sources.toBase58());
+
+      [pool_twap,] = await PublicKey.findProgramAddress(
+        [
+          Uint8Array.from(Buffer.from("kurayashiki")),
+          Uint8Array.from(Buffer.from("nano_swap")),
+          Uint8Array.from(Buffer.from("pool_twap")),
+          wallet.publicKey.toBytes(),
+        ],
+        program.programId);
+      console.log("\tpool_twap", pool_twap.toBase58());
+
+      [price_history,] = await PublicKey.findProgramAddress(
+        [
+          Uint8Array.from(Buffer.from("kurayashiki")),
+          Uint8Array.from(Buffer.from("nano_swap")),
+          Uint8Array.from(Buffer.from("price_history")),
+          wallet.publicKey.toBytes(),
+        ],
+        program.programId);
+      console.log("\tprice_history", price_history.toBase58());
+
+      [treasury_stats,] = await PublicKey.findProgramAddress(
+        [
+          Uint8Array.from(Buffer.from("kurayashiki")),
+          Uint8Array.from(Buffer.from("nano_swap")),
+          Uint8Array.from(Buffer.from("treasury_stats")),
+          wallet.publicKey.toBytes(),
+        ],
+        program.programId);
+      console.log("\ttreasury_stats", treasury_stats.toBase58());
+
+      [pool_cursor,] = await PublicKey.findProgramAddress(
+        [
+          Uint8Array.from(Buffer.from("kurayashiki")),
+          Uint8Array.from(Buffer.from("nano_swap")),
+          Uint8Array.from(Buffer.from("pool_cursor")),
+          wallet.publicKey.toBytes(),
+        ],
+        program.programId);
+      console.log("\tpool_cursor", pool_cursor.toBase58());
     
     wallet_usdc = await Token.getAssociatedTokenAddress(
         ASSOCIATED_TOKEN_PROGRAM_ID,
@@ -369,6 +677,13 @@ async function main() {
     //await initialize();
     // https://solscan.io/tx/z2QZhKj1dcArQ9k4Q11hDQQFwN9y6C1PC2xSuubWb3hqNH3KjuVmDZ9vNXCj9YUNJzfHRBjN6Vpwpcjcnpcy2yk?cluster=devnet
 
+    //await initialize_config();
+    //await initialize_oracle_sources();
+    //await initialize_price_history();
+    //await initialize_treasury_stats();
+    //await initialize_pool_cursor(8);
+    //await initialize_pool_target(0, 200_000_000, 150_000_000);
+
     //await create_pool(0);
     //await create_pool(1);
     //await create_pool(2);
@@ -398,6 +713,8 @@ async function main() {
     await neutralize(5);
     await neutralize(6);
     await neutralize(7);
+    // round-robin with pool_cursor
+    //await neutralize(await get_next_pool_index());
 
     await collect_from_pool(0, 1, 2, 3);
     // https://solscan.io/tx/3jHAy9FVJXX7SvqaXVk93xM9uyeBJVErp42fgDX5oyzeMtLngKyth7vfA3nUAK68h2r8mUwVfKFmoYxjNaXyt9gT?cluster=devnet
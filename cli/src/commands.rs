@@ -0,0 +1,366 @@
+// Subcommands of kurayashiki-cli. Each command returns a report printed by output.
+use std::error::Error;
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_spl::associated_token::get_associated_token_address;
+use kurayashiki_client::rpc::{Keypair, RpcClient, Signer};
+use kurayashiki_client::{
+    admin, crank, decode_oracle_sources, decode_pool_cursor, decode_pool_stats, decode_pool_target, decode_price_info, decode_treasury_stats,
+    find_address, find_pool_address, neutralize_instruction, quote_neutralize, PoolCursor, PriceInfo,
+};
+use kurayashiki_client::selection::{select_pool, PoolSelection, PoolState};
+use serde_json::{json, Map, Value};
+
+pub type CommandResult = std::result::Result<Value, Box<dyn Error>>;
+
+// network fee of a transaction with a single signature
+const LAMPORTS_PER_SIGNATURE: u64 = 5_000;
+// pools listed by status if the pool cursor is not initialized
+const DEFAULT_NUM_POOLS: u32 = 8;
+
+pub struct Context {
+    pub rpc: RpcClient,
+    pub keypair: Keypair,
+    pub creator: Pubkey,
+    pub usdc_mint: Option<Pubkey>,
+    pub accounts: Vec<AccountMeta>,     // remaining accounts of update-price (twap) and convert (swap)
+    pub max_price: Option<u64>,
+    pub minimum_output_amount: u64,
+    pub pools: Option<Vec<u32>>,
+}
+
+impl Context {
+    // --usdc-mint, or the mint recorded in TreasuryStats
+    fn usdc_mint(&self) -> std::result::Result<Pubkey, Box<dyn Error>> {
+        if let Some(usdc_mint) = self.usdc_mint {
+            return Ok(usdc_mint);
+        }
+        match self.rpc.get_account_data(&find_address("treasury_stats", &self.creator))? {
+            Some(data) => Ok(decode_treasury_stats(&data)?.usdc_mint),
+            None => Err("--usdc-mint is required (treasury_stats is not initialized)".into()),
+        }
+    }
+
+    fn require_creator_signer(&self) -> std::result::Result<(), Box<dyn Error>> {
+        if self.creator != self.keypair.pubkey() {
+            return Err(format!("the keypair {} is not the creator {}", self.keypair.pubkey(), self.creator).into());
+        }
+        Ok(())
+    }
+
+    fn send(&self, instruction: Instruction) -> std::result::Result<String, Box<dyn Error>> {
+        Ok(self.rpc.send_and_confirm(&[instruction], &self.keypair, &[])?)
+    }
+
+    fn exists(&self, address: &Pubkey) -> std::result::Result<bool, Box<dyn Error>> {
+        Ok(self.rpc.get_account_data(address)?.is_some())
+    }
+
+    fn pool_cursor(&self) -> std::result::Result<Option<PoolCursor>, Box<dyn Error>> {
+        Ok(self.rpc.get_account_data(&find_address("pool_cursor", &self.creator))?.map(|data| decode_pool_cursor(&data)).transpose()?)
+    }
+
+    // --pools, or all pools of the pool cursor
+    fn pool_indexes(&self, pool_cursor: Option<&PoolCursor>) -> Vec<u32> {
+        match (&self.pools, pool_cursor) {
+            (Some(pools), _) => pools.clone(),
+            (None, Some(cursor)) => (0..cursor.num_pools).collect(),
+            (None, None) => (0..DEFAULT_NUM_POOLS).collect(),
+        }
+    }
+}
+
+// initializes the accounts of a deployment (accounts that exist are skipped, a price_info of the
+// previous layout is migrated).
+pub fn init(ctx: &Context) -> CommandResult {
+    ctx.require_creator_signer()?;
+    let creator = &ctx.creator;
+    let usdc_mint = ctx.usdc_mint()?;
+
+    let steps = [
+        ("price_info", admin::initialize(creator, &usdc_mint)),
+        ("config", admin::initialize_config(creator)),
+        ("oracle_sources", admin::initialize_oracle_sources(creator)),
+        ("price_history", admin::initialize_price_history(creator)),
+        ("treasury_stats", admin::initialize_treasury_stats(creator, &usdc_mint)),
+    ];
+    let mut accounts = Map::new();
+    for (name, instruction) in steps {
+        let address = find_address(name, creator);
+        let result = match ctx.rpc.get_account_data(&address)? {
+            // price_info of the layout before the manual price
+            Some(data) if name == "price_info" && data.len() < kurayashiki::PRICE_INFO_SPACE => ctx.send(admin::migrate_price_info(creator))?,
+            Some(_) => "exists".to_string(),
+            None => ctx.send(instruction)?,
+        };
+        accounts.insert(name.to_string(), json!({ "address": address.to_string(), "tx": result }));
+    }
+    Ok(json!({ "creator": creator.to_string(), "usdc_mint": usdc_mint.to_string(), "accounts": accounts }))
+}
+
+pub fn create_pool(ctx: &Context, indexes: &[u32]) -> CommandResult {
+    ctx.require_creator_signer()?;
+    let usdc_mint = ctx.usdc_mint()?;
+
+    let mut pools = Vec::new();
+    for index in indexes {
+        let signature = ctx.send(admin::create_pool(&ctx.creator, &usdc_mint, *index))?;
+        let mut pool = pool_addresses(&ctx.creator, &usdc_mint, *index);
+        pool.insert("tx".to_string(), json!(signature));
+        pools.push(Value::Object(pool));
+    }
+    Ok(json!({ "pools": pools }))
+}
+
+// initializes the PoolTarget of a pool, or updates it if it exists.
+pub fn set_pool_target(ctx: &Context, index: u32, target_lamports: u64, low_water_lamports: u64) -> CommandResult {
+    ctx.require_creator_signer()?;
+    let pool_target = find_pool_address("pool_target", index, &ctx.creator);
+    let instruction = if ctx.exists(&pool_target)? {
+        admin::set_pool_target(&ctx.creator, index, target_lamports, low_water_lamports)
+    } else {
+        admin::initialize_pool_target(&ctx.creator, index, target_lamports, low_water_lamports)
+    };
+    Ok(json!({
+        "index": index,
+        "pool_target": pool_target.to_string(),
+        "target_lamports": target_lamports,
+        "low_water_lamports": low_water_lamports,
+        "tx": ctx.send(instruction)?,
+    }))
+}
+
+pub fn update_price(ctx: &Context) -> CommandResult {
+    let creator = &ctx.creator;
+    let data = ctx.rpc.get_account_data(&find_address("oracle_sources", creator))?.ok_or("oracle_sources is not initialized")?;
+    let signature = ctx.send(crank::update_price_instruction(creator, &decode_oracle_sources(&data)?, ctx.accounts.clone()))?;
+
+    let data = ctx.rpc.get_account_data(&find_address("price_info", creator))?.ok_or("price_info is not initialized")?;
+    let price_info = decode_price_info(&data)?;
+    Ok(json!({
+        "tx": signature,
+        "usdc_per_sol_price": price_info.current_usdc_per_sol_price,
+        "updated": price_info.current_usdc_per_sol_price_updated,
+    }))
+}
+
+pub fn collect(ctx: &Context, indexes: &[u32]) -> CommandResult {
+    let usdc_mint = ctx.usdc_mint()?;
+    let mut signatures = Vec::new();
+    for page in crank::pages(indexes) {
+        signatures.push(json!({ "pools": page, "tx": ctx.send(crank::collect_from_pool_instruction(&ctx.creator, &usdc_mint, page))? }));
+    }
+    Ok(json!({ "collect_from_pool": signatures }))
+}
+
+pub fn distribute(ctx: &Context, indexes: &[u32]) -> CommandResult {
+    let mut signatures = Vec::new();
+    for page in crank::pages(indexes) {
+        signatures.push(json!({ "pools": page, "tx": ctx.send(crank::distribute_to_pool_instruction(&ctx.creator, page))? }));
+    }
+    Ok(json!({ "distribute_to_pool": signatures }))
+}
+
+// swaps deposit_usdc with the configured pool (--account: remaining accounts of the swap backend).
+pub fn convert(ctx: &Context) -> CommandResult {
+    let usdc_mint = ctx.usdc_mint()?;
+    let instruction = crank::convert_to_sol_instruction(&ctx.creator, &usdc_mint, vec![], ctx.minimum_output_amount, ctx.accounts.clone());
+    Ok(json!({ "tx": ctx.send(instruction)? }))
+}
+
+pub fn status(ctx: &Context) -> CommandResult {
+    let creator = &ctx.creator;
+    let rpc = &ctx.rpc;
+    let usdc_mint = ctx.usdc_mint()?;
+    let deposit_sol = find_address("deposit_sol", creator);
+    let deposit_usdc = get_associated_token_address(&deposit_sol, &usdc_mint);
+    let deposit_wsol = get_associated_token_address(&deposit_sol, &crank::WSOL_MINT);
+
+    let price_info_address = find_address("price_info", creator);
+    let price_info = match rpc.get_account_data(&price_info_address)? {
+        Some(data) => {
+            let price_info = decode_price_info(&data)?;
+            json!({
+                "address": price_info_address.to_string(),
+                "usdc_per_sol_price": price_info.current_usdc_per_sol_price,
+                "updated": price_info.current_usdc_per_sol_price_updated,
+                "old_usdc_per_sol_price": price_info.old_usdc_per_sol_price,
+                "old_usdc_per_sol_price_grace_period": price_info.old_usdc_per_sol_price_grace_period,
+                "manual_price_expiry": price_info.manual_price_expiry,
+            })
+        },
+        None => Value::Null,
+    };
+
+    let pool_cursor = ctx.pool_cursor()?;
+    let mut pools = Vec::new();
+    for index in ctx.pool_indexes(pool_cursor.as_ref()) {
+        let mut pool = pool_addresses(creator, &usdc_mint, index);
+        let pool_deposit_sol = find_pool_address("deposit_sol", index, creator);
+        let pool_deposit_usdc = get_associated_token_address(&pool_deposit_sol, &usdc_mint);
+        pool.insert("lamports".to_string(), json!(rpc.get_balance(&pool_deposit_sol)?));
+        pool.insert("usdc".to_string(), json!(rpc.get_token_amount(&pool_deposit_usdc)?));
+        let stats = match rpc.get_account_data(&find_pool_address("pool_stats", index, creator))? {
+            Some(data) => {
+                let stats = decode_pool_stats(&data)?;
+                json!({
+                    "neutralize_count": stats.neutralize_count,
+                    "lamports_paid": stats.lamports_paid,
+                    "usdc_received": stats.usdc_received,
+                    "integrator_fees": stats.integrator_fees,
+                    "last_used_slot": stats.last_used_slot,
+                    "unreported_lamports_paid": stats.unreported_lamports_paid,
+                    "unreported_usdc_received": stats.unreported_usdc_received,
+                    "unreported_integrator_fees": stats.unreported_integrator_fees,
+                })
+            },
+            None => Value::Null,
+        };
+        pool.insert("stats".to_string(), stats);
+        let target = match rpc.get_account_data(&find_pool_address("pool_target", index, creator))? {
+            Some(data) => {
+                let target = decode_pool_target(&data)?;
+                json!({ "target_lamports": target.target_lamports, "low_water_lamports": target.low_water_lamports })
+            },
+            None => Value::Null,
+        };
+        pool.insert("target".to_string(), target);
+        pools.push(Value::Object(pool));
+    }
+
+    let treasury_stats = match rpc.get_account_data(&find_address("treasury_stats", creator))? {
+        Some(data) => {
+            let stats = decode_treasury_stats(&data)?;
+            json!({
+                "lamports_reimbursed": stats.lamports_reimbursed,
+                "usdc_received": stats.usdc_received,
+                "integrator_fees": stats.integrator_fees,
+                "usdc_collected": stats.usdc_collected,
+                "usdc_swapped": stats.usdc_swapped,
+                "sol_received": stats.sol_received,
+                "lamports_distributed": stats.lamports_distributed,
+                "realized_spread": stats.realized_spread,
+            })
+        },
+        None => Value::Null,
+    };
+
+    Ok(json!({
+        "program_id": kurayashiki_client::ID.to_string(),
+        "creator": creator.to_string(),
+        "usdc_mint": usdc_mint.to_string(),
+        "price_info": price_info,
+        "deposit_sol": { "address": deposit_sol.to_string(), "lamports": rpc.get_balance(&deposit_sol)? },
+        "deposit_usdc": { "address": deposit_usdc.to_string(), "amount": rpc.get_token_amount(&deposit_usdc)? },
+        "deposit_wsol": { "address": deposit_wsol.to_string(), "amount": rpc.get_token_amount(&deposit_wsol)? },
+        "pool_cursor": pool_cursor.map(|cursor| json!({ "num_pools": cursor.num_pools, "next_index": cursor.next_index })),
+        "pools": pools,
+        "treasury_stats": treasury_stats,
+    }))
+}
+
+// simulates neutralize by the keypair (the network fee of the simulated transaction is reimbursed).
+pub fn neutralize_dry_run(ctx: &Context, index: Option<u32>) -> CommandResult {
+    let creator = &ctx.creator;
+    let usdc_mint = ctx.usdc_mint()?;
+    let user = ctx.keypair.pubkey();
+
+    let data = ctx.rpc.get_account_data(&find_address("price_info", creator))?.ok_or("price_info is not initialized")?;
+    let price_info = decode_price_info(&data)?;
+    let clock = ctx.rpc.get_clock()?;
+    let quote = dry_run_quote(&price_info, clock.unix_timestamp);
+
+    let index = match index {
+        Some(index) => index,
+        None => select_neutralize_pool(ctx, &user, clock.slot)?,
+    };
+    let max_price = ctx.max_price.unwrap_or(price_info.current_usdc_per_sol_price);
+    let pre_tx_lamports = ctx.rpc.get_balance(&user)?;
+    let simulation = ctx.rpc.simulate(&[neutralize_instruction(creator, &usdc_mint, &user, index, max_price, pre_tx_lamports)], &ctx.keypair, &[])?;
+
+    Ok(json!({
+        "user": user.to_string(),
+        "index": index,
+        "max_price": max_price,
+        "pre_tx_lamports": pre_tx_lamports,
+        "quote": quote,
+        "simulation": {
+            "result": if simulation.err.is_none() { "ok".to_string() } else { "failed".to_string() },
+            "err": simulation.err,
+            "logs": simulation.logs,
+        },
+    }))
+}
+
+// the pool a client selects: round-robin from PoolCursor::index_for of the user among the pools that can pay.
+fn select_neutralize_pool(ctx: &Context, user: &Pubkey, slot: u64) -> std::result::Result<u32, Box<dyn Error>> {
+    let creator = &ctx.creator;
+    let pool_cursor = ctx.pool_cursor()?;
+    let mut pools = Vec::new();
+    for index in ctx.pool_indexes(pool_cursor.as_ref()) {
+        let stats = ctx.rpc.get_account_data(&find_pool_address("pool_stats", index, creator))?.map(|data| decode_pool_stats(&data)).transpose()?;
+        pools.push(PoolState { index, lamports: ctx.rpc.get_balance(&find_pool_address("deposit_sol", index, creator))?, stats });
+    }
+
+    let selection = match &pool_cursor {
+        Some(cursor) => PoolSelection::round_robin(cursor, user, slot),
+        None => PoolSelection::RoundRobin { next_index: 0 },
+    };
+    select_pool(&pools, LAMPORTS_PER_SIGNATURE, selection).ok_or_else(|| "no pool can pay the network fee".into())
+}
+
+// the charge of neutralize for the network fee of a transaction (None if the price is not valid at now).
+fn dry_run_quote(price_info: &PriceInfo, now: i64) -> Option<Value> {
+    quote_neutralize(price_info, LAMPORTS_PER_SIGNATURE, now).ok().map(|quote| json!({
+        "lamports": LAMPORTS_PER_SIGNATURE,
+        "required_usdc": quote.required_usdc,
+        "usdc_per_sol_price": quote.usdc_per_sol_price,
+        "expiry": quote.expiry,
+    }))
+}
+
+fn pool_addresses(creator: &Pubkey, usdc_mint: &Pubkey, index: u32) -> Map<String, Value> {
+    let pool_deposit_sol = find_pool_address("deposit_sol", index, creator);
+    let mut pool = Map::new();
+    pool.insert("index".to_string(), json!(index));
+    pool.insert("pool_deposit_sol".to_string(), json!(pool_deposit_sol.to_string()));
+    pool.insert("pool_deposit_usdc".to_string(), json!(get_associated_token_address(&pool_deposit_sol, usdc_mint).to_string()));
+    pool.insert("pool_stats".to_string(), json!(find_pool_address("pool_stats", index, creator).to_string()));
+    pool
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 200 USDC per SOL (doubled) updated at 1_000
+    fn price_info() -> PriceInfo {
+        PriceInfo {
+            current_usdc_per_sol_price: 200_000_000,
+            current_usdc_per_sol_price_updated: 1_000,
+            old_usdc_per_sol_price: 0,
+            old_usdc_per_sol_price_grace_period: 0,
+            last_oracle_usdc_per_sol_price: 200_000_000,
+            manual_price_expiry: 0,
+        }
+    }
+
+    #[test]
+    fn dry_run_quotes_as_the_client() {
+        let quote = quote_neutralize(&price_info(), LAMPORTS_PER_SIGNATURE, 2_000).unwrap();
+        assert_eq!(dry_run_quote(&price_info(), 2_000), Some(json!({
+            "lamports": 5_000,
+            "required_usdc": quote.required_usdc,
+            "usdc_per_sol_price": quote.usdc_per_sol_price,
+            "expiry": quote.expiry,
+        })));
+        assert_eq!(quote.required_usdc, 1_000);
+    }
+
+    #[test]
+    fn dry_run_quotes_nothing_for_an_expired_price() {
+        let expiry = quote_neutralize(&price_info(), LAMPORTS_PER_SIGNATURE, 1_000).unwrap().expiry;
+        assert_eq!(dry_run_quote(&price_info(), expiry + 1), None);
+    }
+}
@@ -0,0 +1,232 @@
+// kurayashiki-cli: administers a deployment from the command line.
+//
+// usage: kurayashiki-cli [options] <command> [args]
+//   init                       initialize price_info, deposit_sol/usdc, config, oracle_sources, price_history, treasury_stats
+//                              (migrates a price_info of the previous layout)
+//   create-pool <index>...     create pools (pool_deposit_sol, pool_deposit_usdc, pool_stats)
+//   set-pool-target <index> <target_lamports> <low_water_lamports>
+//                              SOL balance maintained by distribute_to_pool for a pool
+//   update-price               update PriceInfo from the oracle sources (--account: twap accounts)
+//   collect <index>...         collect_from_pool
+//   distribute <index>...      distribute_to_pool
+//   convert                    convert_to_sol with the configured pool (--account: swap accounts)
+//   status                     print the PDAs, balances, price and stats
+//   neutralize --dry-run [index]
+//                              simulate neutralize with the keypair as the user
+//                              (default index: the pool a client selects from the pool cursor)
+//
+// options:
+//   --url <url>                JSON-RPC endpoint (default http://127.0.0.1:8899)
+//   --keypair <path>           signer and fee payer (default ~/.config/solana/id.json)
+//   --creator <pubkey>         creator of the deployment (default the keypair)
+//   --usdc-mint <pubkey>       default the mint recorded in treasury_stats
+//   --output human|json
+//   --pools <index>,...        pools listed by status and selected by neutralize (default all pools of the pool cursor)
+//   --max-price <price>        max_price of neutralize (default the current price)
+//   --minimum-output <amount>  minimum_output_amount of convert
+//   --account <pubkey>[:w]     remaining account (repeatable, :w for writable)
+use std::error::Error;
+use std::str::FromStr;
+use std::{env, process};
+
+use anchor_lang::prelude::*;
+use kurayashiki_client::rpc::{self, RpcClient, Signer};
+
+mod commands;
+mod output;
+
+use commands::{CommandResult, Context};
+
+const USAGE: &str = "usage: kurayashiki-cli [--url <url>] [--keypair <path>] [--creator <pubkey>] [--usdc-mint <pubkey>] [--output human|json] \
+    <init | create-pool <index>... | set-pool-target <index> <target> <low_water> | update-price | collect <index>... | distribute <index>... | convert | status | neutralize --dry-run [index]>";
+
+struct Options {
+    url: String,
+    keypair: String,
+    creator: Option<Pubkey>,
+    usdc_mint: Option<Pubkey>,
+    json: bool,
+    pools: Option<Vec<u32>>,
+    max_price: Option<u64>,
+    minimum_output_amount: u64,
+    accounts: Vec<AccountMeta>,
+    dry_run: bool,
+    args: Vec<String>,          // command and its arguments
+}
+
+fn parse_options(args: Vec<String>) -> std::result::Result<Options, Box<dyn Error>> {
+    let mut options = Options {
+        url: "http://127.0.0.1:8899".to_string(),
+        keypair: format!("{}/.config/solana/id.json", env::var("HOME").unwrap_or_default()),
+        creator: None,
+        usdc_mint: None,
+        json: false,
+        pools: None,
+        max_price: None,
+        minimum_output_amount: 0,
+        accounts: Vec::new(),
+        dry_run: false,
+        args: Vec::new(),
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--dry-run" {
+            options.dry_run = true;
+            continue;
+        }
+        if !arg.starts_with("--") {
+            options.args.push(arg);
+            continue;
+        }
+        let value = args.next().ok_or(format!("{} requires a value", arg))?;
+        match arg.as_str() {
+            "--url" => options.url = value,
+            "--keypair" => options.keypair = value,
+            "--creator" => options.creator = Some(parse_pubkey(&value)?),
+            "--usdc-mint" => options.usdc_mint = Some(parse_pubkey(&value)?),
+            "--output" => options.json = match value.as_str() {
+                "json" => true,
+                "human" => false,
+                _ => return Err(format!("unknown output {}", value).into()),
+            },
+            "--pools" => options.pools = Some(parse_indexes(value.split(','))?),
+            "--max-price" => options.max_price = Some(value.parse()?),
+            "--minimum-output" => options.minimum_output_amount = value.parse()?,
+            "--account" => {
+                let (pubkey, writable) = match value.strip_suffix(":w") {
+                    Some(pubkey) => (pubkey, true),
+                    None => (value.as_str(), false),
+                };
+                let pubkey = parse_pubkey(pubkey)?;
+                options.accounts.push(if writable { AccountMeta::new(pubkey, false) } else { AccountMeta::new_readonly(pubkey, false) });
+            },
+            _ => return Err(format!("unknown option {}", arg).into()),
+        }
+    }
+    Ok(options)
+}
+
+fn parse_pubkey(s: &str) -> std::result::Result<Pubkey, Box<dyn Error>> {
+    Pubkey::from_str(s).map_err(|e| format!("{}: {}", s, e).into())
+}
+
+fn parse_indexes<'a>(args: impl Iterator<Item = &'a str>) -> std::result::Result<Vec<u32>, Box<dyn Error>> {
+    let indexes = args.map(|arg| arg.parse::<u32>().map_err(|e| format!("{}: {}", arg, e))).collect::<std::result::Result<Vec<u32>, String>>()?;
+    if indexes.is_empty() {
+        return Err("pool indexes are required".into());
+    }
+    Ok(indexes)
+}
+
+fn run(options: Options) -> CommandResult {
+    let keypair = rpc::read_keypair_file(&options.keypair)?;
+    let ctx = Context {
+        rpc: RpcClient::new(&options.url)?,
+        creator: options.creator.unwrap_or_else(|| keypair.pubkey()),
+        keypair,
+        usdc_mint: options.usdc_mint,
+        accounts: options.accounts,
+        max_price: options.max_price,
+        minimum_output_amount: options.minimum_output_amount,
+        pools: options.pools,
+    };
+
+    let (command, args) = options.args.split_first().ok_or(USAGE)?;
+    let args = args.iter().map(|arg| arg.as_str());
+    match command.as_str() {
+        "init" => commands::init(&ctx),
+        "create-pool" => commands::create_pool(&ctx, &parse_indexes(args)?),
+        "set-pool-target" => {
+            let args: Vec<&str> = args.collect();
+            match args[..] {
+                [index, target_lamports, low_water_lamports] => commands::set_pool_target(&ctx, index.parse()?, target_lamports.parse()?, low_water_lamports.parse()?),
+                _ => Err("usage: set-pool-target <index> <target_lamports> <low_water_lamports>".into()),
+            }
+        },
+        "update-price" => commands::update_price(&ctx),
+        "collect" => commands::collect(&ctx, &parse_indexes(args)?),
+        "distribute" => commands::distribute(&ctx, &parse_indexes(args)?),
+        "convert" => commands::convert(&ctx),
+        "status" => commands::status(&ctx),
+        "neutralize" => {
+            if !options.dry_run {
+                return Err("neutralize is only supported with --dry-run (it is sent by the user's wallet)".into());
+            }
+            let index = args.map(|arg| arg.parse::<u32>()).next().transpose()?;
+            commands::neutralize_dry_run(&ctx, index)
+        },
+        _ => Err(format!("unknown command {}\n{}", command, USAGE).into()),
+    }
+}
+
+fn main() {
+    let options = match parse_options(env::args().skip(1).collect()) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        },
+    };
+    let json = options.json;
+
+    match run(options) {
+        Ok(report) => output::print(&report, json),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> std::result::Result<Options, Box<dyn Error>> {
+        parse_options(args.iter().map(|arg| arg.to_string()).collect())
+    }
+
+    #[test]
+    fn parses_options_and_the_command() {
+        let creator = Pubkey::new_unique();
+        let account = Pubkey::new_unique();
+        let options = parse(&[
+            "--url", "http://localhost:8899", "--creator", &creator.to_string(), "--output", "json", "--pools", "0,2",
+            "--max-price", "210000000", "--account", &format!("{}:w", account), "--account", &account.to_string(), "collect", "0", "1",
+        ]).unwrap();
+        assert_eq!(options.url, "http://localhost:8899");
+        assert_eq!(options.creator, Some(creator));
+        assert!(options.json);
+        assert_eq!(options.pools, Some(vec![0, 2]));
+        assert_eq!(options.max_price, Some(210_000_000));
+        assert_eq!(options.accounts, vec![AccountMeta::new(account, false), AccountMeta::new_readonly(account, false)]);
+        assert!(!options.dry_run);
+        assert_eq!(options.args, vec!["collect", "0", "1"]);
+    }
+
+    #[test]
+    fn parses_the_dry_run_flag_without_a_value() {
+        let options = parse(&["neutralize", "--dry-run", "3"]).unwrap();
+        assert!(options.dry_run);
+        assert!(!options.json);
+        assert_eq!(options.creator, None);
+        assert_eq!(options.args, vec!["neutralize", "3"]);
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        assert!(parse(&["status", "--url"]).is_err());
+        assert!(parse(&["--unknown", "value", "status"]).is_err());
+        assert!(parse(&["--output", "xml", "status"]).is_err());
+        assert!(parse(&["--creator", "not-a-pubkey", "status"]).is_err());
+        assert!(parse(&["--pools", "0,x", "status"]).is_err());
+    }
+
+    #[test]
+    fn parses_indexes() {
+        assert_eq!(parse_indexes("0,3".split(',')).unwrap(), vec![0, 3]);
+        assert!(parse_indexes("-1".split(',')).is_err());
+        assert!(parse_indexes(std::iter::empty()).is_err());
+    }
+}
@@ -0,0 +1,47 @@
+// Output of a command: JSON as is, or human-readable "key: value" lines.
+use serde_json::Value;
+
+pub fn print(report: &Value, json: bool) {
+    if json {
+        println!("{}", serde_json::to_string_pretty(report).unwrap_or_default());
+    } else {
+        print_human(report, 0);
+    }
+}
+
+fn print_human(value: &Value, depth: usize) {
+    let indent = "  ".repeat(depth);
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                match value {
+                    Value::Object(_) | Value::Array(_) => {
+                        println!("{}{}:", indent, key);
+                        print_human(value, depth + 1);
+                    },
+                    _ => println!("{}{}: {}", indent, key, scalar(value)),
+                }
+            }
+        },
+        Value::Array(items) => {
+            for item in items {
+                match item {
+                    Value::Object(_) | Value::Array(_) => {
+                        println!("{}-", indent);
+                        print_human(item, depth + 1);
+                    },
+                    _ => println!("{}- {}", indent, scalar(item)),
+                }
+            }
+        },
+        _ => println!("{}{}", indent, scalar(value)),
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "-".to_string(),
+        _ => value.to_string(),
+    }
+}
@@ -0,0 +1,108 @@
+// Instructions signed by the creator (kurayashiki-cli, and the deployment of the test of the keeper).
+use anchor_lang::prelude::*;
+use anchor_lang::InstructionData;
+use anchor_lang::solana_program::{instruction::Instruction, system_program, sysvar};
+use anchor_spl::{associated_token, token};
+use crate::{ID, find_address, find_pool_address};
+
+fn instruction(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+pub fn initialize(creator: &Pubkey, usdc_mint: &Pubkey) -> Instruction {
+    let deposit_sol = find_address("deposit_sol", creator);
+    instruction(kurayashiki::accounts::Initialize {
+        creator: *creator,
+        price_info: find_address("price_info", creator),
+        deposit_sol,
+        deposit_usdc: associated_token::get_associated_token_address(&deposit_sol, usdc_mint),
+        usdc_mint: *usdc_mint,
+        system_program: system_program::ID,
+        token_program: token::ID,
+        associated_token_program: associated_token::ID,
+        rent: sysvar::rent::ID,
+    }, kurayashiki::instruction::Initialize {})
+}
+
+pub fn migrate_price_info(creator: &Pubkey) -> Instruction {
+    instruction(kurayashiki::accounts::MigratePriceInfo {
+        creator: *creator,
+        price_info: find_address("price_info", creator),
+        system_program: system_program::ID,
+    }, kurayashiki::instruction::MigratePriceInfo {})
+}
+
+pub fn initialize_config(creator: &Pubkey) -> Instruction {
+    instruction(kurayashiki::accounts::InitializeConfig {
+        creator: *creator,
+        config: find_address("config", creator),
+        system_program: system_program::ID,
+    }, kurayashiki::instruction::InitializeConfig {})
+}
+
+pub fn initialize_oracle_sources(creator: &Pubkey) -> Instruction {
+    instruction(kurayashiki::accounts::InitializeOracleSources {
+        creator: *creator,
+        oracle_sources: find_address("oracle_sources", creator),
+        system_program: system_program::ID,
+    }, kurayashiki::instruction::InitializeOracleSources {})
+}
+
+pub fn initialize_price_history(creator: &Pubkey) -> Instruction {
+    instruction(kurayashiki::accounts::InitializePriceHistory {
+        creator: *creator,
+        price_history: find_address("price_history", creator),
+        system_program: system_program::ID,
+    }, kurayashiki::instruction::InitializePriceHistory {})
+}
+
+pub fn initialize_treasury_stats(creator: &Pubkey, usdc_mint: &Pubkey) -> Instruction {
+    instruction(kurayashiki::accounts::InitializeTreasuryStats {
+        creator: *creator,
+        treasury_stats: find_address("treasury_stats", creator),
+        usdc_mint: *usdc_mint,
+        system_program: system_program::ID,
+    }, kurayashiki::instruction::InitializeTreasuryStats {})
+}
+
+pub fn create_pool(creator: &Pubkey, usdc_mint: &Pubkey, index: u32) -> Instruction {
+    let pool_deposit_sol = find_pool_address("deposit_sol", index, creator);
+    instruction(kurayashiki::accounts::CreatePool {
+        creator: *creator,
+        pool_deposit_sol,
+        pool_deposit_usdc: associated_token::get_associated_token_address(&pool_deposit_sol, usdc_mint),
+        pool_stats: find_pool_address("pool_stats", index, creator),
+        usdc_mint: *usdc_mint,
+        system_program: system_program::ID,
+        token_program: token::ID,
+        associated_token_program: associated_token::ID,
+        rent: sysvar::rent::ID,
+    }, kurayashiki::instruction::CreatePool { index })
+}
+
+pub fn initialize_pool_cursor(creator: &Pubkey, num_pools: u32) -> Instruction {
+    instruction(kurayashiki::accounts::InitializePoolCursor {
+        creator: *creator,
+        pool_cursor: find_address("pool_cursor", creator),
+        system_program: system_program::ID,
+    }, kurayashiki::instruction::InitializePoolCursor { num_pools })
+}
+
+pub fn initialize_pool_target(creator: &Pubkey, index: u32, target_lamports: u64, low_water_lamports: u64) -> Instruction {
+    instruction(kurayashiki::accounts::InitializePoolTarget {
+        creator: *creator,
+        pool_target: find_pool_address("pool_target", index, creator),
+        system_program: system_program::ID,
+    }, kurayashiki::instruction::InitializePoolTarget { index, target_lamports, low_water_lamports })
+}
+
+pub fn set_pool_target(creator: &Pubkey, index: u32, target_lamports: u64, low_water_lamports: u64) -> Instruction {
+    instruction(kurayashiki::accounts::UpdatePoolTarget {
+        creator: *creator,
+        pool_target: find_pool_address("pool_target", index, creator),
+    }, kurayashiki::instruction::SetPoolTarget { _index: index, target_lamports, low_water_lamports })
+}
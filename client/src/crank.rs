@@ -0,0 +1,181 @@
+// Instructions of the crank: update_price, a rebalance (collect_from_pool -> convert_to_sol -> distribute_to_pool)
+// and advance_pool_cursor.
+// collect_from_pool and distribute_to_pool take 4 pools each, so pools are paged by POOLS_PER_PAGE.
+// A rebalance is sent in one transaction if it fits in a packet (a page of collect_from_pool and distribute_to_pool
+// does, but not together with the swap accounts of convert_to_sol), otherwise each instruction in order.
+use anchor_lang::prelude::*;
+use anchor_lang::InstructionData;
+use anchor_lang::solana_program::{instruction::Instruction, system_program, sysvar};
+use anchor_spl::{associated_token::get_associated_token_address, token};
+
+use kurayashiki::OracleSources;
+use kurayashiki::swap::SwapHop;
+use crate::{ID, find_address, find_pool_address};
+
+pub const POOLS_PER_PAGE: usize = 4;
+pub const WSOL_MINT: Pubkey = spl_token::native_mint::ID;
+
+// pages of pool indexes (the last page is padded by repeating its last index, which the program skips).
+pub fn pages(indexes: &[u32]) -> Vec<[u32; POOLS_PER_PAGE]> {
+    indexes.chunks(POOLS_PER_PAGE).map(|chunk| {
+        let mut page = [chunk[chunk.len() - 1]; POOLS_PER_PAGE];
+        page[..chunk.len()].copy_from_slice(chunk);
+        page
+    }).collect()
+}
+
+// remaining accounts: price accounts of oracle_sources (same order) and twap_accounts
+// ([swap_program, pool accounts of the swap backend], required if pool_twap is initialized).
+pub fn update_price_instruction(creator: &Pubkey, oracle_sources: &OracleSources, twap_accounts: Vec<AccountMeta>) -> Instruction {
+    let accounts = kurayashiki::accounts::UpdatePrice {
+        creator: *creator,
+        price_info: find_address("price_info", creator),
+        oracle_sources: find_address("oracle_sources", creator),
+        pool_twap: find_address("pool_twap", creator),
+        price_history: find_address("price_history", creator),
+        clock: sysvar::clock::ID,
+    };
+    let mut account_metas = accounts.to_account_metas(None);
+    account_metas.extend(oracle_sources.sources.iter().map(|source| AccountMeta::new_readonly(source.address, false)));
+    account_metas.extend(twap_accounts);
+    Instruction {
+        program_id: ID,
+        accounts: account_metas,
+        data: kurayashiki::instruction::UpdatePrice {}.data(),
+    }
+}
+
+pub fn collect_from_pool_instruction(creator: &Pubkey, usdc_mint: &Pubkey, page: [u32; POOLS_PER_PAGE]) -> Instruction {
+    let deposit_sol = find_address("deposit_sol", creator);
+    let pool_deposit_sols: Vec<Pubkey> = page.iter().map(|index| find_pool_address("deposit_sol", *index, creator)).collect();
+    let pool_deposit_usdcs: Vec<Pubkey> = pool_deposit_sols.iter().map(|pool| get_associated_token_address(pool, usdc_mint)).collect();
+    let pool_stats: Vec<Pubkey> = page.iter().map(|index| find_pool_address("pool_stats", *index, creator)).collect();
+    let accounts = kurayashiki::accounts::CollectFromPool {
+        creator: *creator,
+        deposit_sol,
+        deposit_usdc: get_associated_token_address(&deposit_sol, usdc_mint),
+        pool_deposit_sol1: pool_deposit_sols[0],
+        pool_deposit_usdc1: pool_deposit_usdcs[0],
+        pool_deposit_sol2: pool_deposit_sols[1],
+        pool_deposit_usdc2: pool_deposit_usdcs[1],
+        pool_deposit_sol3: pool_deposit_sols[2],
+        pool_deposit_usdc3: pool_deposit_usdcs[2],
+        pool_deposit_sol4: pool_deposit_sols[3],
+        pool_deposit_usdc4: pool_deposit_usdcs[3],
+        pool_stats1: pool_stats[0],
+        pool_stats2: pool_stats[1],
+        pool_stats3: pool_stats[2],
+        pool_stats4: pool_stats[3],
+        treasury_stats: find_address("treasury_stats", creator),
+        usdc_mint: *usdc_mint,
+        token_program: token::ID,
+    };
+    Instruction {
+        program_id: ID,
+        accounts: accounts.to_account_metas(None),
+        data: kurayashiki::instruction::CollectFromPool { index1: page[0], index2: page[1], index3: page[2], index4: page[3] }.data(),
+    }
+}
+
+pub fn distribute_to_pool_instruction(creator: &Pubkey, page: [u32; POOLS_PER_PAGE]) -> Instruction {
+    let deposit_sol = find_address("deposit_sol", creator);
+    let pool_deposit_sols: Vec<Pubkey> = page.iter().map(|index| find_pool_address("deposit_sol", *index, creator)).collect();
+    let pool_targets: Vec<Pubkey> = page.iter().map(|index| find_pool_address("pool_target", *index, creator)).collect();
+    let accounts = kurayashiki::accounts::DistributeToPool {
+        creator: *creator,
+        deposit_sol,
+        pool_deposit_sol1: pool_deposit_sols[0],
+        pool_deposit_sol2: pool_deposit_sols[1],
+        pool_deposit_sol3: pool_deposit_sols[2],
+        pool_deposit_sol4: pool_deposit_sols[3],
+        pool_target1: pool_targets[0],
+        pool_target2: pool_targets[1],
+        pool_target3: pool_targets[2],
+        pool_target4: pool_targets[3],
+        deposit_wsol: get_associated_token_address(&deposit_sol, &WSOL_MINT),
+        temporary_deposit_wsol: find_address("temporary_deposit_wsol", creator),
+        treasury_stats: find_address("treasury_stats", creator),
+        wsol_mint: WSOL_MINT,
+        system_program: system_program::ID,
+        token_program: token::ID,
+        rent: sysvar::rent::ID,
+    };
+    Instruction {
+        program_id: ID,
+        accounts: accounts.to_account_metas(None),
+        data: kurayashiki::instruction::DistributeToPool { index1: page[0], index2: page[1], index3: page[2], index4: page[3] }.data(),
+    }
+}
+
+pub fn advance_pool_cursor_instruction(creator: &Pubkey) -> Instruction {
+    let accounts = kurayashiki::accounts::AdvancePoolCursor {
+        creator: *creator,
+        pool_cursor: find_address("pool_cursor", creator),
+    };
+    Instruction {
+        program_id: ID,
+        accounts: accounts.to_account_metas(None),
+        data: kurayashiki::instruction::AdvancePoolCursor {}.data(),
+    }
+}
+
+// swap_accounts: remaining accounts of each hop (see convert_to_sol), an empty route swaps with the configured pool.
+pub fn convert_to_sol_instruction(
+    creator: &Pubkey,
+    deposit_token_mint: &Pubkey,
+    route: Vec<SwapHop>,
+    minimum_output_amount: u64,
+    swap_accounts: Vec<AccountMeta>,
+) -> Instruction {
+    let deposit_sol = find_address("deposit_sol", creator);
+    let accounts = kurayashiki::accounts::ConvertToSol {
+        creator: *creator,
+        config: find_address("config", creator),
+        deposit_sol,
+        deposit_token: get_associated_token_address(&deposit_sol, deposit_token_mint),
+        temporary_deposit_wsol: find_address("temporary_deposit_wsol", creator),
+        deposit_wsol: get_associated_token_address(&deposit_sol, &WSOL_MINT),
+        pool_twap: find_address("pool_twap", creator),
+        treasury_stats: find_address("treasury_stats", creator),
+        wsol_mint: WSOL_MINT,
+        system_program: system_program::ID,
+        token_program: token::ID,
+        rent: sysvar::rent::ID,
+        clock: sysvar::clock::ID,
+    };
+    let mut account_metas = accounts.to_account_metas(None);
+    account_metas.extend(swap_accounts);
+    Instruction {
+        program_id: ID,
+        accounts: account_metas,
+        data: kurayashiki::instruction::ConvertToSol { route, minimum_output_amount }.data(),
+    }
+}
+
+// all instructions of a rebalance in order: collect_from_pool of collect_indexes, convert_to_sol (skipped if None,
+// e.g. deposit_usdc is empty) and distribute_to_pool of distribute_indexes.
+pub fn rebalance_instructions(
+    creator: &Pubkey,
+    usdc_mint: &Pubkey,
+    collect_indexes: &[u32],
+    convert_to_sol: Option<Instruction>,
+    distribute_indexes: &[u32],
+) -> Vec<Instruction> {
+    let mut instructions: Vec<Instruction> = pages(collect_indexes).into_iter().map(|page| collect_from_pool_instruction(creator, usdc_mint, page)).collect();
+    instructions.extend(convert_to_sol);
+    instructions.extend(pages(distribute_indexes).into_iter().map(|page| distribute_to_pool_instruction(creator, page)));
+    instructions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_pad_the_last_page_with_its_last_index() {
+        assert_eq!(pages(&[0, 1, 2, 3]), vec![[0, 1, 2, 3]]);
+        assert_eq!(pages(&[0, 1, 2, 3, 4, 5]), vec![[0, 1, 2, 3], [4, 5, 5, 5]]);
+        assert_eq!(pages(&[7]), vec![[7, 7, 7, 7]]);
+        assert!(pages(&[]).is_empty());
+    }
+}
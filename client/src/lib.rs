@@ -0,0 +1,161 @@
+// Client library of the kurayashiki program.
+// PDAs, instructions and decoding of accounts / return data, without RPC dependencies
+// (the RPC client of solana-client is in the "rpc" feature).
+use anchor_lang::prelude::*;
+use anchor_lang::{AccountDeserialize, InstructionData};
+use anchor_lang::solana_program::{instruction::Instruction, system_program, sysvar};
+use anchor_spl::{associated_token::get_associated_token_address, token};
+
+pub use kurayashiki::{ID, PriceInfo, NeutralizeQuote, TreasuryStats, PoolStats, PoolCursor, PoolTarget, OracleSources};
+
+pub mod admin;
+pub mod crank;
+pub mod report;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+pub mod selection;
+
+// PDA of kurayashiki: ["kurayashiki", "nano_swap", name, creator]
+pub fn find_address(name: &str, creator: &Pubkey) -> Pubkey {
+    let seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), name.as_bytes(), creator.as_ref()];
+    Pubkey::find_program_address(&seeds, &ID).0
+}
+
+// PDA of a pool: ["kurayashiki", "nano_swap", name, index, creator]
+pub fn find_pool_address(name: &str, index: u32, creator: &Pubkey) -> Pubkey {
+    let index_bytes = index.to_le_bytes();
+    let seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), name.as_bytes(), index_bytes.as_ref(), creator.as_ref()];
+    Pubkey::find_program_address(&seeds, &ID).0
+}
+
+// PDA of an integrator: ["kurayashiki", "nano_swap", "integrator_fee", integrator, creator]
+pub fn find_integrator_fee_address(integrator: &Pubkey, creator: &Pubkey) -> Pubkey {
+    let seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"integrator_fee".as_ref(), integrator.as_ref(), creator.as_ref()];
+    Pubkey::find_program_address(&seeds, &ID).0
+}
+
+pub fn decode_pool_stats(data: &[u8]) -> Result<PoolStats, ProgramError> {
+    PoolStats::try_deserialize(&mut &data[..])
+}
+
+pub fn decode_pool_cursor(data: &[u8]) -> Result<PoolCursor, ProgramError> {
+    PoolCursor::try_deserialize(&mut &data[..])
+}
+
+pub fn decode_pool_target(data: &[u8]) -> Result<PoolTarget, ProgramError> {
+    PoolTarget::try_deserialize(&mut &data[..])
+}
+
+pub fn decode_oracle_sources(data: &[u8]) -> Result<OracleSources, ProgramError> {
+    OracleSources::try_deserialize(&mut &data[..])
+}
+
+pub fn decode_price_info(data: &[u8]) -> Result<PriceInfo, ProgramError> {
+    PriceInfo::try_deserialize(&mut &data[..])
+}
+
+pub fn decode_treasury_stats(data: &[u8]) -> Result<TreasuryStats, ProgramError> {
+    TreasuryStats::try_deserialize(&mut &data[..])
+}
+
+// same calculation as neutralize (fails with InvalidPrice if the price is not valid at now).
+pub fn quote_neutralize(price_info: &PriceInfo, lamports: u64, now: i64) -> Result<NeutralizeQuote, ProgramError> {
+    price_info.quote_neutralize(lamports, now)
+}
+
+// quote_neutralize instruction for simulateTransaction (no signer is required).
+pub fn quote_neutralize_instruction(creator: &Pubkey, lamports: u64) -> Instruction {
+    let accounts = kurayashiki::accounts::QuoteNeutralize {
+        creator: *creator,
+        price_info: find_address("price_info", creator),
+        clock: sysvar::clock::ID,
+    };
+    Instruction {
+        program_id: ID,
+        accounts: accounts.to_account_metas(None),
+        data: kurayashiki::instruction::QuoteNeutralize { lamports }.data(),
+    }
+}
+
+// neutralize by user with user_usdc (the associated token account of usdc_mint), without an integrator.
+pub fn neutralize_instruction(creator: &Pubkey, usdc_mint: &Pubkey, user: &Pubkey, index: u32, max_price: u64, pre_tx_lamports: u64) -> Instruction {
+    let pool_deposit_sol = find_pool_address("deposit_sol", index, creator);
+    let accounts = kurayashiki::accounts::Neutralize {
+        creator: *creator,
+        price_info: find_address("price_info", creator),
+        price_history: find_address("price_history", creator),
+        pool_deposit_sol,
+        pool_deposit_usdc: get_associated_token_address(&pool_deposit_sol, usdc_mint),
+        user: *user,
+        user_usdc: get_associated_token_address(user, usdc_mint),
+        pool_stats: find_pool_address("pool_stats", index, creator),
+        usdc_mint: *usdc_mint,
+        system_program: system_program::ID,
+        token_program: token::ID,
+        clock: sysvar::clock::ID,
+    };
+    Instruction {
+        program_id: ID,
+        accounts: accounts.to_account_metas(None),
+        data: kurayashiki::instruction::Neutralize { index, max_price, pre_tx_lamports }.data(),
+    }
+}
+
+// remaining accounts of neutralize for a fallback pool, used if the pool of index is depleted
+// (appended to the accounts of neutralize_instruction, after the integrator accounts if any).
+pub fn neutralize_fallback_accounts(creator: &Pubkey, usdc_mint: &Pubkey, fallback_index: u32) -> Vec<AccountMeta> {
+    let pool_deposit_sol = find_pool_address("deposit_sol", fallback_index, creator);
+    vec![
+        AccountMeta::new(find_pool_address("pool_stats", fallback_index, creator), false),
+        AccountMeta::new(pool_deposit_sol, false),
+        AccountMeta::new(get_associated_token_address(&pool_deposit_sol, usdc_mint), false),
+    ]
+}
+
+// return data of quote_neutralize.
+pub fn decode_neutralize_quote(return_data: &[u8]) -> Result<NeutralizeQuote, ProgramError> {
+    NeutralizeQuote::try_from_slice(return_data).map_err(|_| ProgramError::InvalidAccountData)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 200 USDC per SOL (doubled) updated at 1_000
+    fn price_info(manual_price_expiry: i64) -> PriceInfo {
+        PriceInfo {
+            current_usdc_per_sol_price: 200_000_000,
+            current_usdc_per_sol_price_updated: 1_000,
+            old_usdc_per_sol_price: 0,
+            old_usdc_per_sol_price_grace_period: 0,
+            last_oracle_usdc_per_sol_price: 200_000_000,
+            manual_price_expiry,
+        }
+    }
+
+    #[test]
+    fn quote_neutralize_rounds_the_charge_up() {
+        // 5000 lamports * 200 USDC per SOL = 1000 micro USDC
+        let quote = quote_neutralize(&price_info(0), 5_000, 1_000).unwrap();
+        assert_eq!(quote.required_usdc, 1_000);
+        assert_eq!(quote.usdc_per_sol_price, 200_000_000);
+        assert_eq!(quote_neutralize(&price_info(0), 1, 1_000).unwrap().required_usdc, 1);
+    }
+
+    #[test]
+    fn quote_neutralize_expires() {
+        // MAX_ACCEPTABLE_UPDATE_INTERVAL (72 hours) after the update, or the manual price expiry if earlier
+        let expiry = 1_000 + 60 * 60 * 72;
+        assert_eq!(quote_neutralize(&price_info(0), 5_000, expiry).unwrap().expiry, expiry);
+        assert!(quote_neutralize(&price_info(0), 5_000, expiry + 1).is_err());
+        assert_eq!(quote_neutralize(&price_info(2_000), 5_000, 2_000).unwrap().expiry, 2_000);
+        assert!(quote_neutralize(&price_info(2_000), 5_000, 2_001).is_err());
+    }
+
+    #[test]
+    fn decodes_the_return_data_of_quote_neutralize() {
+        let quote = quote_neutralize(&price_info(0), 5_000, 1_000).unwrap();
+        assert_eq!(decode_neutralize_quote(&quote.try_to_vec().unwrap()).unwrap(), quote);
+        assert!(decode_neutralize_quote(&[0u8; 8]).is_err());
+    }
+}
@@ -0,0 +1,55 @@
+// P&L report of the treasury (TreasuryStats account).
+use std::fmt::Write;
+
+use kurayashiki::TreasuryStats;
+
+const LAMPORTS_PER_SOL: f64 = 1_000_000_000.0;
+const MICRO_USDC_PER_USDC: f64 = 1_000_000.0;
+
+pub fn pnl_report(stats: &TreasuryStats) -> String {
+    let mut report = String::new();
+    let sol = |lamports: u64| lamports as f64 / LAMPORTS_PER_SOL;
+    let usdc = |micro_usdc: u64| micro_usdc as f64 / MICRO_USDC_PER_USDC;
+
+    writeln!(report, "neutralize (as of collect_from_pool)").unwrap();
+    writeln!(report, "  SOL reimbursed ....... {:.9} SOL", sol(stats.lamports_reimbursed)).unwrap();
+    writeln!(report, "  USDC received ........ {:.6} USDC", usdc(stats.usdc_received)).unwrap();
+    writeln!(report, "  integrator fees ...... {:.6} USDC", usdc(stats.integrator_fees)).unwrap();
+    writeln!(report, "  average rate ......... {}", format_rate(stats.usdc_received + stats.integrator_fees, stats.lamports_reimbursed)).unwrap();
+    writeln!(report, "treasury").unwrap();
+    writeln!(report, "  USDC collected ....... {:.6} USDC", usdc(stats.usdc_collected)).unwrap();
+    writeln!(report, "  USDC swapped ......... {:.6} USDC", usdc(stats.usdc_swapped)).unwrap();
+    writeln!(report, "  SOL received ......... {:.9} SOL", sol(stats.sol_received)).unwrap();
+    writeln!(report, "  swap rate ............ {}", format_rate(stats.usdc_swapped, stats.sol_received)).unwrap();
+    writeln!(report, "  SOL distributed ...... {:.9} SOL", sol(stats.lamports_distributed)).unwrap();
+    writeln!(report, "profit").unwrap();
+    write!(report, "  realized spread ...... {:.9} SOL", stats.realized_spread as f64 / LAMPORTS_PER_SOL).unwrap();
+    report
+}
+
+// USDC per SOL
+fn format_rate(micro_usdc: u64, lamports: u64) -> String {
+    if lamports == 0 {
+        return "-".to_string();
+    }
+    let rate = (micro_usdc as f64 / MICRO_USDC_PER_USDC) / (lamports as f64 / LAMPORTS_PER_SOL);
+    format!("{:.6} USDC/SOL", rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_rate_in_usdc_per_sol() {
+        // 1 USDC for 0.01 SOL
+        assert_eq!(format_rate(1_000_000, 10_000_000), "100.000000 USDC/SOL");
+        assert_eq!(format_rate(1, 3), "333.333333 USDC/SOL");
+        assert_eq!(format_rate(0, 1_000_000_000), "0.000000 USDC/SOL");
+    }
+
+    #[test]
+    fn format_rate_without_lamports() {
+        assert_eq!(format_rate(1_000_000, 0), "-");
+    }
+}
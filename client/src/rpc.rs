@@ -0,0 +1,111 @@
+// JSON-RPC client and transaction signing (the "rpc" feature), used by the keeper and the CLI.
+// Thin wrapper of solana-client's RpcClient (http:// and https:// endpoints) and solana-sdk's Keypair / Transaction.
+use std::fmt;
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{instruction::Instruction, program_pack::Pack, sysvar};
+use solana_client::client_error::ClientError;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::packet::PACKET_DATA_SIZE;
+use solana_sdk::transaction::{Transaction, TransactionError};
+
+pub use solana_sdk::signature::{Keypair, Signer};
+
+#[derive(Debug)]
+pub enum RpcError {
+    Client(Box<ClientError>),
+    Account(String),
+    Keypair(String),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RpcError::Client(e) => write!(f, "rpc error: {}", e),
+            RpcError::Account(e) => write!(f, "invalid account: {}", e),
+            RpcError::Keypair(e) => write!(f, "invalid keypair: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+impl From<ClientError> for RpcError {
+    fn from(e: ClientError) -> Self {
+        RpcError::Client(Box::new(e))
+    }
+}
+
+// keypair file of solana-keygen (JSON array of 64 bytes)
+pub fn read_keypair_file(path: &str) -> std::result::Result<Keypair, RpcError> {
+    solana_sdk::signature::read_keypair_file(path).map_err(|e| RpcError::Keypair(format!("{}: {}", path, e)))
+}
+
+// the instructions fit in a single transaction paid by payer (PACKET_DATA_SIZE)
+pub fn fits_in_a_transaction(instructions: &[Instruction], payer: &Pubkey) -> bool {
+    let transaction = Transaction::new_with_payer(instructions, Some(payer));
+    bincode::serialized_size(&transaction).map(|size| size as usize <= PACKET_DATA_SIZE).unwrap_or(false)
+}
+
+// result of simulateTransaction
+pub struct Simulation {
+    pub err: Option<TransactionError>,
+    pub logs: Vec<String>,
+}
+
+pub struct RpcClient {
+    client: solana_client::rpc_client::RpcClient,
+}
+
+impl RpcClient {
+    // reads and confirmations at the "confirmed" commitment
+    pub fn new(url: &str) -> std::result::Result<Self, RpcError> {
+        Ok(RpcClient { client: solana_client::rpc_client::RpcClient::new_with_commitment(url.to_string(), CommitmentConfig::confirmed()) })
+    }
+
+    // None if the account does not exist
+    pub fn get_account_data(&self, pubkey: &Pubkey) -> std::result::Result<Option<Vec<u8>>, RpcError> {
+        let account = self.client.get_account_with_commitment(pubkey, self.client.commitment())?.value;
+        Ok(account.map(|account| account.data))
+    }
+
+    pub fn get_balance(&self, pubkey: &Pubkey) -> std::result::Result<u64, RpcError> {
+        Ok(self.client.get_balance(pubkey)?)
+    }
+
+    // None if the token account does not exist
+    pub fn get_token_amount(&self, pubkey: &Pubkey) -> std::result::Result<Option<u64>, RpcError> {
+        match self.get_account_data(pubkey)? {
+            Some(data) => {
+                let account = spl_token::state::Account::unpack(&data).map_err(|e| RpcError::Account(format!("{}: {}", pubkey, e)))?;
+                Ok(Some(account.amount))
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_clock(&self) -> std::result::Result<Clock, RpcError> {
+        let data = self.get_account_data(&sysvar::clock::ID)?.ok_or_else(|| RpcError::Account("no clock sysvar".to_string()))?;
+        bincode::deserialize(&data).map_err(|e| RpcError::Account(e.to_string()))
+    }
+
+    // transaction signed by payer and signers with the latest blockhash
+    pub fn sign_transaction(&self, instructions: &[Instruction], payer: &Keypair, signers: &[&Keypair]) -> std::result::Result<Transaction, RpcError> {
+        let keypairs: Vec<&Keypair> = std::iter::once(payer).chain(signers.iter().copied()).collect();
+        let mut transaction = Transaction::new_with_payer(instructions, Some(&payer.pubkey()));
+        transaction.try_sign(&keypairs, self.client.get_latest_blockhash()?).map_err(|e| RpcError::Keypair(e.to_string()))?;
+        Ok(transaction)
+    }
+
+    // returns the signature after the transaction is confirmed
+    pub fn send_and_confirm(&self, instructions: &[Instruction], payer: &Keypair, signers: &[&Keypair]) -> std::result::Result<String, RpcError> {
+        let transaction = self.sign_transaction(instructions, payer, signers)?;
+        Ok(self.client.send_and_confirm_transaction(&transaction)?.to_string())
+    }
+
+    pub fn simulate(&self, instructions: &[Instruction], payer: &Keypair, signers: &[&Keypair]) -> std::result::Result<Simulation, RpcError> {
+        let transaction = self.sign_transaction(instructions, payer, signers)?;
+        let result = self.client.simulate_transaction(&transaction)?.value;
+        Ok(Simulation { err: result.err, logs: result.logs.unwrap_or_default() })
+    }
+}
@@ -0,0 +1,114 @@
+// Pool selection for neutralize.
+// Clients that always pick the same index contend for its write lock and drain it,
+// so the index is chosen among the pools that can pay, round-robin or pseudo-randomly.
+use anchor_lang::solana_program::{pubkey::Pubkey, rent::Rent};
+
+use kurayashiki::{PoolCursor, PoolStats};
+
+// state of a pool read by the caller (lamports of pool_deposit_sol and PoolStats if initialized).
+#[derive(Clone)]
+pub struct PoolState {
+    pub index: u32,
+    pub lamports: u64,
+    pub stats: Option<PoolStats>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum PoolSelection {
+    // the first pool at or after next_index (PoolCursor::index_for of the user)
+    RoundRobin { next_index: u32 },
+    // a pool picked by seed (e.g. a recent blockhash or the slot), not used in the current slot if possible
+    Random { seed: u64, slot: u64 },
+}
+
+impl PoolSelection {
+    pub fn round_robin(cursor: &PoolCursor, user: &Pubkey, slot: u64) -> Self {
+        PoolSelection::RoundRobin { next_index: cursor.index_for(user, slot) }
+    }
+}
+
+// returns the index of a pool that can pay lamports and stay rent exempt (None if no pool can).
+pub fn select_pool(pools: &[PoolState], lamports: u64, selection: PoolSelection) -> Option<u32> {
+    let min_balance = Rent::default().minimum_balance(0);
+    let mut eligible: Vec<&PoolState> = pools.iter()
+        .filter(|pool| pool.lamports >= lamports.saturating_add(min_balance))
+        .collect();
+    if eligible.is_empty() {
+        return None;
+    }
+    eligible.sort_unstable_by_key(|pool| pool.index);
+
+    match selection {
+        PoolSelection::RoundRobin { next_index } => {
+            let pool = eligible.iter().find(|pool| pool.index >= next_index).unwrap_or(&eligible[0]);
+            Some(pool.index)
+        },
+        PoolSelection::Random { seed, slot } => {
+            let idle: Vec<&PoolState> = eligible.iter().copied()
+                .filter(|pool| !matches!(&pool.stats, Some(stats) if stats.last_used_slot >= slot))
+                .collect();
+            let candidates = if idle.is_empty() { &eligible } else { &idle };
+            Some(candidates[(seed % candidates.len() as u64) as usize].index)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(index: u32, lamports: u64, last_used_slot: Option<u64>) -> PoolState {
+        let stats = last_used_slot.map(|last_used_slot| PoolStats {
+            index,
+            neutralize_count: 1,
+            lamports_paid: 0,
+            usdc_received: 0,
+            integrator_fees: 0,
+            last_used_slot,
+            unreported_lamports_paid: 0,
+            unreported_usdc_received: 0,
+            unreported_integrator_fees: 0,
+        });
+        PoolState { index, lamports, stats }
+    }
+
+    #[test]
+    fn round_robin_wraps_around_to_the_first_pool() {
+        let pools = [pool(2, 100_000_000, None), pool(0, 100_000_000, None), pool(1, 100_000_000, None)];
+        assert_eq!(select_pool(&pools, 5_000, PoolSelection::RoundRobin { next_index: 1 }), Some(1));
+        assert_eq!(select_pool(&pools, 5_000, PoolSelection::RoundRobin { next_index: 2 }), Some(2));
+        assert_eq!(select_pool(&pools, 5_000, PoolSelection::RoundRobin { next_index: 3 }), Some(0));
+    }
+
+    #[test]
+    fn round_robin_starts_at_the_pool_of_the_user() {
+        let cursor = PoolCursor { num_pools: 3, next_index: 0 };
+        let pools = [pool(0, 100_000_000, None), pool(1, 100_000_000, None), pool(2, 100_000_000, None)];
+        let users = [Pubkey::new_from_array([0; 32]), Pubkey::new_from_array([1; 32]), Pubkey::new_from_array([2; 32])];
+        let indexes: Vec<Option<u32>> = users.iter().map(|user| select_pool(&pools, 5_000, PoolSelection::round_robin(&cursor, user, 0))).collect();
+        assert_eq!(indexes, vec![Some(0), Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn skips_pools_that_cannot_stay_rent_exempt() {
+        let min_balance = Rent::default().minimum_balance(0);
+        let pools = [pool(0, min_balance + 4_999, None), pool(1, min_balance + 5_000, None)];
+        assert_eq!(select_pool(&pools, 5_000, PoolSelection::RoundRobin { next_index: 0 }), Some(1));
+        assert_eq!(select_pool(&pools, 5_000, PoolSelection::Random { seed: 0, slot: 10 }), Some(1));
+        assert_eq!(select_pool(&pools[..1], 5_000, PoolSelection::RoundRobin { next_index: 0 }), None);
+    }
+
+    #[test]
+    fn random_prefers_pools_not_used_in_the_slot() {
+        // pools 0 and 2 were used in slot 10
+        let pools = [pool(0, 100_000_000, Some(10)), pool(1, 100_000_000, Some(9)), pool(2, 100_000_000, Some(10)), pool(3, 100_000_000, None)];
+        for seed in 0..4 {
+            let index = select_pool(&pools, 5_000, PoolSelection::Random { seed, slot: 10 }).unwrap();
+            assert!(index == 1 || index == 3);
+        }
+
+        // all pools were used in the slot
+        let pools = [pool(0, 100_000_000, Some(10)), pool(1, 100_000_000, Some(10))];
+        assert_eq!(select_pool(&pools, 5_000, PoolSelection::Random { seed: 3, slot: 10 }), Some(1));
+    }
+}
@@ -0,0 +1,93 @@
+// TOML configuration of the keeper (see keeper.example.toml).
+use std::error::Error;
+use std::fs;
+use std::str::FromStr;
+
+use anchor_lang::prelude::*;
+use serde::{Deserialize, Deserializer};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub rpc_url: String,
+    pub keypair: String,                // fee payer (all crank instructions are permissionless)
+    #[serde(deserialize_with = "deserialize_pubkey")]
+    pub creator: Pubkey,
+    #[serde(deserialize_with = "deserialize_pubkey")]
+    pub usdc_mint: Pubkey,
+    pub pools: Vec<u32>,                // pool indexes to watch
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default)]
+    pub thresholds: Thresholds,
+    #[serde(default)]
+    pub update_price: UpdatePriceConfig,
+    pub convert: Option<ConvertConfig>, // convert_to_sol is not submitted if None
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Thresholds {
+    pub price_max_age_secs: i64,        // update_price if PriceInfo is older
+    pub pool_min_lamports: u64,         // distribute_to_pool if a pool holds less (the low-water mark of PoolTarget if initialized)
+    pub collect_min_usdc: u64,          // collect_from_pool if pools hold at least (micro USDC in total)
+    pub convert_min_usdc: u64,          // convert_to_sol if deposit_usdc holds at least (micro USDC)
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            price_max_age_secs: 60 * 60,        // 1 hour
+            pool_min_lamports: 50_000_000,      // 0.05 SOL
+            collect_min_usdc: 1_000_000,        // 1 USDC
+            convert_min_usdc: 1_000_000,        // 1 USDC
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct UpdatePriceConfig {
+    // [swap_program, pool accounts of the swap backend] (required if pool_twap is initialized)
+    #[serde(default)]
+    pub twap_accounts: Vec<AccountConfig>,
+}
+
+#[derive(Deserialize)]
+pub struct ConvertConfig {
+    #[serde(default)]
+    pub minimum_output_amount: u64,
+    // remaining accounts of the configured pool (empty route)
+    pub swap_accounts: Vec<AccountConfig>,
+}
+
+#[derive(Deserialize)]
+pub struct AccountConfig {
+    #[serde(deserialize_with = "deserialize_pubkey")]
+    pub pubkey: Pubkey,
+    #[serde(default)]
+    pub writable: bool,
+}
+
+impl AccountConfig {
+    pub fn to_account_meta(&self) -> AccountMeta {
+        if self.writable { AccountMeta::new(self.pubkey, false) } else { AccountMeta::new_readonly(self.pubkey, false) }
+    }
+}
+
+impl Config {
+    pub fn load(path: &str) -> std::result::Result<Config, Box<dyn Error>> {
+        let config: Config = toml::from_str(&fs::read_to_string(path)?)?;
+        if config.pools.is_empty() {
+            return Err("pools is empty".into());
+        }
+        Ok(config)
+    }
+}
+
+fn default_interval_secs() -> u64 {
+    30
+}
+
+fn deserialize_pubkey<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Pubkey, D::Error> {
+    let s = <String as Deserialize>::deserialize(deserializer)?;
+    Pubkey::from_str(&s).map_err(|e| serde::de::Error::custom(format!("{}: {}", s, e)))
+}
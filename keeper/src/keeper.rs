@@ -0,0 +1,440 @@
+// Reads the state of a deployment and submits the crank instructions whose thresholds are crossed.
+use std::cell::Cell;
+use std::error::Error;
+
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_spl::associated_token::get_associated_token_address;
+use kurayashiki_client::rpc::{self, Keypair, RpcClient, Signer};
+use kurayashiki_client::{crank, decode_oracle_sources, decode_pool_stats, decode_pool_target, decode_price_info, find_address, find_pool_address};
+
+use crate::config::{Config, Thresholds};
+
+pub struct PoolBalance {
+    pub index: u32,
+    pub lamports: u64,      // pool_deposit_sol
+    pub usdc: u64,          // pool_deposit_usdc
+    pub low_water_lamports: Option<u64>,    // PoolTarget if initialized
+    pub last_used_slot: Option<u64>,        // PoolStats if initialized
+}
+
+pub struct State {
+    pub now: i64,
+    pub slot: u64,
+    pub price_updated: i64,
+    pub deposit_usdc: u64,
+    pub pools: Vec<PoolBalance>,
+    pub pool_cursor: bool,      // PoolCursor is initialized
+    pub pool_cursor_advanced_slot: u64,     // slot of the last advance_pool_cursor of the keeper (0 before the first)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    UpdatePrice,
+    Collect(Vec<u32>),
+    Convert,
+    Distribute(Vec<u32>),
+    AdvancePoolCursor,
+}
+
+impl Action {
+    // collect_from_pool, convert_to_sol and distribute_to_pool of a round make up a rebalance.
+    fn is_rebalance(&self) -> bool {
+        matches!(self, Action::Collect(_) | Action::Convert | Action::Distribute(_))
+    }
+}
+
+// actions grouped by transaction in the order of submission (the actions of a rebalance go together).
+pub fn transactions(actions: Vec<Action>) -> Vec<Vec<Action>> {
+    let mut transactions: Vec<Vec<Action>> = Vec::new();
+    for action in actions {
+        match transactions.last_mut() {
+            Some(last) if action.is_rebalance() && last[0].is_rebalance() => last.push(action),
+            _ => transactions.push(vec![action]),
+        }
+    }
+    transactions
+}
+
+// actions in the order of submission.
+pub fn plan(state: &State, thresholds: &Thresholds, convert_enabled: bool) -> Vec<Action> {
+    let mut actions = Vec::new();
+
+    if state.now - state.price_updated >= thresholds.price_max_age_secs {
+        actions.push(Action::UpdatePrice);
+    }
+
+    let pool_usdc: u64 = state.pools.iter().map(|pool| pool.usdc).sum();
+    let collected_usdc = if pool_usdc > 0 && pool_usdc >= thresholds.collect_min_usdc {
+        actions.push(Action::Collect(state.pools.iter().filter(|pool| pool.usdc > 0).map(|pool| pool.index).collect()));
+        pool_usdc
+    } else {
+        0
+    };
+
+    let deposit_usdc = state.deposit_usdc + collected_usdc;
+    if convert_enabled && deposit_usdc > 0 && deposit_usdc >= thresholds.convert_min_usdc {
+        actions.push(Action::Convert);
+    }
+
+    let low_pools: Vec<u32> = state.pools.iter()
+        .filter(|pool| pool.lamports < pool.low_water_lamports.unwrap_or(thresholds.pool_min_lamports))
+        .map(|pool| pool.index)
+        .collect();
+    if !low_pools.is_empty() {
+        actions.push(Action::Distribute(low_pools));
+    }
+
+    // clients start their round-robin at the next pool each round, a round ends once a pool has been used
+    // by neutralize since the last advance (users start at their own pool, see PoolCursor::index_for).
+    let pool_used = state.pools.iter().any(|pool| pool.last_used_slot.is_some_and(|slot| slot > state.pool_cursor_advanced_slot));
+    if state.pool_cursor && pool_used {
+        actions.push(Action::AdvancePoolCursor);
+    }
+
+    actions
+}
+
+pub struct Keeper {
+    config: Config,
+    rpc: RpcClient,
+    payer: Keypair,
+    pool_cursor_advanced_slot: Cell<u64>,
+}
+
+impl Keeper {
+    pub fn new(config: Config) -> std::result::Result<Keeper, Box<dyn Error>> {
+        let rpc = RpcClient::new(&config.rpc_url)?;
+        let payer = rpc::read_keypair_file(&config.keypair)?;
+        Ok(Keeper { config, rpc, payer, pool_cursor_advanced_slot: Cell::new(0) })
+    }
+
+    pub fn read_state(&self) -> std::result::Result<State, Box<dyn Error>> {
+        let creator = &self.config.creator;
+        let price_info_data = self.rpc.get_account_data(&find_address("price_info", creator))?.ok_or("price_info is not initialized")?;
+        let price_info = decode_price_info(&price_info_data)?;
+
+        let deposit_usdc = get_associated_token_address(&find_address("deposit_sol", creator), &self.config.usdc_mint);
+        let mut pools = Vec::with_capacity(self.config.pools.len());
+        for index in self.config.pools.iter() {
+            let pool_deposit_sol = find_pool_address("deposit_sol", *index, creator);
+            let pool_deposit_usdc = get_associated_token_address(&pool_deposit_sol, &self.config.usdc_mint);
+            let pool_target = self.rpc.get_account_data(&find_pool_address("pool_target", *index, creator))?.map(|data| decode_pool_target(&data)).transpose()?;
+            let pool_stats = self.rpc.get_account_data(&find_pool_address("pool_stats", *index, creator))?.map(|data| decode_pool_stats(&data)).transpose()?;
+            pools.push(PoolBalance {
+                index: *index,
+                lamports: self.rpc.get_balance(&pool_deposit_sol)?,
+                usdc: self.rpc.get_token_amount(&pool_deposit_usdc)?.unwrap_or(0),
+                low_water_lamports: pool_target.map(|target| target.low_water_lamports),
+                last_used_slot: pool_stats.map(|stats| stats.last_used_slot),
+            });
+        }
+
+        let clock = self.rpc.get_clock()?;
+        Ok(State {
+            now: clock.unix_timestamp,
+            slot: clock.slot,
+            price_updated: price_info.current_usdc_per_sol_price_updated,
+            deposit_usdc: self.rpc.get_token_amount(&deposit_usdc)?.unwrap_or(0),
+            pools,
+            pool_cursor: self.rpc.get_account_data(&find_address("pool_cursor", creator))?.is_some(),
+            pool_cursor_advanced_slot: self.pool_cursor_advanced_slot.get(),
+        })
+    }
+
+    // instructions of a transaction of actions (a rebalance is built by crank::rebalance_instructions).
+    pub fn instructions(&self, actions: &[Action]) -> std::result::Result<Vec<Instruction>, Box<dyn Error>> {
+        let creator = &self.config.creator;
+        let mut instructions = Vec::new();
+        let (mut collect, mut convert, mut distribute): (&[u32], Option<Instruction>, &[u32]) = (&[], None, &[]);
+        for action in actions {
+            match action {
+                Action::UpdatePrice => {
+                    let data = self.rpc.get_account_data(&find_address("oracle_sources", creator))?.ok_or("oracle_sources is not initialized")?;
+                    let twap_accounts = self.config.update_price.twap_accounts.iter().map(|account| account.to_account_meta()).collect();
+                    instructions.push(crank::update_price_instruction(creator, &decode_oracle_sources(&data)?, twap_accounts));
+                },
+                Action::Collect(indexes) => collect = indexes,
+                Action::Convert => {
+                    let config = self.config.convert.as_ref().ok_or("convert is not configured")?;
+                    let swap_accounts = config.swap_accounts.iter().map(|account| account.to_account_meta()).collect();
+                    convert = Some(crank::convert_to_sol_instruction(creator, &self.config.usdc_mint, vec![], config.minimum_output_amount, swap_accounts));
+                },
+                Action::Distribute(indexes) => distribute = indexes,
+                Action::AdvancePoolCursor => instructions.push(crank::advance_pool_cursor_instruction(creator)),
+            }
+        }
+        instructions.extend(crank::rebalance_instructions(creator, &self.config.usdc_mint, collect, convert, distribute));
+        Ok(instructions)
+    }
+
+    // the instructions are sent in one transaction, or in a transaction each (in order) if they do not fit in a packet.
+    fn submit(&self, instructions: &[Instruction]) -> std::result::Result<(), Box<dyn Error>> {
+        let transactions: Vec<&[Instruction]> = if rpc::fits_in_a_transaction(instructions, &self.payer.pubkey()) {
+            vec![instructions]
+        } else {
+            instructions.chunks(1).collect()
+        };
+        for transaction in transactions {
+            let signature = self.rpc.send_and_confirm(transaction, &self.payer, &[])?;
+            println!("\ttx signature {}", signature);
+        }
+        Ok(())
+    }
+
+    // a failed transaction is reported and the following transactions are still submitted.
+    pub fn tick(&self, dry_run: bool) -> std::result::Result<(), Box<dyn Error>> {
+        let state = self.read_state()?;
+        println!("price age {}s, deposit_usdc {}, pools {}", state.now - state.price_updated, state.deposit_usdc,
+            state.pools.iter().map(|pool| format!("{}:{}/{}", pool.index, pool.lamports, pool.usdc)).collect::<Vec<String>>().join(" "));
+
+        for actions in transactions(plan(&state, &self.config.thresholds, self.config.convert.is_some())) {
+            println!("{:?}", actions);
+            if dry_run {
+                continue;
+            }
+            match self.instructions(&actions).and_then(|instructions| self.submit(&instructions)) {
+                Ok(()) if actions.contains(&Action::AdvancePoolCursor) => self.pool_cursor_advanced_slot.set(state.slot),
+                Ok(()) => (),
+                Err(e) => eprintln!("\t{:?} failed: {}", actions, e),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn interval_secs(&self) -> u64 {
+        self.config.interval_secs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::{env, fs};
+    use anchor_lang::prelude::*;
+    use anchor_lang::solana_program::{native_token::LAMPORTS_PER_SOL, program_pack::Pack, system_instruction};
+    use anchor_spl::token;
+    use kurayashiki_client::{admin, decode_pool_cursor, decode_treasury_stats};
+    use kurayashiki_client::rpc::{Keypair, Signer};
+    use solana_sdk::commitment_config::CommitmentConfig;
+    use solana_sdk::signature::write_keypair_file;
+
+    use crate::config::UpdatePriceConfig;
+
+    fn pool(index: u32, lamports: u64, usdc: u64) -> PoolBalance {
+        PoolBalance { index, lamports, usdc, low_water_lamports: None, last_used_slot: None }
+    }
+
+    fn new_state(price_age: i64, deposit_usdc: u64, pools: Vec<PoolBalance>) -> State {
+        State { now: 10_000, slot: 25_000, price_updated: 10_000 - price_age, deposit_usdc, pools, pool_cursor: false, pool_cursor_advanced_slot: 0 }
+    }
+
+    #[test]
+    fn plan_is_empty_below_the_thresholds() {
+        let thresholds = Thresholds::default();
+        let state = new_state(60 * 60 - 1, 999_999, vec![pool(0, 50_000_000, 500_000), pool(1, 100_000_000, 499_999)]);
+        assert_eq!(plan(&state, &thresholds, true), vec![]);
+    }
+
+    #[test]
+    fn plan_updates_an_old_price() {
+        let thresholds = Thresholds::default();
+        assert_eq!(plan(&new_state(60 * 60, 0, vec![]), &thresholds, true), vec![Action::UpdatePrice]);
+    }
+
+    #[test]
+    fn plan_collects_pools_holding_usdc_in_total() {
+        let thresholds = Thresholds::default();
+        let state = new_state(0, 0, vec![pool(0, 100_000_000, 600_000), pool(1, 100_000_000, 0), pool(2, 100_000_000, 400_000)]);
+        assert_eq!(plan(&state, &thresholds, false), vec![Action::Collect(vec![0, 2])]);
+    }
+
+    #[test]
+    fn plan_converts_the_usdc_to_be_collected() {
+        let thresholds = Thresholds::default();
+        let state = new_state(0, 500_000, vec![pool(0, 100_000_000, 1_000_000)]);
+        assert_eq!(plan(&state, &thresholds, true), vec![Action::Collect(vec![0]), Action::Convert]);
+        assert_eq!(plan(&state, &thresholds, false), vec![Action::Collect(vec![0])]);
+
+        // usdc below collect_min_usdc is not collected, so it is not converted either.
+        let state = new_state(0, 600_000, vec![pool(0, 100_000_000, 500_000)]);
+        assert_eq!(plan(&state, &thresholds, true), vec![]);
+    }
+
+    #[test]
+    fn plan_distributes_to_pools_below_the_low_water_mark() {
+        let thresholds = Thresholds::default();
+        let mut pools = vec![pool(0, 49_999_999, 0), pool(1, 50_000_000, 0), pool(2, 140_000_000, 0), pool(3, 10_000_000, 0)];
+        pools[2].low_water_lamports = Some(150_000_000);    // PoolTarget overrides pool_min_lamports
+        pools[3].low_water_lamports = Some(5_000_000);
+        assert_eq!(plan(&new_state(0, 0, pools), &thresholds, true), vec![Action::Distribute(vec![0, 2])]);
+    }
+
+    #[test]
+    fn plan_advances_the_pool_cursor_once_a_pool_is_used() {
+        let thresholds = Thresholds::default();
+        let mut pools = vec![pool(0, 100_000_000, 0), pool(1, 100_000_000, 0)];
+        pools[1].last_used_slot = Some(20_000);
+        let state = State { pool_cursor: true, ..new_state(0, 0, pools) };
+        assert_eq!(plan(&state, &thresholds, true), vec![Action::AdvancePoolCursor]);
+
+        // no pool has been used since the last advance.
+        let state = State { pool_cursor_advanced_slot: 20_000, ..state };
+        assert_eq!(plan(&state, &thresholds, true), vec![]);
+
+        // pools without PoolStats are never used.
+        let state = State { pool_cursor: true, ..new_state(0, 0, vec![pool(0, 100_000_000, 0)]) };
+        assert_eq!(plan(&state, &thresholds, true), vec![]);
+
+        let mut pools = vec![pool(0, 100_000_000, 0)];
+        pools[0].last_used_slot = Some(20_000);
+        assert_eq!(plan(&new_state(0, 0, pools), &thresholds, true), vec![]);
+    }
+
+    #[test]
+    fn plan_orders_the_actions() {
+        let thresholds = Thresholds::default();
+        let mut pools = vec![pool(0, 0, 1_000_000), pool(1, 100_000_000, 0)];
+        pools[0].last_used_slot = Some(1);
+        let state = State { pool_cursor: true, ..new_state(60 * 60, 0, pools) };
+        assert_eq!(plan(&state, &thresholds, true),
+            vec![Action::UpdatePrice, Action::Collect(vec![0]), Action::Convert, Action::Distribute(vec![0]), Action::AdvancePoolCursor]);
+    }
+
+    #[test]
+    fn transactions_group_the_actions_of_a_rebalance() {
+        let actions = vec![Action::UpdatePrice, Action::Collect(vec![0]), Action::Convert, Action::Distribute(vec![0]), Action::AdvancePoolCursor];
+        assert_eq!(transactions(actions), vec![
+            vec![Action::UpdatePrice],
+            vec![Action::Collect(vec![0]), Action::Convert, Action::Distribute(vec![0])],
+            vec![Action::AdvancePoolCursor],
+        ]);
+        assert_eq!(transactions(vec![Action::Distribute(vec![1])]), vec![vec![Action::Distribute(vec![1])]]);
+    }
+
+    #[test]
+    fn a_rebalance_of_a_page_fits_in_a_transaction_without_convert() {
+        let (creator, usdc_mint, payer) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let indexes = [0, 1, 2, 3];
+        assert!(rpc::fits_in_a_transaction(&crank::rebalance_instructions(&creator, &usdc_mint, &indexes, None, &indexes), &payer));
+
+        // the swap accounts of convert_to_sol (e.g. a Whirlpool) do not fit together with a page of pools.
+        let swap_accounts = (0..8).map(|_| AccountMeta::new(Pubkey::new_unique(), false)).collect();
+        let convert = crank::convert_to_sol_instruction(&creator, &usdc_mint, vec![], 0, swap_accounts);
+        assert!(!rpc::fits_in_a_transaction(&crank::rebalance_instructions(&creator, &usdc_mint, &indexes, Some(convert), &indexes), &payer));
+    }
+
+    // a deployment on a local validator with the program deployed at its ID:
+    //   anchor build && solana-test-validator --reset --bpf-program <program ID> target/deploy/kurayashiki.so
+    //   cargo test -p kurayashiki-keeper -- --ignored
+    // (KURAYASHIKI_TEST_VALIDATOR_URL overrides http://127.0.0.1:8899)
+    struct Deployment {
+        keeper: Keeper,
+        creator: Keypair,
+        usdc_mint: Pubkey,
+        keypair_path: PathBuf,
+    }
+
+    impl Deployment {
+        // initialize and create_pool of pools 0..4 (0.1 SOL each, pool_usdc micro USDC each),
+        // deposit_sol holds DEPOSIT_SOL_MIN_BALANCE (0.1 SOL) and deposit_sol_lamports.
+        fn new(deposit_sol_lamports: u64, pool_usdc: [u64; 4]) -> Deployment {
+            let rpc_url = env::var("KURAYASHIKI_TEST_VALIDATOR_URL").unwrap_or_else(|_| "http://127.0.0.1:8899".to_string());
+            let creator = Keypair::new();
+            let client = solana_client::rpc_client::RpcClient::new_with_commitment(rpc_url.clone(), CommitmentConfig::confirmed());
+            client.poll_for_signature(&client.request_airdrop(&creator.pubkey(), 10 * LAMPORTS_PER_SOL).unwrap()).unwrap();
+
+            // the creator is the fee payer of the keeper too.
+            let keypair_path = env::temp_dir().join(format!("kurayashiki-keeper-{}.json", creator.pubkey()));
+            write_keypair_file(&creator, &keypair_path).unwrap();
+            let usdc_mint = Keypair::new();
+            let keeper = Keeper::new(Config {
+                rpc_url,
+                keypair: keypair_path.to_str().unwrap().to_string(),
+                creator: creator.pubkey(),
+                usdc_mint: usdc_mint.pubkey(),
+                pools: vec![0, 1, 2, 3],
+                interval_secs: 30,
+                thresholds: Thresholds::default(),
+                update_price: UpdatePriceConfig::default(),
+                convert: None,
+            }).unwrap();
+            let deployment = Deployment { keeper, creator, usdc_mint: usdc_mint.pubkey(), keypair_path };
+
+            let creator_key = deployment.creator.pubkey();
+            deployment.send(&[
+                system_instruction::create_account(&creator_key, &deployment.usdc_mint, 1_461_600, spl_token::state::Mint::LEN as u64, &token::ID),
+                spl_token::instruction::initialize_mint(&token::ID, &deployment.usdc_mint, &creator_key, None, 6).unwrap(),
+            ], &[&usdc_mint]);
+            deployment.send(&[admin::initialize(&creator_key, &deployment.usdc_mint), admin::initialize_treasury_stats(&creator_key, &deployment.usdc_mint)], &[]);
+            deployment.send(&[system_instruction::transfer(&creator_key, &find_address("deposit_sol", &creator_key), deposit_sol_lamports)], &[]);
+            for (index, usdc) in pool_usdc.iter().enumerate() {
+                let index = index as u32;
+                deployment.send(&[admin::create_pool(&creator_key, &deployment.usdc_mint, index)], &[]);
+                if *usdc > 0 {
+                    let pool_deposit_usdc = get_associated_token_address(&find_pool_address("deposit_sol", index, &creator_key), &deployment.usdc_mint);
+                    deployment.send(&[spl_token::instruction::mint_to(&token::ID, &deployment.usdc_mint, &pool_deposit_usdc, &creator_key, &[], *usdc).unwrap()], &[]);
+                }
+            }
+            deployment.send(&[admin::initialize_pool_cursor(&creator_key, 4)], &[]);
+            deployment
+        }
+
+        fn send(&self, instructions: &[Instruction], signers: &[&Keypair]) {
+            self.keeper.rpc.send_and_confirm(instructions, &self.creator, signers).unwrap();
+        }
+
+        fn treasury_stats(&self) -> kurayashiki::TreasuryStats {
+            decode_treasury_stats(&self.keeper.rpc.get_account_data(&find_address("treasury_stats", &self.creator.pubkey())).unwrap().unwrap()).unwrap()
+        }
+    }
+
+    impl Drop for Deployment {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.keypair_path);
+        }
+    }
+
+    #[test]
+    #[ignore = "requires solana-test-validator with the kurayashiki program"]
+    fn crank_cycle_collects_and_refills_pools() {
+        // pool 0 is below the low-water mark of its PoolTarget, pools 1 and 2 hold USDC.
+        let deployment = Deployment::new(LAMPORTS_PER_SOL, [0, 2_000_000, 3_000_000, 0]);
+        deployment.send(&[admin::initialize_pool_target(&deployment.creator.pubkey(), 0, 200_000_000, 150_000_000)], &[]);
+
+        let thresholds = Thresholds::default();
+        let state = deployment.keeper.read_state().unwrap();
+        assert_eq!(plan(&state, &thresholds, false), vec![Action::UpdatePrice, Action::Collect(vec![1, 2]), Action::Distribute(vec![0])]);
+
+        // update_price fails without oracle_sources, the rebalance is still sent (in one transaction).
+        deployment.keeper.tick(false).unwrap();
+
+        let state = deployment.keeper.read_state().unwrap();
+        assert_eq!(state.deposit_usdc, 5_000_000);
+        assert_eq!(state.pools.iter().map(|pool| (pool.lamports, pool.usdc)).collect::<Vec<(u64, u64)>>(),
+            vec![(200_000_000, 0), (100_000_000, 0), (100_000_000, 0), (100_000_000, 0)]);
+        // no pool has been used by neutralize, so the cursor stays.
+        assert_eq!(plan(&state, &thresholds, false), vec![Action::UpdatePrice]);
+
+        let treasury_stats = deployment.treasury_stats();
+        assert_eq!(treasury_stats.usdc_collected, 5_000_000);
+        assert_eq!(treasury_stats.lamports_distributed, 100_000_000);
+        let pool_cursor = deployment.keeper.rpc.get_account_data(&find_address("pool_cursor", &deployment.creator.pubkey())).unwrap().unwrap();
+        assert_eq!(decode_pool_cursor(&pool_cursor).unwrap().next_index, 0);
+    }
+
+    #[test]
+    #[ignore = "requires solana-test-validator with the kurayashiki program"]
+    fn distribute_below_the_deposit_sol_minimum_refills_nothing() {
+        // deposit_sol holds only DEPOSIT_SOL_MIN_BALANCE (0.1 SOL), pool 0 is below the low-water mark.
+        let deployment = Deployment::new(0, [0; 4]);
+        deployment.send(&[admin::initialize_pool_target(&deployment.creator.pubkey(), 0, 200_000_000, 150_000_000)], &[]);
+
+        deployment.keeper.tick(false).unwrap();
+
+        let state = deployment.keeper.read_state().unwrap();
+        assert_eq!(state.pools.iter().map(|pool| pool.lamports).collect::<Vec<u64>>(), vec![100_000_000; 4]);
+        assert_eq!(deployment.keeper.rpc.get_balance(&find_address("deposit_sol", &deployment.creator.pubkey())).unwrap(), 100_000_000);
+        assert_eq!(deployment.treasury_stats().lamports_distributed, 0);
+    }
+}
@@ -0,0 +1,47 @@
+// kurayashiki-keeper: watches PriceInfo freshness, pool SOL balances and deposit_usdc,
+// and submits update_price, a rebalance (collect_from_pool, convert_to_sol, distribute_to_pool) and advance_pool_cursor.
+//
+// usage: kurayashiki-keeper <config.toml> [--once] [--dry-run]
+//   --once     run a single round (e.g. against solana-test-validator)
+//   --dry-run  print the actions without submitting them
+use std::{env, process, thread, time::Duration};
+
+mod config;
+mod keeper;
+
+use config::Config;
+use keeper::Keeper;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let config_path = match args.iter().find(|arg| !arg.starts_with("--")) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: kurayashiki-keeper <config.toml> [--once] [--dry-run]");
+            process::exit(2);
+        },
+    };
+    let once = args.iter().any(|arg| arg == "--once");
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+
+    let keeper = match Config::load(config_path).and_then(Keeper::new) {
+        Ok(keeper) => keeper,
+        Err(e) => {
+            eprintln!("{}: {}", config_path, e);
+            process::exit(1);
+        },
+    };
+
+    loop {
+        if let Err(e) = keeper.tick(dry_run) {
+            eprintln!("{}", e);
+            if once {
+                process::exit(1);
+            }
+        }
+        if once {
+            break;
+        }
+        thread::sleep(Duration::from_secs(keeper.interval_secs()));
+    }
+}
@@ -1,46 +1,48 @@
 use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
 use anchor_spl::{
     token::{TokenAccount, Mint, Token},
-    associated_token::AssociatedToken,
-};
-use solana_program::{
-    pubkey,
-    instruction::{Instruction, AccountMeta},
-};
-use pyth_client::{
-    Price,
-    PriceStatus,
-    AccountType,
-    VERSION_2,
-    MAGIC,
-    cast
+    associated_token::{AssociatedToken, get_associated_token_address},
 };
+use solana_program::pubkey;
 use std::convert::TryFrom;
-use borsh::{BorshDeserialize, BorshSerialize};
-use spl_token;
+
+pub mod swap;
+use swap::{SwapBackend, SwapAccounts, SwapPoolAccounts, SwapPool, SwapHop};
+mod oracle;
+use oracle::{OracleSource, OracleSourceType, is_pool_twap_outweighed, weighted_median};
 
 declare_id!("F3jaebcEGakVPRagMXGZ13iPSnH5XUiwW35A5LCe1eVe");
 
 // DEVNET accounts
 const WSOL_MINT: Pubkey = pubkey!("So11111111111111111111111111111111111111112");
+#[allow(dead_code)]
 const USDC_MINT: Pubkey = pubkey!("FMwbjM1stnTzi74LV4cS937jeSUds7mZDgcdgnJ1yBDw"); // My USDC on Devnet
 const ORCA_SWAP_PROGRAM_ID: Pubkey = pubkey!("3xQ8SWv2GaFXXpHZNqkXsdxq5DZciHBz6ZFoPPfbFd7U");
 const ORCA_ADDRESS: Pubkey = pubkey!("DosfiDxjKb9b3XAuqwZ8cbg7F9iWtuew1PpKrkXUxi1V");
-const ORCA_AUTHORITY: Pubkey = pubkey!("EAj5W6dVep8xK5MXgu22sD28cBWLmGc3GL963sMVod44");
-const ORCA_POOL_WSOL: Pubkey = pubkey!("C3cMYaFPADiGYJjdYTZ7PiuduMuuGLRV3Nsdo1N1m1xo");
-const ORCA_POOL_USDC: Pubkey = pubkey!("GKmRB5Zpq77aws79YDZ3Ekke4x5g6mBJqCq86vy36Yqe");
-const ORCA_POOL_TOKEN_MINT: Pubkey = pubkey!("7AEdkVjrFAfYMgJTRW7VAXyxiq7652t8eQWa5JcDL7fM");
-const ORCA_FEE_ACCOUNT: Pubkey = pubkey!("67kMLL5ezDoxRD3PAtNP1LDE9oV4archrmNeePySHLQ");
 const PYTH_SOL_USDC_PRICE: Pubkey = pubkey!("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix");
 
-const ORCA_SWAP_FEE: (u128, u128) = (30, 10000); // 0.3% (trade_fee: 0.25%, owner_fee: 0.05%)
-
 // KURAYASHIKI specific parameters
 const DEPOSIT_SOL_MIN_BALANCE: u64 = 100_000_000; // 0.1 SOL (>= rent_exempt of system account * 2)
 const POOL_DEPOSIT_SOL_MAX_BALANCE: u64 = 100_000_000; // 0.1 SOL
 const MAX_ACCEPTABLE_DIFF_LAMPORTS: u64 = 50_000; // 0.000050 SOL (network fee of TX with 10 signers)
 const MAX_ACCEPTABLE_UPDATE_INTERVAL: i64 = 60 * 60 * 72; // 72 hours
 const GRACE_PERIOD: i64 = 60 * 10; // 10 minutes
+const MAX_SWAP_HOPS: usize = 3; // USDT -> USDC -> SOL needs 2 hops
+const MAX_ROUTE_POOLS: usize = 4;
+const MAX_ORACLE_SOURCES: usize = 4;
+const DEFAULT_MAX_STALENESS_SLOTS: u64 = 150; // about 60 seconds
+const DEFAULT_MANUAL_PRICE_MAX_DEVIATION_BPS: u16 = 500; // 5% of the last oracle price
+const DEFAULT_MANUAL_PRICE_DURATION: i64 = 60 * 60 * 6; // 6 hours
+const TWAP_OBSERVATIONS: usize = 8;
+const TWAP_OBSERVATION_INTERVAL: i64 = 60 * 5; // 5 minutes
+const TWAP_MIN_WINDOW: i64 = 60 * 10; // 10 minutes
+const PRICE_HISTORY_ENTRIES: usize = 16;
+pub const PRICE_INFO_SPACE: usize = 48 + 8;
+const PRICE_INFO_V1_SPACE: usize = 32 + 8; // before last_oracle_usdc_per_sol_price and manual_price_expiry
+const MAX_INTEGRATOR_SHARE_BPS: u16 = 5_000; // 50% of the charge
+const DEFAULT_WHIRLPOOL_SLIPPAGE_BPS: u16 = 100; // 1.0%
+const POOL_ROTATION_SLOTS: u64 = 150; // about 60 seconds, a user starts at the same pool within the window
 
 #[program]
 pub mod kurayashiki {
@@ -67,6 +69,47 @@ pub mod kurayashiki {
         ctx.accounts.price_info.current_usdc_per_sol_price_updated = 0;
         ctx.accounts.price_info.old_usdc_per_sol_price = 0;
         ctx.accounts.price_info.old_usdc_per_sol_price_grace_period = 0;
+        ctx.accounts.price_info.last_oracle_usdc_per_sol_price = 0;
+        ctx.accounts.price_info.manual_price_expiry = 0;
+
+        Ok(())
+    }
+
+    // for price_info created before the manual price: grows it to the current PriceInfo,
+    // last_oracle_usdc_per_sol_price and manual_price_expiry are zero-initialized.
+    pub fn migrate_price_info(ctx: Context<MigratePriceInfo>) -> ProgramResult {
+        let price_info = &ctx.accounts.price_info;
+        if price_info.owner != ctx.program_id || !price_info.try_borrow_data()?.starts_with(&PriceInfo::discriminator()) {
+            return Err(ErrorCode::InvalidPriceInfo.into());
+        }
+        if price_info.data_len() == PRICE_INFO_SPACE {
+            msg!("price_info is migrated already");
+            return Ok(());
+        }
+        if price_info.data_len() != PRICE_INFO_V1_SPACE {
+            return Err(ErrorCode::InvalidPriceInfo.into());
+        }
+
+        let rent_lamports = Rent::get()?.minimum_balance(PRICE_INFO_SPACE).saturating_sub(price_info.lamports());
+        if rent_lamports > 0 {
+            msg!("transfer sol, creator to price_info, {} lamports", rent_lamports);
+            let ix = solana_program::system_instruction::transfer(
+                &ctx.accounts.creator.key(),
+                &price_info.key(),
+                rent_lamports,
+            );
+            solana_program::program::invoke(
+                &ix,
+                &[
+                    ctx.accounts.system_program.to_account_info(),
+                    ctx.accounts.creator.to_account_info(),
+                    price_info.clone(),
+                ],
+            )?;
+        }
+
+        msg!("realloc price_info, {} -> {} bytes", price_info.data_len(), PRICE_INFO_SPACE);
+        price_info.realloc(PRICE_INFO_SPACE, true)?;
 
         Ok(())
     }
@@ -87,57 +130,500 @@ pub mod kurayashiki {
             ],
         )?;
 
+        let pool_stats = &mut ctx.accounts.pool_stats;
+        pool_stats.index = index;
+        pool_stats.neutralize_count = 0;
+        pool_stats.lamports_paid = 0;
+        pool_stats.usdc_received = 0;
+        pool_stats.integrator_fees = 0;
+        pool_stats.last_used_slot = 0;
+        pool_stats.unreported_lamports_paid = 0;
+        pool_stats.unreported_usdc_received = 0;
+        pool_stats.unreported_integrator_fees = 0;
+
+        Ok(())
+    }
+
+    // for pools created before PoolStats was introduced.
+    pub fn initialize_pool_stats(ctx: Context<InitializePoolStats>, index: u32) -> ProgramResult {
+        msg!("initialize pool_stats, index {}", index);
+        let pool_stats = &mut ctx.accounts.pool_stats;
+        pool_stats.index = index;
+        pool_stats.neutralize_count = 0;
+        pool_stats.lamports_paid = 0;
+        pool_stats.usdc_received = 0;
+        pool_stats.integrator_fees = 0;
+        pool_stats.last_used_slot = 0;
+        pool_stats.unreported_lamports_paid = 0;
+        pool_stats.unreported_usdc_received = 0;
+        pool_stats.unreported_integrator_fees = 0;
+
+        Ok(())
+    }
+
+    pub fn initialize_pool_cursor(ctx: Context<InitializePoolCursor>, num_pools: u32) -> ProgramResult {
+        if num_pools == 0 {
+            return Err(ErrorCode::InvalidPoolCursor.into());
+        }
+
+        msg!("initialize pool_cursor, num_pools {}", num_pools);
+        let pool_cursor = &mut ctx.accounts.pool_cursor;
+        pool_cursor.num_pools = num_pools;
+        pool_cursor.next_index = 0;
+
+        Ok(())
+    }
+
+    pub fn set_num_pools(ctx: Context<UpdatePoolCursor>, num_pools: u32) -> ProgramResult {
+        if num_pools == 0 {
+            return Err(ErrorCode::InvalidPoolCursor.into());
+        }
+
+        let pool_cursor = &mut ctx.accounts.pool_cursor;
+        pool_cursor.num_pools = num_pools;
+        pool_cursor.next_index %= num_pools;
+        msg!("num_pools: {}, next_index: {}", pool_cursor.num_pools, pool_cursor.next_index);
+
+        Ok(())
+    }
+
+    // permissionless, sent by the crank (neutralize does not lock the cursor).
+    pub fn advance_pool_cursor(ctx: Context<AdvancePoolCursor>) -> ProgramResult {
+        let pool_cursor = &mut ctx.accounts.pool_cursor;
+        pool_cursor.next_index = (pool_cursor.next_index + 1) % pool_cursor.num_pools;
+        msg!("next_index: {}", pool_cursor.next_index);
+
+        Ok(())
+    }
+
+    pub fn initialize_pool_target(ctx: Context<InitializePoolTarget>, index: u32, target_lamports: u64, low_water_lamports: u64) -> ProgramResult {
+        if target_lamports == 0 || low_water_lamports > target_lamports {
+            return Err(ErrorCode::InvalidPoolTarget.into());
+        }
+
+        msg!("initialize pool_target, index {}", index);
+        let pool_target = &mut ctx.accounts.pool_target;
+        pool_target.index = index;
+        pool_target.target_lamports = target_lamports;
+        pool_target.low_water_lamports = low_water_lamports;
+
+        Ok(())
+    }
+
+    pub fn set_pool_target(ctx: Context<UpdatePoolTarget>, _index: u32, target_lamports: u64, low_water_lamports: u64) -> ProgramResult {
+        if target_lamports == 0 || low_water_lamports > target_lamports {
+            return Err(ErrorCode::InvalidPoolTarget.into());
+        }
+
+        let pool_target = &mut ctx.accounts.pool_target;
+        pool_target.target_lamports = target_lamports;
+        pool_target.low_water_lamports = low_water_lamports;
+        msg!("index: {}, target_lamports: {}, low_water_lamports: {}", pool_target.index, pool_target.target_lamports, pool_target.low_water_lamports);
+
+        Ok(())
+    }
+
+    pub fn initialize_config(ctx: Context<InitializeConfig>) -> ProgramResult {
+        msg!("initialize config");
+        ctx.accounts.config.swap_backend = SwapBackend::TokenSwap;
+        ctx.accounts.config.swap_program = ORCA_SWAP_PROGRAM_ID;
+        ctx.accounts.config.swap_pool = ORCA_ADDRESS;
+        ctx.accounts.config.whirlpool_slippage_bps = DEFAULT_WHIRLPOOL_SLIPPAGE_BPS;
+
+        Ok(())
+    }
+
+    pub fn set_swap_backend(ctx: Context<UpdateConfig>, swap_backend: SwapBackend, swap_program: Pubkey, swap_pool: Pubkey) -> ProgramResult {
+        ctx.accounts.config.swap_backend = swap_backend;
+        ctx.accounts.config.swap_program = swap_program;
+        ctx.accounts.config.swap_pool = swap_pool;
+
+        msg!("swap_backend: {:?}", ctx.accounts.config.swap_backend);
+        msg!("swap_program: {}", ctx.accounts.config.swap_program);
+        msg!("swap_pool: {}", ctx.accounts.config.swap_pool);
+
+        Ok(())
+    }
+
+    pub fn set_route_pools(ctx: Context<UpdateConfig>, route_pools: Vec<SwapPool>) -> ProgramResult {
+        if route_pools.len() > MAX_ROUTE_POOLS {
+            return Err(ErrorCode::InvalidSwapRoute.into());
+        }
+
+        ctx.accounts.config.route_pools = route_pools;
+        for route_pool in ctx.accounts.config.route_pools.iter() {
+            msg!("route_pool: {:?} {} {}", route_pool.swap_backend, route_pool.swap_program, route_pool.swap_pool);
+        }
+
         Ok(())
     }
 
-    pub fn update_price(ctx: Context<UpdatePrice>) -> ProgramResult {
-        msg!("read from PYTH Price account");
-        let pyth_price_account_info = ctx.accounts.pyth_sol_usdc_price.to_account_info();
-        let data = pyth_price_account_info.data.borrow();
-        let price = cast::<Price>( &data );
-    
-        assert_eq!( price.magic, MAGIC, "not a valid pyth account" );
-        assert_eq!( price.atype, AccountType::Price as u32, "not a valid pyth price account" );
-        assert_eq!( price.ver, VERSION_2, "unexpected pyth price account version" );
-    
-        let maybe_price = get_pyth_current_price(price);
-        match maybe_price {
-            Some((p, confidence, expo)) => {
-                msg!("pyth price ........ {} x 10^{}", p, expo);
-                msg!("pyth conf ......... {} x 10^{}", confidence, expo);
-
-                let mut micro_usdc = p;
-                let mut e = expo;
-                while e < -6 { micro_usdc /= 10; e += 1; }
-                while e > -6 { micro_usdc *= 10; e -= 1; }
-
-                // DOUBLE RATE
-                micro_usdc *= 2;
-
-                let now = ctx.accounts.clock.unix_timestamp;
-                let old = ctx.accounts.price_info.current_usdc_per_sol_price_updated;
-                ctx.accounts.price_info.old_usdc_per_sol_price = ctx.accounts.price_info.current_usdc_per_sol_price;
-                // no grace period if update price is delayed over MAX_ACCEPTABLE_UPDATE_INTERVAL.
-                ctx.accounts.price_info.old_usdc_per_sol_price_grace_period = if now - old <= MAX_ACCEPTABLE_UPDATE_INTERVAL { now + GRACE_PERIOD } else { 0 };
-                ctx.accounts.price_info.current_usdc_per_sol_price = micro_usdc as u64;
-                ctx.accounts.price_info.current_usdc_per_sol_price_updated = now;
-
-                msg!("now: {}, old: {}", now, old);
-                msg!("current_usdc_per_sol_price: {}", ctx.accounts.price_info.current_usdc_per_sol_price);
-                msg!("current_usdc_per_sol_price_updated: {}", ctx.accounts.price_info.current_usdc_per_sol_price_updated);
-                msg!("old_usdc_per_sol_price: {}", ctx.accounts.price_info.old_usdc_per_sol_price);
-                msg!("old_usdc_per_sol_price_grace_period: {}", ctx.accounts.price_info.old_usdc_per_sol_price_grace_period);
+    pub fn initialize_deposit_wsol(_ctx: Context<InitializeDepositWsol>) -> ProgramResult {
+        msg!("initialize deposit_wsol");
+        Ok(())
+    }
+
+    pub fn set_keep_wsol(ctx: Context<UpdateConfig>, keep_wsol: bool) -> ProgramResult {
+        ctx.accounts.config.keep_wsol = keep_wsol;
+        msg!("keep_wsol: {}", ctx.accounts.config.keep_wsol);
+
+        Ok(())
+    }
+
+    pub fn set_whirlpool_slippage(ctx: Context<UpdateConfig>, slippage_bps: u16) -> ProgramResult {
+        if slippage_bps > 10_000 {
+            return Err(ErrorCode::InvalidSwapSlippage.into());
+        }
+
+        ctx.accounts.config.whirlpool_slippage_bps = slippage_bps;
+        msg!("whirlpool_slippage_bps: {}", ctx.accounts.config.whirlpool_slippage_bps);
+
+        Ok(())
+    }
+
+    pub fn initialize_oracle_sources(ctx: Context<InitializeOracleSources>) -> ProgramResult {
+        msg!("initialize oracle_sources");
+        ctx.accounts.oracle_sources.sources = vec![OracleSource {
+            source_type: OracleSourceType::Pyth,
+            address: PYTH_SOL_USDC_PRICE,
+            weight: 1,
+            max_staleness_slots: DEFAULT_MAX_STALENESS_SLOTS,
+        }];
+        ctx.accounts.oracle_sources.min_valid_sources = 1;
+        ctx.accounts.oracle_sources.manual_price_max_deviation_bps = DEFAULT_MANUAL_PRICE_MAX_DEVIATION_BPS;
+        ctx.accounts.oracle_sources.manual_price_duration = DEFAULT_MANUAL_PRICE_DURATION;
+        ctx.accounts.oracle_sources.min_update_interval = 0;
+        ctx.accounts.oracle_sources.min_update_deviation_bps = 0;
+
+        Ok(())
+    }
+
+    pub fn set_oracle_sources(ctx: Context<UpdateOracleSources>, sources: Vec<OracleSource>, min_valid_sources: u8) -> ProgramResult {
+        // a disabled source (weight 0) never counts toward min_valid_sources.
+        let enabled_sources = sources.iter().filter(|source| source.weight > 0).count();
+        if sources.len() > MAX_ORACLE_SOURCES || min_valid_sources == 0 || min_valid_sources as usize > enabled_sources
+           || !is_pool_twap_outweighed(&sources) {
+            return Err(ErrorCode::InvalidOracleSources.into());
+        }
+
+        ctx.accounts.oracle_sources.sources = sources;
+        ctx.accounts.oracle_sources.min_valid_sources = min_valid_sources;
+        for source in ctx.accounts.oracle_sources.sources.iter() {
+            msg!("source: {:?} {} weight {} max_staleness_slots {}", source.source_type, source.address, source.weight, source.max_staleness_slots);
+        }
+        msg!("min_valid_sources: {}", ctx.accounts.oracle_sources.min_valid_sources);
+
+        Ok(())
+    }
+
+    pub fn initialize_posted_price(ctx: Context<InitializePostedPrice>, _index: u32) -> ProgramResult {
+        msg!("initialize posted_price");
+        ctx.accounts.posted_price.usdc_per_sol_price = 0;
+        ctx.accounts.posted_price.updated_slot = 0;
+
+        Ok(())
+    }
+
+    pub fn post_price(ctx: Context<PostPrice>, _index: u32, usdc_per_sol_price: u64) -> ProgramResult {
+        if usdc_per_sol_price == 0 {
+            return Err(ErrorCode::InvalidPrice.into());
+        }
+
+        ctx.accounts.posted_price.usdc_per_sol_price = usdc_per_sol_price;
+        ctx.accounts.posted_price.updated_slot = ctx.accounts.clock.slot;
+
+        msg!("usdc_per_sol_price: {}", ctx.accounts.posted_price.usdc_per_sol_price);
+        msg!("updated_slot: {}", ctx.accounts.posted_price.updated_slot);
+
+        Ok(())
+    }
+
+    pub fn initialize_pool_twap(ctx: Context<InitializePoolTwap>) -> ProgramResult {
+        msg!("initialize pool_twap");
+        let pool_twap = &mut ctx.accounts.pool_twap;
+        pool_twap.swap_backend = ctx.accounts.config.swap_backend;
+        pool_twap.swap_program = ctx.accounts.config.swap_program;
+        pool_twap.swap_pool = ctx.accounts.config.swap_pool;
+        pool_twap.usdc_mint = ctx.accounts.usdc_mint.key();
+        pool_twap.max_deviation_bps = 0;
+
+        msg!("swap_backend: {:?}", pool_twap.swap_backend);
+        msg!("swap_program: {}", pool_twap.swap_program);
+        msg!("swap_pool: {}", pool_twap.swap_pool);
+
+        Ok(())
+    }
+
+    pub fn set_twap_max_deviation(ctx: Context<UpdatePoolTwap>, max_deviation_bps: u16) -> ProgramResult {
+        ctx.accounts.pool_twap.max_deviation_bps = max_deviation_bps;
+        msg!("max_deviation_bps: {}", ctx.accounts.pool_twap.max_deviation_bps);
+
+        Ok(())
+    }
+
+    pub fn initialize_price_history(ctx: Context<InitializePriceHistory>) -> ProgramResult {
+        msg!("initialize price_history");
+        ctx.accounts.price_history.sequence = 0;
+
+        Ok(())
+    }
+
+    pub fn initialize_treasury_stats(ctx: Context<InitializeTreasuryStats>) -> ProgramResult {
+        msg!("initialize treasury_stats");
+        let stats = &mut ctx.accounts.treasury_stats;
+        stats.usdc_mint = ctx.accounts.usdc_mint.key();
+        stats.lamports_reimbursed = 0;
+        stats.usdc_received = 0;
+        stats.integrator_fees = 0;
+        stats.usdc_collected = 0;
+        stats.usdc_swapped = 0;
+        stats.sol_received = 0;
+        stats.lamports_distributed = 0;
+        stats.realized_spread = 0;
+
+        Ok(())
+    }
+
+    pub fn update_price<'info>(ctx: Context<'_, '_, '_, 'info, UpdatePrice<'info>>) -> ProgramResult {
+        // remaining accounts: price accounts of oracle_sources (same order),
+        //                     [swap_program, pool accounts of the swap backend] if pool_twap is initialized
+        let sources = &ctx.accounts.oracle_sources.sources;
+        if ctx.remaining_accounts.len() < sources.len() {
+            return Err(ErrorCode::InvalidOracleSources.into());
+        }
+        let (source_accounts, twap_accounts) = ctx.remaining_accounts.split_at(sources.len());
+
+        let pool_twap_initialized = !ctx.accounts.pool_twap.data_is_empty();
+        if pool_twap_initialized {
+            if twap_accounts.is_empty() {
+                return Err(ErrorCode::InvalidSwapAccounts.into());
+            }
+            update_pool_twap(&ctx.accounts.pool_twap, ctx.program_id, &twap_accounts[0], &twap_accounts[1..], &ctx.accounts.clock)?;
+        }
+        else if !twap_accounts.is_empty() {
+            return Err(ErrorCode::InvalidOracleSources.into());
+        }
+
+        let mut prices: Vec<(u64, u8)> = Vec::with_capacity(sources.len());
+        let mut oracle_prices = [0u64; MAX_ORACLE_SOURCES];
+        let mut oracle_confs = [0u64; MAX_ORACLE_SOURCES];
+        for (i, (source, account)) in sources.iter().zip(source_accounts.iter()).enumerate() {
+            if account.key() != source.address {
+                return Err(ErrorCode::InvalidOracleSources.into());
+            }
+            // a disabled source does not count toward min_valid_sources.
+            if source.weight == 0 {
+                continue;
+            }
+            if let Some(oracle_price) = source.read_price(account, &ctx.accounts.clock)? {
+                oracle_prices[i] = oracle_price.micro_usdc;
+                oracle_confs[i] = oracle_price.conf;
+                prices.push((oracle_price.micro_usdc, source.weight));
+            }
+        }
+
+        msg!("valid sources: {}, min_valid_sources: {}", prices.len(), ctx.accounts.oracle_sources.min_valid_sources);
+        if prices.len() < ctx.accounts.oracle_sources.min_valid_sources as usize {
+            return Err(ErrorCode::OraclePriceUnavailable.into());
+        }
+        let mut micro_usdc = weighted_median(&mut prices).ok_or(ErrorCode::OraclePriceUnavailable)?;
+        msg!("median price ...... {}", micro_usdc);
+
+        // sanity check with the TWAP of the swap pool.
+        if pool_twap_initialized {
+            let pool_twap = Account::<PoolTwap>::try_from(&ctx.accounts.pool_twap)?;
+            if let (true, Some(twap)) = (pool_twap.max_deviation_bps > 0, pool_twap.get_twap(ctx.accounts.clock.unix_timestamp)) {
+                let max_deviation = (twap as u128) * (pool_twap.max_deviation_bps as u128) / 10_000;
+                let deviation = (micro_usdc as i128 - twap as i128).unsigned_abs();
+                msg!("pool twap: {}, deviation: {}, max deviation: {}", twap, deviation, max_deviation);
+                if deviation > max_deviation {
+                    return Err(ErrorCode::OraclePriceDeviation.into());
+                }
             }
-            None => {
-                msg!("pyth price ........ unavailable");
-                msg!("pyth conf ......... unavailable");
-                return Err(ErrorCode::PythPriceUnavailable.into());
+        }
+
+        if micro_usdc == 0 {
+            return Err(ErrorCode::InvalidPrice.into());
+        }
+
+        // DOUBLE RATE
+        micro_usdc = micro_usdc.checked_mul(2).ok_or(ErrorCode::MathOverflow)?;
+
+        // the current price (and the grace period of the old price) is kept
+        // unless enough time has passed or the price has moved enough.
+        let now = ctx.accounts.clock.unix_timestamp;
+        let price_info = &ctx.accounts.price_info;
+        if price_info.current_usdc_per_sol_price != 0 && price_info.manual_price_expiry == 0 {
+            let oracle_sources = &ctx.accounts.oracle_sources;
+            let elapsed = now - price_info.current_usdc_per_sol_price_updated;
+            let deviation = (micro_usdc as i128 - price_info.current_usdc_per_sol_price as i128).unsigned_abs();
+            let min_deviation = (price_info.current_usdc_per_sol_price as u128) * (oracle_sources.min_update_deviation_bps as u128) / 10_000;
+            msg!("elapsed: {}, min_update_interval: {}", elapsed, oracle_sources.min_update_interval);
+            msg!("deviation: {}, min deviation: {}", deviation, min_deviation);
+            if elapsed < oracle_sources.min_update_interval
+               && (oracle_sources.min_update_deviation_bps == 0 || deviation < min_deviation) {
+                return Err(ErrorCode::PriceUpdateTooFrequent.into());
             }
         }
+
+        ctx.accounts.price_info.last_oracle_usdc_per_sol_price = micro_usdc;
+        ctx.accounts.price_info.rotate(micro_usdc, now, 0);
+
+        record_price_history(&ctx.accounts.price_history, ctx.program_id, PriceHistoryEntry {
+            sequence: 0,
+            usdc_per_sol_price: micro_usdc,
+            updated: now,
+            manual: false,
+            oracle_prices,
+            oracle_confs,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_price_manual(ctx: Context<SetPriceManual>, usdc_per_sol_price: u64) -> ProgramResult {
+        // DOUBLE RATE (same as update_price)
+        let micro_usdc = usdc_per_sol_price.checked_mul(2).ok_or(ErrorCode::MathOverflow)?;
+        if micro_usdc == 0 {
+            return Err(ErrorCode::InvalidPrice.into());
+        }
+
+        // bounded by the last oracle price.
+        let last_oracle = ctx.accounts.price_info.last_oracle_usdc_per_sol_price;
+        let max_deviation = (last_oracle as u128) * (ctx.accounts.oracle_sources.manual_price_max_deviation_bps as u128) / 10_000;
+        let deviation = (micro_usdc as i128 - last_oracle as i128).unsigned_abs();
+        msg!("manual price: {}, last oracle price: {}, max deviation: {}", micro_usdc, last_oracle, max_deviation);
+        if last_oracle == 0 || deviation > max_deviation {
+            return Err(ErrorCode::ManualPriceOutOfBounds.into());
+        }
+
+        let now = ctx.accounts.clock.unix_timestamp;
+        let expiry = now + ctx.accounts.oracle_sources.manual_price_duration;
+        ctx.accounts.price_info.rotate(micro_usdc, now, expiry);
+
+        record_price_history(&ctx.accounts.price_history, ctx.program_id, PriceHistoryEntry {
+            sequence: 0,
+            usdc_per_sol_price: micro_usdc,
+            updated: now,
+            manual: true,
+            ..Default::default()
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_manual_price_params(ctx: Context<UpdateOracleSources>, max_deviation_bps: u16, duration: i64) -> ProgramResult {
+        if max_deviation_bps > 10_000 || duration <= 0 {
+            return Err(ErrorCode::InvalidOracleSources.into());
+        }
+
+        ctx.accounts.oracle_sources.manual_price_max_deviation_bps = max_deviation_bps;
+        ctx.accounts.oracle_sources.manual_price_duration = duration;
+        msg!("manual_price_max_deviation_bps: {}", ctx.accounts.oracle_sources.manual_price_max_deviation_bps);
+        msg!("manual_price_duration: {}", ctx.accounts.oracle_sources.manual_price_duration);
+
+        Ok(())
+    }
+
+    pub fn set_update_rules(ctx: Context<UpdateOracleSources>, min_update_interval: i64, min_update_deviation_bps: u16) -> ProgramResult {
+        if !(0..=MAX_ACCEPTABLE_UPDATE_INTERVAL).contains(&min_update_interval) || min_update_deviation_bps > 10_000 {
+            return Err(ErrorCode::InvalidOracleSources.into());
+        }
+
+        ctx.accounts.oracle_sources.min_update_interval = min_update_interval;
+        ctx.accounts.oracle_sources.min_update_deviation_bps = min_update_deviation_bps;
+        msg!("min_update_interval: {}", ctx.accounts.oracle_sources.min_update_interval);
+        msg!("min_update_deviation_bps: {}", ctx.accounts.oracle_sources.min_update_deviation_bps);
+
+        Ok(())
+    }
+
+    // read-only, for simulateTransaction. returns NeutralizeQuote (borsh) as the return data.
+    pub fn quote_neutralize(ctx: Context<QuoteNeutralize>, lamports: u64) -> ProgramResult {
+        let quote = ctx.accounts.price_info.quote_neutralize(lamports, ctx.accounts.clock.unix_timestamp)?;
+        msg!("required_usdc: {}, usdc_per_sol_price: {}, expiry: {}", quote.required_usdc, quote.usdc_per_sol_price, quote.expiry);
+
+        solana_program::program::set_return_data(&quote.try_to_vec()?);
+
+        Ok(())
+    }
+
+    pub fn register_integrator(ctx: Context<RegisterIntegrator>, integrator: Pubkey, share_bps: u16) -> ProgramResult {
+        if share_bps > MAX_INTEGRATOR_SHARE_BPS {
+            return Err(ErrorCode::InvalidIntegratorAccounts.into());
+        }
+
+        msg!("initialize integrator_fee");
+        let integrator_fee = &mut ctx.accounts.integrator_fee;
+        integrator_fee.integrator = integrator;
+        integrator_fee.share_bps = share_bps;
+        integrator_fee.accrued = 0;
+        integrator_fee.claimed = 0;
+        msg!("integrator: {}, share_bps: {}", integrator_fee.integrator, integrator_fee.share_bps);
+
+        Ok(())
+    }
+
+    pub fn set_integrator_share(ctx: Context<UpdateIntegratorFee>, _integrator: Pubkey, share_bps: u16) -> ProgramResult {
+        if share_bps > MAX_INTEGRATOR_SHARE_BPS {
+            return Err(ErrorCode::InvalidIntegratorAccounts.into());
+        }
+
+        ctx.accounts.integrator_fee.share_bps = share_bps;
+        msg!("share_bps: {}", ctx.accounts.integrator_fee.share_bps);
+
+        Ok(())
+    }
+
+    pub fn claim_integrator_fee(ctx: Context<ClaimIntegratorFee>) -> ProgramResult {
+        let amount = ctx.accounts.integrator_fee_usdc.amount;
+        if amount == 0 {
+            msg!("no fee to claim");
+            return Ok(());
+        }
+
+        // find PDA and bump.
+        let integrator_key = ctx.accounts.integrator.key();
+        let init_seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"integrator_fee".as_ref(), integrator_key.as_ref(), &ctx.accounts.creator.key().to_bytes()];
+        let (_pda , bump) = Pubkey::find_program_address(&init_seeds, ctx.program_id);
+        let seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"integrator_fee".as_ref(), integrator_key.as_ref(), &ctx.accounts.creator.key().to_bytes(), &[bump]];
+
+        msg!("transfer usdc, integrator_fee_usdc to integrator_usdc, {} mUSDC", amount);
+        let transfer_usdc_ix = spl_token::instruction::transfer(
+            &ctx.accounts.token_program.key(),
+            &ctx.accounts.integrator_fee_usdc.key(),
+            &ctx.accounts.integrator_usdc.key(),
+            &ctx.accounts.integrator_fee.key(),
+            &[],
+            amount
+        )?;
+        solana_program::program::invoke_signed(
+            &transfer_usdc_ix,
+            &[
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.integrator_fee_usdc.to_account_info(),
+                ctx.accounts.integrator_fee.to_account_info(),
+                ctx.accounts.integrator_usdc.to_account_info(),
+            ],
+            &[seeds.as_ref()],
+        )?;
+
+        ctx.accounts.integrator_fee.claimed = ctx.accounts.integrator_fee.claimed.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+        msg!("accrued: {}, claimed: {}", ctx.accounts.integrator_fee.accrued, ctx.accounts.integrator_fee.claimed);
+
         Ok(())
     }
 
-    pub fn neutralize(ctx: Context<Neutralize>, index: u32, price: u64, pre_tx_lamports: u64) -> ProgramResult {
+    pub fn neutralize<'info>(ctx: Context<'_, '_, '_, 'info, Neutralize<'info>>, index: u32, max_price: u64, pre_tx_lamports: u64) -> ProgramResult {
+        // remaining accounts (optional): [integrator_fee, integrator_fee_usdc] and/or
+        // [fallback_pool_stats, fallback_pool_deposit_sol, fallback_pool_deposit_usdc] (used if the pool of index is depleted)
+        let (integrator_accounts, fallback_pool_accounts) = match ctx.remaining_accounts.len() {
+            0 | 2 => (ctx.remaining_accounts, &[][..]),
+            3 => (&[][..], ctx.remaining_accounts),
+            5 => ctx.remaining_accounts.split_at(2),
+            _ => return Err(ErrorCode::InvalidIntegratorAccounts.into()),
+        };
+
         // network fee is paid before processing.
 
         let current_lamports = ctx.accounts.user.lamports();
@@ -153,20 +639,59 @@ pub mod kurayashiki {
             return Err(ErrorCode::ExceedMaxAcceptableDiffLamports.into());
         }
 
-        // is valid price ?
+        // is valid price ? (and calculate USDC amount)
         let now = ctx.accounts.clock.unix_timestamp;
-        if now <= ctx.accounts.price_info.current_usdc_per_sol_price_updated + MAX_ACCEPTABLE_UPDATE_INTERVAL
-           && price == ctx.accounts.price_info.current_usdc_per_sol_price {
-               // valid price (current value)
-        }
-        else if now <= ctx.accounts.price_info.old_usdc_per_sol_price_grace_period
-           && price == ctx.accounts.price_info.old_usdc_per_sol_price {
-               // valid price (old value within grace period)
-        }
-        else {
-            return Err(ErrorCode::InvalidPrice.into());
+        let quote = ctx.accounts.price_info.quote_neutralize(diff_lamports, now)?;
+        let price = quote.usdc_per_sol_price;
+        let required_usdc = quote.required_usdc;
+        msg!("price: {}, max_price: {}, expiry: {}", price, max_price, quote.expiry);
+        // the current price is charged if the user accepts it.
+        if price > max_price {
+            return Err(ErrorCode::MaxPriceExceeded.into());
         }
 
+        // entry of price_history for the price (0 if price_history is not initialized or does not have it).
+        let price_history_sequence = if ctx.accounts.price_history.data_is_empty() { 0 } else {
+            let price_history = Account::<PriceHistory>::try_from(&ctx.accounts.price_history)?;
+            price_history.latest_entry(price).map(|entry| entry.sequence).unwrap_or(0)
+        };
+        msg!("price_history_sequence: {}", price_history_sequence);
+
+        // the pool must stay rent exempt after paying diff_lamports.
+        let min_balance = Rent::get()?.minimum_balance(0);
+        let can_pay = |pool_deposit_sol: &AccountInfo| pool_deposit_sol.lamports() >= diff_lamports + min_balance;
+        let (index, pool_deposit_sol, pool_deposit_usdc, pool_stats) = if can_pay(&ctx.accounts.pool_deposit_sol) {
+            (index, ctx.accounts.pool_deposit_sol.to_account_info(), ctx.accounts.pool_deposit_usdc.to_account_info(), ctx.accounts.pool_stats.clone())
+        } else {
+            msg!("pool {} is depleted, {} lamports", index, ctx.accounts.pool_deposit_sol.lamports());
+            let (fallback_index, fallback_pool_deposit_sol, fallback_pool_deposit_usdc, fallback_pool_stats) = match fallback_pool_accounts {
+                [] => return Err(ErrorCode::PoolDepleted.into()),
+                [pool_stats_info, pool_deposit_sol_info, pool_deposit_usdc_info] => {
+                    // PoolStats (owned by the program) identifies the fallback pool.
+                    let fallback_index = Account::<PoolStats>::try_from(pool_stats_info)?.index;
+                    let fallback_index_bytes = fallback_index.to_le_bytes();
+                    let stats_seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_stats".as_ref(), fallback_index_bytes.as_ref(), &ctx.accounts.creator.key().to_bytes()];
+                    let sol_seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"deposit_sol".as_ref(), fallback_index_bytes.as_ref(), &ctx.accounts.creator.key().to_bytes()];
+                    let (stats_pda, _) = Pubkey::find_program_address(&stats_seeds, ctx.program_id);
+                    let (sol_pda, _) = Pubkey::find_program_address(&sol_seeds, ctx.program_id);
+                    if fallback_index == index
+                       || pool_stats_info.key() != stats_pda
+                       || pool_deposit_sol_info.key() != sol_pda
+                       || pool_deposit_usdc_info.key() != get_associated_token_address(&sol_pda, &ctx.accounts.usdc_mint.key()) {
+                        return Err(ErrorCode::InvalidFallbackPoolAccounts.into());
+                    }
+                    (fallback_index, pool_deposit_sol_info.clone(), pool_deposit_usdc_info.clone(), pool_stats_info.clone())
+                },
+                _ => return Err(ErrorCode::InvalidFallbackPoolAccounts.into()),
+            };
+            if !can_pay(&fallback_pool_deposit_sol) {
+                msg!("fallback pool {} is depleted, {} lamports", fallback_index, fallback_pool_deposit_sol.lamports());
+                return Err(ErrorCode::PoolDepleted.into());
+            }
+            msg!("fallback to pool {}", fallback_index);
+            (fallback_index, fallback_pool_deposit_sol, fallback_pool_deposit_usdc, fallback_pool_stats)
+        };
+
         // find PDA and bump.
         let index_bytes = index.to_le_bytes();
         let init_seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"deposit_sol".as_ref(), index_bytes.as_ref(), &ctx.accounts.creator.key().to_bytes()];
@@ -174,20 +699,58 @@ pub mod kurayashiki {
         let seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"deposit_sol".as_ref(), index_bytes.as_ref(), &ctx.accounts.creator.key().to_bytes(), &[bump]];
         msg!("_pda: {}, bump: {}", _pda.to_string(), bump);
 
-        // calculate USDC amount.
-        let required_usdc: u64 = TryFrom::try_from(div_ceiling(
-            (ctx.accounts.price_info.current_usdc_per_sol_price as u128) * (diff_lamports as u128),
-            1_000_000_000 /* lamrports = 1 SOL */)).unwrap();
+        // share of the integrator.
+        let mut integrator = Pubkey::default();
+        let mut integrator_fee = 0u64;
+        match integrator_accounts {
+            [] => {},
+            [integrator_fee_info, integrator_fee_usdc] => {
+                let mut fee = Account::<IntegratorFee>::try_from(integrator_fee_info)?;
+                let fee_seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"integrator_fee".as_ref(), fee.integrator.as_ref(), &ctx.accounts.creator.key().to_bytes()];
+                let (fee_pda, _) = Pubkey::find_program_address(&fee_seeds, ctx.program_id);
+                if integrator_fee_info.key() != fee_pda
+                   || integrator_fee_usdc.key() != get_associated_token_address(&fee_pda, &ctx.accounts.usdc_mint.key()) {
+                    return Err(ErrorCode::InvalidIntegratorAccounts.into());
+                }
+
+                integrator = fee.integrator;
+                integrator_fee = ((required_usdc as u128) * (fee.share_bps as u128) / 10_000) as u64;
+                msg!("transfer usdc, user_usdc to integrator_fee_usdc, {} mUSDC", integrator_fee);
+                if integrator_fee > 0 {
+                    let transfer_fee_ix = spl_token::instruction::transfer(
+                        &ctx.accounts.token_program.key(),
+                        &ctx.accounts.user_usdc.key(),
+                        &integrator_fee_usdc.key(),
+                        &ctx.accounts.user.key(),
+                        &[],
+                        integrator_fee
+                    )?;
+                    solana_program::program::invoke(
+                        &transfer_fee_ix,
+                        &[
+                            ctx.accounts.token_program.to_account_info(),
+                            ctx.accounts.user_usdc.to_account_info(),
+                            ctx.accounts.user.to_account_info(),
+                            integrator_fee_usdc.clone(),
+                        ],
+                    )?;
+                }
+
+                fee.accrued = fee.accrued.checked_add(integrator_fee).ok_or(ErrorCode::MathOverflow)?;
+                fee.exit(ctx.program_id)?;
+            },
+            _ => return Err(ErrorCode::InvalidIntegratorAccounts.into()),
+        }
 
         // transfer USDC from user.
-        msg!("transfer usdc, user_usdc to pool_deposit_usdc, {} mUSDC", required_usdc);
+        msg!("transfer usdc, user_usdc to pool_deposit_usdc, {} mUSDC", required_usdc - integrator_fee);
         let transfer_usdc_ix = spl_token::instruction::transfer(
             &ctx.accounts.token_program.key(),
             &ctx.accounts.user_usdc.key(),
-            &ctx.accounts.pool_deposit_usdc.key(),
+            &pool_deposit_usdc.key(),
             &ctx.accounts.user.key(),
             &[],
-            required_usdc
+            required_usdc - integrator_fee
         )?;
         solana_program::program::invoke_signed(
             &transfer_usdc_ix,
@@ -195,7 +758,7 @@ pub mod kurayashiki {
                 ctx.accounts.token_program.to_account_info(),
                 ctx.accounts.user_usdc.to_account_info(),
                 ctx.accounts.user.to_account_info(),
-                ctx.accounts.pool_deposit_usdc.to_account_info(),
+                pool_deposit_usdc.clone(),
             ],
             &[],
         )?;
@@ -203,7 +766,7 @@ pub mod kurayashiki {
         // transfer SOL to user.
         msg!("transfer sol, pool_deposit_sol to user, {} lamports", diff_lamports);
         let transfer_sol_ix = solana_program::system_instruction::transfer(
-            &ctx.accounts.pool_deposit_sol.key(),
+            &pool_deposit_sol.key(),
             &ctx.accounts.user.key(),
             diff_lamports,
         );
@@ -211,23 +774,59 @@ pub mod kurayashiki {
             &transfer_sol_ix,
             &[
                 ctx.accounts.system_program.to_account_info(),
-                ctx.accounts.pool_deposit_sol.to_account_info(),
+                pool_deposit_sol.clone(),
                 ctx.accounts.user.to_account_info(),
             ],
             &[seeds.as_ref()],
         )?;
 
+        // TreasuryStats is not locked here, collect_from_pool adds the unreported totals of the pool to it.
+        let slot = ctx.accounts.clock.slot;
+        update_stats(&pool_stats, ctx.program_id, |stats: &mut PoolStats| {
+            stats.neutralize_count = stats.neutralize_count.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+            stats.lamports_paid = stats.lamports_paid.checked_add(diff_lamports).ok_or(ErrorCode::MathOverflow)?;
+            stats.usdc_received = stats.usdc_received.checked_add(required_usdc - integrator_fee).ok_or(ErrorCode::MathOverflow)?;
+            stats.integrator_fees = stats.integrator_fees.checked_add(integrator_fee).ok_or(ErrorCode::MathOverflow)?;
+            stats.last_used_slot = slot;
+            stats.unreported_lamports_paid = stats.unreported_lamports_paid.checked_add(diff_lamports).ok_or(ErrorCode::MathOverflow)?;
+            stats.unreported_usdc_received = stats.unreported_usdc_received.checked_add(required_usdc - integrator_fee).ok_or(ErrorCode::MathOverflow)?;
+            stats.unreported_integrator_fees = stats.unreported_integrator_fees.checked_add(integrator_fee).ok_or(ErrorCode::MathOverflow)?;
+            Ok(())
+        })?;
+
+        emit!(Neutralized {
+            user: ctx.accounts.user.key(),
+            index,
+            usdc_per_sol_price: price,
+            max_price,
+            diff_lamports,
+            required_usdc,
+            price_history_sequence,
+            integrator,
+            integrator_fee,
+        });
+
         Ok(())
     }
 
     pub fn collect_from_pool(ctx: Context<CollectFromPool>, index1: u32, index2: u32, index3: u32, index4: u32) -> ProgramResult {
         let pool_deposit_sols = [&ctx.accounts.pool_deposit_sol1, &ctx.accounts.pool_deposit_sol2, &ctx.accounts.pool_deposit_sol3, &ctx.accounts.pool_deposit_sol4];
         let pool_deposit_usdcs = [&ctx.accounts.pool_deposit_usdc1, &ctx.accounts.pool_deposit_usdc2, &ctx.accounts.pool_deposit_usdc3, &ctx.accounts.pool_deposit_usdc4];
+        let pool_stats_infos = [&ctx.accounts.pool_stats1, &ctx.accounts.pool_stats2, &ctx.accounts.pool_stats3, &ctx.accounts.pool_stats4];
         let indexes = [index1, index2, index3, index4];
+        // the unreported totals of neutralize are moved only if there is a TreasuryStats to move them to.
+        let report_stats = !ctx.accounts.treasury_stats.data_is_empty();
 
+        let mut collected_usdc = 0u64;
+        let (mut lamports_reimbursed, mut usdc_received, mut integrator_fees) = (0u64, 0u64, 0u64);
         for i in 0..4 {
             // find PDA and bump.
             let index = indexes[i];
+            // a page of pools may be padded by repeating an index.
+            if indexes[..i].contains(&index) {
+                msg!("duplicate index {}", index);
+                continue;
+            }
             let index_bytes = index.to_le_bytes();
             let init_seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"deposit_sol".as_ref(), index_bytes.as_ref(), &ctx.accounts.creator.key().to_bytes()];
             let (_pda , bump) = Pubkey::find_program_address(&init_seeds, ctx.program_id);
@@ -261,14 +860,38 @@ pub mod kurayashiki {
                     ],
                     &[&seeds],
                 )?;
+                collected_usdc = collected_usdc.checked_add(usdc_amount).ok_or(ErrorCode::MathOverflow)?;
+            }
+
+            if report_stats {
+                update_stats(pool_stats_infos[i], ctx.program_id, |stats: &mut PoolStats| {
+                    lamports_reimbursed = lamports_reimbursed.checked_add(stats.unreported_lamports_paid).ok_or(ErrorCode::MathOverflow)?;
+                    usdc_received = usdc_received.checked_add(stats.unreported_usdc_received).ok_or(ErrorCode::MathOverflow)?;
+                    integrator_fees = integrator_fees.checked_add(stats.unreported_integrator_fees).ok_or(ErrorCode::MathOverflow)?;
+                    stats.unreported_lamports_paid = 0;
+                    stats.unreported_usdc_received = 0;
+                    stats.unreported_integrator_fees = 0;
+                    Ok(())
+                })?;
             }
         }
 
+        update_stats(&ctx.accounts.treasury_stats, ctx.program_id, |stats: &mut TreasuryStats| {
+            stats.usdc_collected = stats.usdc_collected.checked_add(collected_usdc).ok_or(ErrorCode::MathOverflow)?;
+            stats.lamports_reimbursed = stats.lamports_reimbursed.checked_add(lamports_reimbursed).ok_or(ErrorCode::MathOverflow)?;
+            stats.usdc_received = stats.usdc_received.checked_add(usdc_received).ok_or(ErrorCode::MathOverflow)?;
+            stats.integrator_fees = stats.integrator_fees.checked_add(integrator_fees).ok_or(ErrorCode::MathOverflow)?;
+            Ok(())
+        })?;
+
         Ok(())
     }
 
     pub fn distribute_to_pool(ctx: Context<DistributeToPool>, index1: u32, index2: u32, index3: u32, index4: u32) -> ProgramResult {
         let pool_deposit_sols = [&ctx.accounts.pool_deposit_sol1, &ctx.accounts.pool_deposit_sol2, &ctx.accounts.pool_deposit_sol3, &ctx.accounts.pool_deposit_sol4];
+        let indexes = [index1, index2, index3, index4];
+        // a page of pools may be padded by repeating an index.
+        let is_duplicate = |i: usize| indexes[..i].contains(&indexes[i]);
         
         // find PDA and bump.
         let init_seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"deposit_sol".as_ref(), &ctx.accounts.creator.key().to_bytes()];
@@ -277,28 +900,87 @@ pub mod kurayashiki {
         msg!("_pda: {}, bump: {}", _pda.to_string(), bump);
 
         // calculate allocatable & refill lamports.
-        assert!(ctx.accounts.deposit_sol.lamports() >= DEPOSIT_SOL_MIN_BALANCE);
-        let allocatable_lamports = ctx.accounts.deposit_sol.lamports() - DEPOSIT_SOL_MIN_BALANCE;
+        // nothing is allocatable while deposit_sol is below DEPOSIT_SOL_MIN_BALANCE (reported as a shortfall).
+        let allocatable_lamports = ctx.accounts.deposit_sol.lamports().saturating_sub(DEPOSIT_SOL_MIN_BALANCE);
         msg!("allocatable_lamports: {}", allocatable_lamports);
 
-        let mut refill_lamports = 0u64;
+        // a pool is topped up to its target only below its low-water mark.
+        let pool_targets = [&ctx.accounts.pool_target1, &ctx.accounts.pool_target2, &ctx.accounts.pool_target3, &ctx.accounts.pool_target4];
+        let mut pool_refill_lamports = [0u64; 4];
         for i in 0..4 {
-            if pool_deposit_sols[i].lamports() < POOL_DEPOSIT_SOL_MAX_BALANCE {
-                refill_lamports += POOL_DEPOSIT_SOL_MAX_BALANCE - pool_deposit_sols[i].lamports();
+            let (target_lamports, low_water_lamports) = read_pool_target(pool_targets[i])?;
+            let current_lamports = pool_deposit_sols[i].lamports();
+            if !is_duplicate(i) && current_lamports < low_water_lamports {
+                pool_refill_lamports[i] = target_lamports - current_lamports;
             }
         }
+        let refill_lamports: u64 = pool_refill_lamports.iter().sum();
         msg!("refill_lamports: {}", refill_lamports);
-        assert!(allocatable_lamports >= refill_lamports);
+
+        // unwrap WSOL kept by convert_to_sol only when lamports are short.
+        if allocatable_lamports < refill_lamports && !ctx.accounts.deposit_wsol.data_is_empty() {
+            let deposit_wsol = Account::<TokenAccount>::try_from(&ctx.accounts.deposit_wsol)?;
+            let unwrap_amount = std::cmp::min(refill_lamports - allocatable_lamports, deposit_wsol.amount);
+            if unwrap_amount > 0 {
+                let init_seeds_wsol = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"temporary_deposit_wsol".as_ref(), &ctx.accounts.creator.key().to_bytes()];
+                let (_pda_wsol , bump_wsol) = Pubkey::find_program_address(&init_seeds_wsol, ctx.program_id);
+                let seeds_wsol = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"temporary_deposit_wsol".as_ref(), &ctx.accounts.creator.key().to_bytes(), &[bump_wsol]];
+
+                create_temporary_deposit_wsol(
+                    &ctx.accounts.deposit_sol,
+                    &ctx.accounts.temporary_deposit_wsol,
+                    &ctx.accounts.wsol_mint,
+                    &ctx.accounts.system_program,
+                    &ctx.accounts.token_program,
+                    &ctx.accounts.rent,
+                    &[seeds.as_ref(), seeds_wsol.as_ref()],
+                )?;
+
+                msg!("unwrap wsol, deposit_wsol to deposit_sol, {} lamports", unwrap_amount);
+                let transfer_ix = spl_token::instruction::transfer(
+                    &ctx.accounts.token_program.key(),
+                    &ctx.accounts.deposit_wsol.key(),
+                    &ctx.accounts.temporary_deposit_wsol.key(),
+                    &ctx.accounts.deposit_sol.key(),
+                    &[],
+                    unwrap_amount,
+                )?;
+                solana_program::program::invoke_signed(
+                    &transfer_ix,
+                    &[
+                        ctx.accounts.token_program.to_account_info(),
+                        ctx.accounts.deposit_wsol.to_account_info(),
+                        ctx.accounts.temporary_deposit_wsol.to_account_info(),
+                        ctx.accounts.deposit_sol.to_account_info(),
+                    ],
+                    &[seeds.as_ref()],
+                )?;
+
+                close_temporary_deposit_wsol(
+                    &ctx.accounts.deposit_sol,
+                    &ctx.accounts.temporary_deposit_wsol,
+                    &ctx.accounts.token_program,
+                    &[seeds.as_ref()],
+                )?;
+            }
+        }
+
+        // refill partially if the treasury is short.
+        let allocatable_lamports = ctx.accounts.deposit_sol.lamports().saturating_sub(DEPOSIT_SOL_MIN_BALANCE);
+        let current_lamports = [pool_deposit_sols[0].lamports(), pool_deposit_sols[1].lamports(), pool_deposit_sols[2].lamports(), pool_deposit_sols[3].lamports()];
+        let pool_refill_lamports = allocate_refill(current_lamports, pool_refill_lamports, allocatable_lamports, ctx.accounts.rent.minimum_balance(0));
 
         // refill SOL.
+        let mut distributed_lamports = 0u64;
         for i in 0..4 {
-            let current_lamports = pool_deposit_sols[i].lamports();
-
-            if current_lamports >= POOL_DEPOSIT_SOL_MAX_BALANCE {
+            if is_duplicate(i) {
+                msg!("duplicate index {}", indexes[i]);
+            }
+            else if pool_refill_lamports[i] == 0 {
                 msg!("no transfer");
             }
             else {
-                let sol_amount = POOL_DEPOSIT_SOL_MAX_BALANCE - current_lamports;
+                let sol_amount = pool_refill_lamports[i];
 
                 msg!("transfer sol, deposit_sol to pool_deposit_sol, {} lamports", sol_amount);
                 let ix = solana_program::system_instruction::transfer(
@@ -315,25 +997,128 @@ pub mod kurayashiki {
                     ],
                     &[seeds.as_ref()],
                 )?;
+                distributed_lamports += sol_amount;
             }
         }
 
+        if distributed_lamports < refill_lamports {
+            msg!("shortfall_lamports: {}", refill_lamports - distributed_lamports);
+            emit!(PoolRefillShortfall {
+                indexes,
+                refill_lamports,
+                distributed_lamports,
+                shortfall_lamports: refill_lamports - distributed_lamports,
+            });
+        }
+
+        update_stats(&ctx.accounts.treasury_stats, ctx.program_id, |stats: &mut TreasuryStats| {
+            stats.lamports_distributed = stats.lamports_distributed.checked_add(distributed_lamports).ok_or(ErrorCode::MathOverflow)?;
+            Ok(())
+        })?;
+
         Ok(())
     }
 
-    pub fn convert_to_sol(ctx: Context<ConvertToSol>) -> ProgramResult {
-        let input_usdc = ctx.accounts.deposit_usdc.amount;
-        let output_wsol = get_expected_output_amount(ctx.accounts.orca_pool_usdc.amount, ctx.accounts.orca_pool_wsol.amount, input_usdc);
-        if input_usdc == 0 || output_wsol == 0 {
-            msg!("no swap operation");
-            return Ok(());
+    // each hop must output at least max(expected output, hop.minimum_output_amount). the expected output of Whirlpool
+    // is of the spot price less config.whirlpool_slippage_bps (ticks are not traversed on-chain), so a swap that crosses
+    // ticks beyond the slippage fails even if the caller's minimum is lower. the caller can only raise the minimum
+    // (anyone can call convert_to_sol), and the creator widens the slippage with set_whirlpool_slippage.
+    pub fn convert_to_sol<'info>(ctx: Context<'_, '_, '_, 'info, ConvertToSol<'info>>, route: Vec<SwapHop>, minimum_output_amount: u64) -> ProgramResult {
+        let config = &ctx.accounts.config;
+        // empty route: swap directly with the configured pool.
+        let route = if route.is_empty() {
+            vec![SwapHop { swap_backend: config.swap_backend, minimum_output_amount: 0 }]
+        } else {
+            route
+        };
+        if route.len() > MAX_SWAP_HOPS {
+            return Err(ErrorCode::InvalidSwapRoute.into());
         }
 
-        msg!("swap usdc to wsol, {} mUSDC to expected {} lamports", input_usdc, output_wsol);
+        let keep_wsol = config.keep_wsol;
+        let deposit_sol = ctx.accounts.deposit_sol.to_account_info();
+        let deposit_token = ctx.accounts.deposit_token.to_account_info();
+        // keep_wsol: the last hop swaps into deposit_wsol and the output is kept as WSOL.
+        let destination_wsol = if keep_wsol { ctx.accounts.deposit_wsol.to_account_info() } else { ctx.accounts.temporary_deposit_wsol.to_account_info() };
+        let token_program = ctx.accounts.token_program.to_account_info();
+
+        // remaining accounts of each hop: [swap_program, destination (except the last hop), pool accounts of the swap backend]
+        // the last hop swaps into temporary_deposit_wsol (or deposit_wsol if keep_wsol).
+        let mut hops: Vec<SwapAccounts> = Vec::with_capacity(route.len());
+        let mut remaining_accounts = ctx.remaining_accounts;
+        let mut source = &deposit_token;
+        let mut source_mint = ctx.accounts.deposit_token.mint;
+        for (i, hop) in route.iter().enumerate() {
+            let is_last_hop = i == route.len() - 1;
+            let hop_accounts_len = 1 + if is_last_hop { 0 } else { 1 } + hop.swap_backend.pool_accounts_len();
+            if remaining_accounts.len() < hop_accounts_len {
+                return Err(ErrorCode::InvalidSwapRoute.into());
+            }
+            let (hop_accounts, rest) = remaining_accounts.split_at(hop_accounts_len);
+            remaining_accounts = rest;
+
+            let swap_program = &hop_accounts[0];
+            let (destination, destination_mint, pool_accounts) = if is_last_hop {
+                (&destination_wsol, WSOL_MINT, &hop_accounts[1..])
+            } else {
+                let destination = Account::<TokenAccount>::try_from(&hop_accounts[1])?;
+                if destination.owner != deposit_sol.key() {
+                    return Err(ErrorCode::InvalidSwapRoute.into());
+                }
+                (&hop_accounts[1], destination.mint, &hop_accounts[2..])
+            };
+
+            let swap_pool = SwapPool {
+                swap_backend: hop.swap_backend,
+                swap_program: swap_program.key(),
+                swap_pool: pool_accounts[0].key(),
+            };
+            if !config.is_allowed_pool(&swap_pool) {
+                return Err(ErrorCode::InvalidSwapAccounts.into());
+            }
 
-        // find PDA and bump.
-        let init_seeds_sol = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"deposit_sol".as_ref(), &ctx.accounts.creator.key().to_bytes()];
-        let (_pda_sol , bump_sol) = Pubkey::find_program_address(&init_seeds_sol, ctx.program_id);
+            hops.push(SwapAccounts {
+                swap_program,
+                authority: &deposit_sol,
+                source,
+                destination,
+                source_mint,
+                destination_mint,
+                token_program: &token_program,
+                pool_accounts,
+            });
+            source = destination;
+            source_mint = destination_mint;
+        }
+        if !remaining_accounts.is_empty() {
+            return Err(ErrorCode::InvalidSwapRoute.into());
+        }
+
+        // update pool_twap with the spot price before the swap (if the route uses the pool).
+        if !ctx.accounts.pool_twap.data_is_empty() {
+            let pool_twap = Account::<PoolTwap>::try_from(&ctx.accounts.pool_twap)?;
+            let twap_pool = SwapPool { swap_backend: pool_twap.swap_backend, swap_program: pool_twap.swap_program, swap_pool: pool_twap.swap_pool };
+            let twap_hop = route.iter().zip(hops.iter()).find(|(hop, swap_accounts)| {
+                let swap_pool = SwapPool { swap_backend: hop.swap_backend, swap_program: swap_accounts.swap_program.key(), swap_pool: swap_accounts.pool_accounts[0].key() };
+                swap_pool == twap_pool && swap_accounts.source_mint == pool_twap.usdc_mint && swap_accounts.destination_mint == WSOL_MINT
+            });
+            if let Some((_, swap_accounts)) = twap_hop {
+                update_pool_twap(&ctx.accounts.pool_twap, ctx.program_id, swap_accounts.swap_program, swap_accounts.pool_accounts, &ctx.accounts.clock)?;
+            }
+        }
+
+        let input_amount = ctx.accounts.deposit_token.amount;
+        let expected_output_amount = if input_amount == 0 { 0 } else { route[0].swap_backend.get_expected_output_amount(&hops[0], input_amount, config.whirlpool_slippage_bps)? };
+        if input_amount == 0 || expected_output_amount == 0 {
+            msg!("no swap operation");
+            return Ok(());
+        }
+
+        msg!("swap {} {} to wsol through {} hop(s)", input_amount, ctx.accounts.deposit_token.mint, route.len());
+
+        // find PDA and bump.
+        let init_seeds_sol = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"deposit_sol".as_ref(), &ctx.accounts.creator.key().to_bytes()];
+        let (_pda_sol , bump_sol) = Pubkey::find_program_address(&init_seeds_sol, ctx.program_id);
         let seeds_sol = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"deposit_sol".as_ref(), &ctx.accounts.creator.key().to_bytes(), &[bump_sol]];
         msg!("_pda_sol: {}, bump_sol: {}", _pda_sol.to_string(), bump_sol);
 
@@ -343,98 +1128,54 @@ pub mod kurayashiki {
         msg!("_pda_wsol: {}, bump_wsol: {}", _pda_wsol.to_string(), bump_wsol);
 
         // create temporary WSOL account.
-        msg!("create account");
-        let create_wsol_account_ix = solana_program::system_instruction::create_account(
-            &ctx.accounts.deposit_sol.key(),
-            &ctx.accounts.temporary_deposit_wsol.key(),
-            ctx.accounts.rent.minimum_balance(165),
-            165,
-            &ctx.accounts.token_program.key());
-        solana_program::program::invoke_signed(
-            &create_wsol_account_ix,
-            &[
-                ctx.accounts.system_program.to_account_info(),
-                ctx.accounts.deposit_sol.to_account_info(),
-                ctx.accounts.temporary_deposit_wsol.to_account_info(),
-                ctx.accounts.token_program.to_account_info(),
-            ],
-            &[seeds_sol.as_ref(), seeds_wsol.as_ref()],
-        )?;
-        msg!("initialize account");
-        let init_account_ix = spl_token::instruction::initialize_account(
-            &ctx.accounts.token_program.key(),
-            &ctx.accounts.temporary_deposit_wsol.key(),
-            &ctx.accounts.wsol_mint.key(),
-            &ctx.accounts.deposit_sol.key())?;
-        solana_program::program::invoke(
-            &init_account_ix,
-            &[
-                ctx.accounts.token_program.to_account_info(),
-                ctx.accounts.temporary_deposit_wsol.to_account_info(),
-                ctx.accounts.wsol_mint.to_account_info(),
-                ctx.accounts.deposit_sol.to_account_info(),
-                ctx.accounts.rent.to_account_info(),
-            ],
-        )?;
+        if !keep_wsol {
+            create_temporary_deposit_wsol(
+                &ctx.accounts.deposit_sol,
+                &ctx.accounts.temporary_deposit_wsol,
+                &ctx.accounts.wsol_mint,
+                &ctx.accounts.system_program,
+                &ctx.accounts.token_program,
+                &ctx.accounts.rent,
+                &[seeds_sol.as_ref(), seeds_wsol.as_ref()],
+            )?;
+        }
 
-        // invoke ORCA swap.
-        msg!("invoke swap");
-        let swap_ix = Instruction {
-            program_id: ctx.accounts.orca_swap_program.key(),
-            accounts: vec![
-                AccountMeta::new_readonly(ctx.accounts.orca_address.key(), false),
-                AccountMeta::new_readonly(ctx.accounts.orca_authority.key(), false),
-                AccountMeta::new_readonly(ctx.accounts.deposit_sol.key(), true),
-                AccountMeta::new(ctx.accounts.deposit_usdc.key(), false),
-                AccountMeta::new(ctx.accounts.orca_pool_usdc.key(), false),
-                AccountMeta::new(ctx.accounts.orca_pool_wsol.key(), false),
-                AccountMeta::new(ctx.accounts.temporary_deposit_wsol.key(), false),
-                AccountMeta::new(ctx.accounts.orca_pool_token_mint.key(), false),
-                AccountMeta::new(ctx.accounts.orca_fee_account.key(), false),
-                AccountMeta::new_readonly(ctx.accounts.token_program.key(), false),
-            ],
-            data: OrcaSwapInstructionData {
-                instruction: 1, // Swap instruction
-                input_amount: input_usdc,
-                minimum_output_amount: output_wsol,
-            }.try_to_vec()?
-        };
-        solana_program::program::invoke_signed(
-            &swap_ix,
-            &[
-                ctx.accounts.orca_swap_program.to_account_info(),
-                ctx.accounts.orca_address.to_account_info(),
-                ctx.accounts.orca_authority.to_account_info(),
-                ctx.accounts.deposit_sol.to_account_info(),
-                ctx.accounts.deposit_usdc.to_account_info(),
-                ctx.accounts.orca_pool_usdc.to_account_info(),
-                ctx.accounts.orca_pool_wsol.to_account_info(),
-                ctx.accounts.temporary_deposit_wsol.to_account_info(),
-                ctx.accounts.orca_pool_token_mint.to_account_info(),
-                ctx.accounts.orca_fee_account.to_account_info(),
-                ctx.accounts.token_program.to_account_info(),
-            ],
-            &[seeds_sol.as_ref()],
-        )?;
+        // invoke swap of each hop. the output of a hop is the input of the next hop.
+        let mut amount = input_amount;
+        for (hop, swap_accounts) in route.iter().zip(hops.iter()) {
+            let expected_output_amount = hop.swap_backend.get_expected_output_amount(swap_accounts, amount, config.whirlpool_slippage_bps)?;
+            let hop_minimum_output_amount = std::cmp::max(expected_output_amount, hop.minimum_output_amount);
+            msg!("invoke swap ({:?}), {} {} to expected {} {}", hop.swap_backend, amount, swap_accounts.source_mint, hop_minimum_output_amount, swap_accounts.destination_mint);
+
+            let pre_balance = Account::<TokenAccount>::try_from(swap_accounts.destination)?.amount;
+            hop.swap_backend.swap(swap_accounts, amount, hop_minimum_output_amount, &[seeds_sol.as_ref()])?;
+            let post_balance = Account::<TokenAccount>::try_from(swap_accounts.destination)?.amount;
+
+            let output_amount = post_balance.checked_sub(pre_balance).ok_or(ErrorCode::MathOverflow)?;
+            msg!("output {}", output_amount);
+            if output_amount < hop_minimum_output_amount {
+                return Err(ErrorCode::SwapOutputBelowMinimum.into());
+            }
+            amount = output_amount;
+        }
+        if amount < minimum_output_amount {
+            return Err(ErrorCode::SwapOutputBelowMinimum.into());
+        }
+
+        let input_mint = ctx.accounts.deposit_token.mint;
+        update_stats(&ctx.accounts.treasury_stats, ctx.program_id, |stats: &mut TreasuryStats| {
+            stats.record_swap(input_mint, input_amount, amount)
+        })?;
 
         // close temporary WSOL account.
-        msg!("close account");
-        let close_account_ix = spl_token::instruction::close_account(
-            &ctx.accounts.token_program.key(),
-            &ctx.accounts.temporary_deposit_wsol.key(),
-            &ctx.accounts.deposit_sol.key(),
-            &ctx.accounts.deposit_sol.key(),
-            &[],
-        )?;
-        solana_program::program::invoke_signed(
-            &close_account_ix,
-            &[
-                ctx.accounts.token_program.to_account_info(),
-                ctx.accounts.temporary_deposit_wsol.to_account_info(),
-                ctx.accounts.deposit_sol.to_account_info(),
-            ],
-            &[&seeds_sol]
-        )?;
+        if !keep_wsol {
+            close_temporary_deposit_wsol(
+                &ctx.accounts.deposit_sol,
+                &ctx.accounts.temporary_deposit_wsol,
+                &ctx.accounts.token_program,
+                &[seeds_sol.as_ref()],
+            )?;
+        }
 
         Ok(())
     }
@@ -445,7 +1186,7 @@ pub struct Initialize<'info> {
     #[account(mut)]
     pub creator: Signer<'info>,
 
-    #[account(init, payer = creator, space = 32 + 8, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"price_info".as_ref(), &creator.key().to_bytes()], bump)]
+    #[account(init, payer = creator, space = PRICE_INFO_SPACE, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"price_info".as_ref(), &creator.key().to_bytes()], bump)]
     pub price_info: Box<Account<'info, PriceInfo>>,
     #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"deposit_sol".as_ref(), &creator.key().to_bytes()], bump)]
     pub deposit_sol: SystemAccount<'info>,
@@ -460,14 +1201,173 @@ pub struct Initialize<'info> {
     pub rent: Sysvar<'info, Rent>,
 }
 
+#[derive(Accounts)]
+pub struct MigratePriceInfo<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    // PriceInfo of PRICE_INFO_V1_SPACE (deserialized by migrate_price_info)
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"price_info".as_ref(), &creator.key().to_bytes()], bump)]
+    pub price_info: AccountInfo<'info>,
+
+    // aux accounts
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    #[account(init, payer = creator, space = 8 + 1 + 32 + 32 + 4 + (1 + 32 + 32) * MAX_ROUTE_POOLS + 1 + 2, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"config".as_ref(), &creator.key().to_bytes()], bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    // aux accounts
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeDepositWsol<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    #[account(seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"deposit_sol".as_ref(), &creator.key().to_bytes()], bump)]
+    pub deposit_sol: SystemAccount<'info>,
+    #[account(init, payer = creator, associated_token::mint = wsol_mint, associated_token::authority = deposit_sol)]
+    pub deposit_wsol: Box<Account<'info, TokenAccount>>,
+
+    // aux accounts
+    #[account(address = WSOL_MINT)]
+    pub wsol_mint: Box<Account<'info, Mint>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    pub creator: Signer<'info>,
+
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"config".as_ref(), &creator.key().to_bytes()], bump)]
+    pub config: Box<Account<'info, Config>>,
+}
+
 #[derive(Accounts)]
 pub struct UpdatePrice<'info> {
     pub creator: SystemAccount<'info>,
 
     #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"price_info".as_ref(), &creator.key().to_bytes()], bump)]
     pub price_info: Box<Account<'info, PriceInfo>>,
-    #[account(address = PYTH_SOL_USDC_PRICE)]
-    pub pyth_sol_usdc_price: AccountInfo<'info>,
+    #[account(seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"oracle_sources".as_ref(), &creator.key().to_bytes()], bump)]
+    pub oracle_sources: Box<Account<'info, OracleSources>>,
+    // optional (updated if initialized)
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_twap".as_ref(), &creator.key().to_bytes()], bump)]
+    pub pool_twap: AccountInfo<'info>,
+
+    // optional (recorded if initialized)
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"price_history".as_ref(), &creator.key().to_bytes()], bump)]
+    pub price_history: AccountInfo<'info>,
+
+    // price accounts of oracle_sources (and swap accounts for pool_twap) are passed as remaining accounts
+
+    // aux accounts
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct InitializePoolTwap<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    #[account(seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"config".as_ref(), &creator.key().to_bytes()], bump)]
+    pub config: Box<Account<'info, Config>>,
+    #[account(init, payer = creator, space = 8 + 1 + 32 + 32 + 32 + 2 + 8 + 8 + 8 + 16 + 1 + (8 + 16) * TWAP_OBSERVATIONS, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_twap".as_ref(), &creator.key().to_bytes()], bump)]
+    pub pool_twap: Box<Account<'info, PoolTwap>>,
+
+    // aux accounts
+    pub usdc_mint: Box<Account<'info, Mint>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePoolTwap<'info> {
+    pub creator: Signer<'info>,
+
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_twap".as_ref(), &creator.key().to_bytes()], bump)]
+    pub pool_twap: Box<Account<'info, PoolTwap>>,
+}
+
+#[derive(Accounts)]
+pub struct SetPriceManual<'info> {
+    pub creator: Signer<'info>,
+
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"price_info".as_ref(), &creator.key().to_bytes()], bump)]
+    pub price_info: Box<Account<'info, PriceInfo>>,
+    #[account(seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"oracle_sources".as_ref(), &creator.key().to_bytes()], bump)]
+    pub oracle_sources: Box<Account<'info, OracleSources>>,
+    // optional (recorded if initialized)
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"price_history".as_ref(), &creator.key().to_bytes()], bump)]
+    pub price_history: AccountInfo<'info>,
+
+    // aux accounts
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct InitializePriceHistory<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    #[account(init, payer = creator, space = 8 + 8 + (8 + 8 + 8 + 1 + 8 * MAX_ORACLE_SOURCES + 8 * MAX_ORACLE_SOURCES) * PRICE_HISTORY_ENTRIES, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"price_history".as_ref(), &creator.key().to_bytes()], bump)]
+    pub price_history: Box<Account<'info, PriceHistory>>,
+
+    // aux accounts
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeOracleSources<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    #[account(init, payer = creator, space = 8 + 4 + (1 + 32 + 1 + 8) * MAX_ORACLE_SOURCES + 1 + 2 + 8 + 8 + 2, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"oracle_sources".as_ref(), &creator.key().to_bytes()], bump)]
+    pub oracle_sources: Box<Account<'info, OracleSources>>,
+
+    // aux accounts
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateOracleSources<'info> {
+    pub creator: Signer<'info>,
+
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"oracle_sources".as_ref(), &creator.key().to_bytes()], bump)]
+    pub oracle_sources: Box<Account<'info, OracleSources>>,
+}
+
+#[derive(Accounts)]
+#[instruction(index: u32)]
+pub struct InitializePostedPrice<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    #[account(init, payer = creator, space = 8 + 8 + 8, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"posted_price".as_ref(), index.to_le_bytes().as_ref(), &creator.key().to_bytes()], bump)]
+    pub posted_price: Box<Account<'info, PostedPrice>>,
+
+    // aux accounts
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(index: u32)]
+pub struct PostPrice<'info> {
+    pub creator: Signer<'info>,
+
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"posted_price".as_ref(), index.to_le_bytes().as_ref(), &creator.key().to_bytes()], bump)]
+    pub posted_price: Box<Account<'info, PostedPrice>>,
+
+    // aux accounts
     pub clock: Sysvar<'info, Clock>,
 }
 
@@ -481,6 +1381,8 @@ pub struct CreatePool<'info> {
     pub pool_deposit_sol: SystemAccount<'info>,
     #[account(init, payer = creator, associated_token::mint = usdc_mint, associated_token::authority = pool_deposit_sol)]
     pub pool_deposit_usdc: Box<Account<'info, TokenAccount>>,
+    #[account(init, payer = creator, space = 8 + 4 + 8 * 8, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_stats".as_ref(), index.to_le_bytes().as_ref(), &creator.key().to_bytes()], bump)]
+    pub pool_stats: Box<Account<'info, PoolStats>>,
 
     // aux accounts
     pub usdc_mint: Box<Account<'info, Mint>>,
@@ -490,6 +1392,69 @@ pub struct CreatePool<'info> {
     pub rent: Sysvar<'info, Rent>,
 }
 
+#[derive(Accounts)]
+#[instruction(index: u32)]
+pub struct InitializePoolStats<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    #[account(init, payer = creator, space = 8 + 4 + 8 * 8, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_stats".as_ref(), index.to_le_bytes().as_ref(), &creator.key().to_bytes()], bump)]
+    pub pool_stats: Box<Account<'info, PoolStats>>,
+
+    // aux accounts
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializePoolCursor<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    #[account(init, payer = creator, space = 8 + 4 + 4, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_cursor".as_ref(), &creator.key().to_bytes()], bump)]
+    pub pool_cursor: Box<Account<'info, PoolCursor>>,
+
+    // aux accounts
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePoolCursor<'info> {
+    pub creator: Signer<'info>,
+
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_cursor".as_ref(), &creator.key().to_bytes()], bump)]
+    pub pool_cursor: Box<Account<'info, PoolCursor>>,
+}
+
+#[derive(Accounts)]
+pub struct AdvancePoolCursor<'info> {
+    pub creator: SystemAccount<'info>,
+
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_cursor".as_ref(), &creator.key().to_bytes()], bump)]
+    pub pool_cursor: Box<Account<'info, PoolCursor>>,
+}
+
+#[derive(Accounts)]
+#[instruction(index: u32)]
+pub struct InitializePoolTarget<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    #[account(init, payer = creator, space = 8 + 4 + 8 + 8, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_target".as_ref(), index.to_le_bytes().as_ref(), &creator.key().to_bytes()], bump)]
+    pub pool_target: Box<Account<'info, PoolTarget>>,
+
+    // aux accounts
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(index: u32)]
+pub struct UpdatePoolTarget<'info> {
+    pub creator: Signer<'info>,
+
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_target".as_ref(), index.to_le_bytes().as_ref(), &creator.key().to_bytes()], bump)]
+    pub pool_target: Box<Account<'info, PoolTarget>>,
+}
+
 #[derive(Accounts)]
 #[instruction(index1: u32, index2: u32, index3: u32, index4: u32)]
 pub struct CollectFromPool<'info> {
@@ -517,6 +1482,19 @@ pub struct CollectFromPool<'info> {
     pub pool_deposit_sol4: SystemAccount<'info>,
     #[account(mut, associated_token::mint = usdc_mint, associated_token::authority = pool_deposit_sol4)]
     pub pool_deposit_usdc4: Box<Account<'info, TokenAccount>>,
+    // optional (the unreported totals of neutralize are moved to treasury_stats if both are initialized)
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_stats".as_ref(), index1.to_le_bytes().as_ref(), &creator.key().to_bytes()], bump)]
+    pub pool_stats1: AccountInfo<'info>,
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_stats".as_ref(), index2.to_le_bytes().as_ref(), &creator.key().to_bytes()], bump)]
+    pub pool_stats2: AccountInfo<'info>,
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_stats".as_ref(), index3.to_le_bytes().as_ref(), &creator.key().to_bytes()], bump)]
+    pub pool_stats3: AccountInfo<'info>,
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_stats".as_ref(), index4.to_le_bytes().as_ref(), &creator.key().to_bytes()], bump)]
+    pub pool_stats4: AccountInfo<'info>,
+
+    // optional (updated if initialized)
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"treasury_stats".as_ref(), &creator.key().to_bytes()], bump)]
+    pub treasury_stats: AccountInfo<'info>,
 
     // aux accounts
     pub usdc_mint: Box<Account<'info, Mint>>,
@@ -540,9 +1518,31 @@ pub struct DistributeToPool<'info> {
     pub pool_deposit_sol3: SystemAccount<'info>,
     #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"deposit_sol".as_ref(), index4.to_le_bytes().as_ref(), &creator.key().to_bytes()], bump)]
     pub pool_deposit_sol4: SystemAccount<'info>,
+    // optional (POOL_DEPOSIT_SOL_MAX_BALANCE is used as both target and low-water mark if not initialized)
+    #[account(seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_target".as_ref(), index1.to_le_bytes().as_ref(), &creator.key().to_bytes()], bump)]
+    pub pool_target1: AccountInfo<'info>,
+    #[account(seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_target".as_ref(), index2.to_le_bytes().as_ref(), &creator.key().to_bytes()], bump)]
+    pub pool_target2: AccountInfo<'info>,
+    #[account(seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_target".as_ref(), index3.to_le_bytes().as_ref(), &creator.key().to_bytes()], bump)]
+    pub pool_target3: AccountInfo<'info>,
+    #[account(seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_target".as_ref(), index4.to_le_bytes().as_ref(), &creator.key().to_bytes()], bump)]
+    pub pool_target4: AccountInfo<'info>,
+
+    // unwrap accounts (deposit_wsol may be uninitialized)
+    #[account(mut, address = get_associated_token_address(&deposit_sol.key(), &WSOL_MINT))]
+    pub deposit_wsol: AccountInfo<'info>,
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"temporary_deposit_wsol".as_ref(), &creator.key().to_bytes()], bump)]
+    pub temporary_deposit_wsol: AccountInfo<'info>,
+    // optional (updated if initialized)
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"treasury_stats".as_ref(), &creator.key().to_bytes()], bump)]
+    pub treasury_stats: AccountInfo<'info>,
 
     // aux accounts
+    #[account(address = WSOL_MINT)]
+    pub wsol_mint: Box<Account<'info, Mint>>,
     pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub rent: Sysvar<'info, Rent>,
 }
 
 
@@ -550,35 +1550,34 @@ pub struct DistributeToPool<'info> {
 pub struct ConvertToSol<'info> {
     pub creator: SystemAccount<'info>,
 
+    #[account(seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"config".as_ref(), &creator.key().to_bytes()], bump)]
+    pub config: Box<Account<'info, Config>>,
+
     #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"deposit_sol".as_ref(), &creator.key().to_bytes()], bump)]
     pub deposit_sol: SystemAccount<'info>,
-    #[account(mut, associated_token::mint = usdc_mint, associated_token::authority = deposit_sol)]
-    pub deposit_usdc: Box<Account<'info, TokenAccount>>,
+    // deposit_usdc or another token account of deposit_sol (fee tokens without a direct pool to SOL)
+    #[account(mut, constraint = deposit_token.owner == deposit_sol.key())]
+    pub deposit_token: Box<Account<'info, TokenAccount>>,
     #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"temporary_deposit_wsol".as_ref(), &creator.key().to_bytes()], bump)]
     pub temporary_deposit_wsol: AccountInfo<'info>,
-
-    // ORCA accounts
-    #[account(address = ORCA_SWAP_PROGRAM_ID)]
-    pub orca_swap_program: AccountInfo<'info>,
-    #[account(address = ORCA_ADDRESS)]
-    pub orca_address: AccountInfo<'info>,
-    #[account(address = ORCA_AUTHORITY)]
-    pub orca_authority: AccountInfo<'info>,
-    #[account(mut, address = ORCA_POOL_WSOL)]
-    pub orca_pool_wsol: Box<Account<'info, TokenAccount>>,
-    #[account(mut, address = ORCA_POOL_USDC)]
-    pub orca_pool_usdc: Box<Account<'info, TokenAccount>>,
-    #[account(mut, address = ORCA_POOL_TOKEN_MINT)]
-    pub orca_pool_token_mint: Box<Account<'info, Mint>>,
-    #[account(mut, address = ORCA_FEE_ACCOUNT)]
-    pub orca_fee_account: Box<Account<'info, TokenAccount>>,
+    // used instead of temporary_deposit_wsol if config.keep_wsol (may be uninitialized otherwise)
+    #[account(mut, address = get_associated_token_address(&deposit_sol.key(), &WSOL_MINT))]
+    pub deposit_wsol: AccountInfo<'info>,
+    // optional (updated if initialized)
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_twap".as_ref(), &creator.key().to_bytes()], bump)]
+    pub pool_twap: AccountInfo<'info>,
+    // optional (updated if initialized)
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"treasury_stats".as_ref(), &creator.key().to_bytes()], bump)]
+    pub treasury_stats: AccountInfo<'info>,
+
+    // swap accounts of each hop are passed as remaining accounts
 
     // aux accounts
     pub wsol_mint: Box<Account<'info, Mint>>,
-    pub usdc_mint: Box<Account<'info, Mint>>,
     pub system_program: Program<'info, System>,
     pub token_program: Program<'info, Token>,
     pub rent: Sysvar<'info, Rent>,
+    pub clock: Sysvar<'info, Clock>,
 }
 
 #[derive(Accounts)]
@@ -587,6 +1586,9 @@ pub struct Neutralize<'info> {
     pub creator: SystemAccount<'info>,
     #[account(seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"price_info".as_ref(), &creator.key().to_bytes()], bump)]
     pub price_info: Box<Account<'info, PriceInfo>>,
+    // optional (the entry used is reported in Neutralized if initialized)
+    #[account(seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"price_history".as_ref(), &creator.key().to_bytes()], bump)]
+    pub price_history: AccountInfo<'info>,
     #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"deposit_sol".as_ref(), index.to_le_bytes().as_ref(), &creator.key().to_bytes()], bump)]
     pub pool_deposit_sol: SystemAccount<'info>,
     #[account(mut, associated_token::mint = usdc_mint, associated_token::authority = pool_deposit_sol)]
@@ -595,6 +1597,11 @@ pub struct Neutralize<'info> {
     pub user: Signer<'info>,
     #[account(mut)]
     pub user_usdc: Box<Account<'info, TokenAccount>>,
+    // optional (updated if initialized)
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"pool_stats".as_ref(), index.to_le_bytes().as_ref(), &creator.key().to_bytes()], bump)]
+    pub pool_stats: AccountInfo<'info>,
+
+    // integrator accounts and a fallback pool (optional) are passed as remaining accounts
 
     // aux accounts
     pub usdc_mint: Box<Account<'info, Mint>>,
@@ -603,63 +1610,765 @@ pub struct Neutralize<'info> {
     pub clock: Sysvar<'info, Clock>,
 }
 
+#[derive(Accounts)]
+#[instruction(integrator: Pubkey)]
+pub struct RegisterIntegrator<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    #[account(init, payer = creator, space = 8 + 32 + 2 + 8 + 8, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"integrator_fee".as_ref(), integrator.as_ref(), &creator.key().to_bytes()], bump)]
+    pub integrator_fee: Box<Account<'info, IntegratorFee>>,
+    #[account(init, payer = creator, associated_token::mint = usdc_mint, associated_token::authority = integrator_fee)]
+    pub integrator_fee_usdc: Box<Account<'info, TokenAccount>>,
+
+    // aux accounts
+    pub usdc_mint: Box<Account<'info, Mint>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(integrator: Pubkey)]
+pub struct UpdateIntegratorFee<'info> {
+    pub creator: Signer<'info>,
+
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"integrator_fee".as_ref(), integrator.as_ref(), &creator.key().to_bytes()], bump)]
+    pub integrator_fee: Box<Account<'info, IntegratorFee>>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimIntegratorFee<'info> {
+    pub creator: SystemAccount<'info>,
+    pub integrator: Signer<'info>,
+
+    #[account(mut, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"integrator_fee".as_ref(), integrator.key().as_ref(), &creator.key().to_bytes()], bump)]
+    pub integrator_fee: Box<Account<'info, IntegratorFee>>,
+    #[account(mut, associated_token::mint = usdc_mint, associated_token::authority = integrator_fee)]
+    pub integrator_fee_usdc: Box<Account<'info, TokenAccount>>,
+    #[account(mut, constraint = integrator_usdc.mint == usdc_mint.key())]
+    pub integrator_usdc: Box<Account<'info, TokenAccount>>,
+
+    // aux accounts
+    pub usdc_mint: Box<Account<'info, Mint>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeTreasuryStats<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    #[account(init, payer = creator, space = 8 + 32 + 8 * 7 + 8, seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"treasury_stats".as_ref(), &creator.key().to_bytes()], bump)]
+    pub treasury_stats: Box<Account<'info, TreasuryStats>>,
+
+    // aux accounts
+    pub usdc_mint: Box<Account<'info, Mint>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct QuoteNeutralize<'info> {
+    pub creator: SystemAccount<'info>,
+    #[account(seeds = [b"kurayashiki".as_ref(), b"nano_swap".as_ref(), b"price_info".as_ref(), &creator.key().to_bytes()], bump)]
+    pub price_info: Box<Account<'info, PriceInfo>>,
+
+    // aux accounts
+    pub clock: Sysvar<'info, Clock>,
+}
+
 #[account]
 pub struct PriceInfo {
     pub current_usdc_per_sol_price: u64,
     pub current_usdc_per_sol_price_updated: i64,   // UnixTimestamp
-    pub old_usdc_per_sol_price: u64,
+    pub old_usdc_per_sol_price: u64,               // previous price (informational, neutralize charges the current price)
     pub old_usdc_per_sol_price_grace_period: i64,  // UnixTimestamp
+    pub last_oracle_usdc_per_sol_price: u64,
+    pub manual_price_expiry: i64,                  // UnixTimestamp (0 if the current price is from oracles)
+}
+
+impl PriceInfo {
+    // USDC charged by neutralize for lamports at now.
+    pub fn quote_neutralize(&self, lamports: u64, now: i64) -> std::result::Result<NeutralizeQuote, ProgramError> {
+        let usdc_per_sol_price = self.current_usdc_per_sol_price;
+        let mut expiry = self.current_usdc_per_sol_price_updated + MAX_ACCEPTABLE_UPDATE_INTERVAL;
+        if self.manual_price_expiry != 0 {
+            expiry = expiry.min(self.manual_price_expiry);
+        }
+        if usdc_per_sol_price == 0 || now > expiry {
+            return Err(ErrorCode::InvalidPrice.into());
+        }
+
+        let required_usdc: u64 = TryFrom::try_from(div_ceiling(
+            (usdc_per_sol_price as u128) * (lamports as u128),
+            1_000_000_000 /* lamrports = 1 SOL */)).map_err(|_| ErrorCode::MathOverflow)?;
+
+        Ok(NeutralizeQuote { required_usdc, usdc_per_sol_price, expiry })
+    }
+
+    // current price becomes old price (with grace period).
+    fn rotate(&mut self, usdc_per_sol_price: u64, now: i64, manual_price_expiry: i64) {
+        let old = self.current_usdc_per_sol_price_updated;
+        self.old_usdc_per_sol_price = self.current_usdc_per_sol_price;
+        // no grace period if update price is delayed over MAX_ACCEPTABLE_UPDATE_INTERVAL or there is no old price.
+        self.old_usdc_per_sol_price_grace_period = if self.old_usdc_per_sol_price != 0 && now - old <= MAX_ACCEPTABLE_UPDATE_INTERVAL { now + GRACE_PERIOD } else { 0 };
+        self.current_usdc_per_sol_price = usdc_per_sol_price;
+        self.current_usdc_per_sol_price_updated = now;
+        self.manual_price_expiry = manual_price_expiry;
+
+        msg!("now: {}, old: {}", now, old);
+        msg!("current_usdc_per_sol_price: {}", self.current_usdc_per_sol_price);
+        msg!("current_usdc_per_sol_price_updated: {}", self.current_usdc_per_sol_price_updated);
+        msg!("old_usdc_per_sol_price: {}", self.old_usdc_per_sol_price);
+        msg!("old_usdc_per_sol_price_grace_period: {}", self.old_usdc_per_sol_price_grace_period);
+        msg!("manual_price_expiry: {}", self.manual_price_expiry);
+
+        emit!(PriceUpdated {
+            current_usdc_per_sol_price: self.current_usdc_per_sol_price,
+            old_usdc_per_sol_price: self.old_usdc_per_sol_price,
+            old_usdc_per_sol_price_grace_period: self.old_usdc_per_sol_price_grace_period,
+            updated: now,
+            manual: manual_price_expiry != 0,
+            manual_price_expiry,
+        });
+    }
+}
+
+#[account]
+pub struct TreasuryStats {
+    pub usdc_mint: Pubkey,
+    // totals of neutralize as of the last collect_from_pool of each pool (see PoolStats)
+    pub lamports_reimbursed: u64,    // SOL handed out by neutralize
+    pub usdc_received: u64,          // micro USDC paid by users into pools (excluding integrator fees)
+    pub integrator_fees: u64,        // micro USDC paid to integrators
+    pub usdc_collected: u64,         // micro USDC swept from pools by collect_from_pool
+    pub usdc_swapped: u64,           // micro USDC swapped by convert_to_sol
+    pub sol_received: u64,           // lamports (or WSOL) received from swaps
+    pub lamports_distributed: u64,   // SOL refilled into pools by distribute_to_pool
+    pub realized_spread: i64,        // lamports, SOL received for USDC minus the SOL reimbursed for it (at the average rate)
+}
+
+impl TreasuryStats {
+    fn record_swap(&mut self, input_mint: Pubkey, input_amount: u64, output_lamports: u64) -> ProgramResult {
+        let sol_received = self.sol_received.checked_add(output_lamports).ok_or(ErrorCode::MathOverflow)?;
+        // the spread is realized only for USDC (other fee tokens have no reimbursement rate).
+        if input_mint != self.usdc_mint {
+            self.sol_received = sol_received;
+            return Ok(());
+        }
+
+        // SOL reimbursed for input_amount at the average rate of neutralize (users paid the integrator fees
+        // for the same SOL, so the rate is of the USDC charged including them).
+        let usdc_charged = self.usdc_received as u128 + self.integrator_fees as u128;
+        let cost_lamports = ((input_amount as u128) * (self.lamports_reimbursed as u128)).checked_div(usdc_charged).unwrap_or(0);
+        let cost_lamports = i64::try_from(cost_lamports).map_err(|_| ErrorCode::MathOverflow)?;
+        let output_lamports = i64::try_from(output_lamports).map_err(|_| ErrorCode::MathOverflow)?;
+        let spread = output_lamports.checked_sub(cost_lamports).ok_or(ErrorCode::MathOverflow)?;
+        let usdc_swapped = self.usdc_swapped.checked_add(input_amount).ok_or(ErrorCode::MathOverflow)?;
+        let realized_spread = self.realized_spread.checked_add(spread).ok_or(ErrorCode::MathOverflow)?;
+        self.sol_received = sol_received;
+        self.usdc_swapped = usdc_swapped;
+        self.realized_spread = realized_spread;
+        msg!("treasury_stats: usdc_swapped {}, cost_lamports {}, realized_spread {}", self.usdc_swapped, cost_lamports, self.realized_spread);
+        Ok(())
+    }
+}
+
+#[account]
+pub struct PoolStats {
+    pub index: u32,
+    pub neutralize_count: u64,
+    pub lamports_paid: u64,         // SOL handed out by neutralize
+    pub usdc_received: u64,         // micro USDC paid into pool_deposit_usdc (excluding integrator fees)
+    pub integrator_fees: u64,       // micro USDC paid to integrators
+    pub last_used_slot: u64,        // slot of the last neutralize (0 if never used)
+    // totals of neutralize not yet added to TreasuryStats (moved by collect_from_pool)
+    pub unreported_lamports_paid: u64,
+    pub unreported_usdc_received: u64,
+    pub unreported_integrator_fees: u64,
+}
+
+// round-robin cursor over pools 0..num_pools (clients start at index_for(user, slot) to spread neutralize
+// over pools, the crank advances next_index with advance_pool_cursor).
+#[account]
+pub struct PoolCursor {
+    pub num_pools: u32,
+    pub next_index: u32,
+}
+
+impl PoolCursor {
+    // next_index offset by the user key and rotated every POOL_ROTATION_SLOTS: concurrent users start at
+    // different pools, and a user moves on to the next pool without any write to the cursor.
+    pub fn index_for(&self, user: &Pubkey, slot: u64) -> u32 {
+        if self.num_pools == 0 {
+            return 0;
+        }
+        let key = user.to_bytes();
+        let user_offset = u32::from_le_bytes([key[0], key[1], key[2], key[3]]) as u64;
+        ((self.next_index as u64 + user_offset + slot / POOL_ROTATION_SLOTS) % self.num_pools as u64) as u32
+    }
+}
+
+// SOL balance of a pool maintained by distribute_to_pool (set by the creator).
+#[account]
+pub struct PoolTarget {
+    pub index: u32,
+    pub target_lamports: u64,       // distribute_to_pool tops the pool up to this balance
+    pub low_water_lamports: u64,    // ... only if the pool holds less than this
+}
+
+#[account]
+pub struct IntegratorFee {
+    pub integrator: Pubkey,
+    pub share_bps: u16,              // share of the USDC charged in neutralize
+    pub accrued: u64,                // micro USDC (total)
+    pub claimed: u64,                // micro USDC (total)
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Debug)]
+pub struct NeutralizeQuote {
+    pub required_usdc: u64,          // micro USDC
+    pub usdc_per_sol_price: u64,     // price used (doubled)
+    pub expiry: i64,                 // UnixTimestamp, the price is valid until
+}
+
+#[event]
+pub struct PriceUpdated {
+    pub current_usdc_per_sol_price: u64,
+    pub old_usdc_per_sol_price: u64,
+    pub old_usdc_per_sol_price_grace_period: i64,
+    pub updated: i64,
+    pub manual: bool,
+    pub manual_price_expiry: i64,
+}
+
+#[account]
+pub struct PriceHistory {
+    pub sequence: u64,                          // sequence of the latest entry (0: no entry)
+    pub entries: [PriceHistoryEntry; 16],       // PRICE_HISTORY_ENTRIES, ring buffer indexed by (sequence - 1) % PRICE_HISTORY_ENTRIES
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct PriceHistoryEntry {
+    pub sequence: u64,                          // starts from 1
+    pub usdc_per_sol_price: u64,                // accepted price (doubled)
+    pub updated: i64,                           // UnixTimestamp
+    pub manual: bool,
+    pub oracle_prices: [u64; 4],                // MAX_ORACLE_SOURCES, raw price of each source of oracle_sources (0: unavailable)
+    pub oracle_confs: [u64; 4],                 // MAX_ORACLE_SOURCES, confidence interval of each source
+}
+
+impl PriceHistory {
+    fn push(&mut self, mut entry: PriceHistoryEntry) -> u64 {
+        self.sequence += 1;
+        entry.sequence = self.sequence;
+        self.entries[((self.sequence - 1) % PRICE_HISTORY_ENTRIES as u64) as usize] = entry;
+        self.sequence
+    }
+
+    // latest entry, if it has the price.
+    fn latest_entry(&self, usdc_per_sol_price: u64) -> Option<&PriceHistoryEntry> {
+        if self.sequence == 0 {
+            return None;
+        }
+        let entry = &self.entries[((self.sequence - 1) % PRICE_HISTORY_ENTRIES as u64) as usize];
+        if entry.usdc_per_sol_price == usdc_per_sol_price { Some(entry) } else { None }
+    }
+}
+
+#[event]
+pub struct Neutralized {
+    pub user: Pubkey,
+    pub index: u32,                   // pool used (the fallback pool if the pool of index is depleted)
+    pub usdc_per_sol_price: u64,      // applied price (required_usdc is computed from it)
+    pub max_price: u64,
+    pub diff_lamports: u64,
+    pub required_usdc: u64,
+    pub price_history_sequence: u64,  // 0 if price_history is not initialized
+    pub integrator: Pubkey,           // default if no integrator is passed
+    pub integrator_fee: u64,          // share of required_usdc paid to the integrator
+}
+
+// distribute_to_pool could not top up all pools of the page.
+#[event]
+pub struct PoolRefillShortfall {
+    pub indexes: [u32; 4],
+    pub refill_lamports: u64,         // needed by the pools below their low-water mark
+    pub distributed_lamports: u64,
+    pub shortfall_lamports: u64,
+}
+
+#[account]
+pub struct OracleSources {
+    pub sources: Vec<OracleSource>,  // max MAX_ORACLE_SOURCES
+    pub min_valid_sources: u8,
+    pub manual_price_max_deviation_bps: u16,
+    pub manual_price_duration: i64,  // seconds
+    pub min_update_interval: i64,    // seconds, update_price is accepted after the interval ...
+    pub min_update_deviation_bps: u16,  // ... or if the price moves by the deviation (0: disabled)
+}
+
+#[account]
+pub struct PostedPrice {
+    pub usdc_per_sol_price: u64,     // micro USDC per SOL (not doubled)
+    pub updated_slot: u64,
+}
+
+#[account]
+pub struct PoolTwap {
+    pub swap_backend: SwapBackend,
+    pub swap_program: Pubkey,
+    pub swap_pool: Pubkey,
+    pub usdc_mint: Pubkey,
+    pub max_deviation_bps: u16,         // sanity check of update_price (0: disabled)
+    pub last_price: u64,                // micro USDC per SOL (spot price, not doubled)
+    pub last_updated: i64,              // UnixTimestamp
+    pub last_updated_slot: u64,
+    pub cumulative_price: u128,         // sum of last_price * seconds
+    pub observation_index: u8,          // next observation to overwrite (= oldest)
+    pub observations: [TwapObservation; 8],  // TWAP_OBSERVATIONS
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct TwapObservation {
+    pub timestamp: i64,
+    pub cumulative_price: u128,
+}
+
+impl PoolTwap {
+    // only the first spot price of a slot is sampled (a swap and its reversal around update_price in the
+    // same slot cannot replace it), returns false if the spot price is ignored.
+    fn update(&mut self, spot_price: u64, now: i64, slot: u64) -> bool {
+        if self.last_updated > 0 && slot == self.last_updated_slot {
+            msg!("pool twap: already sampled in slot {}", slot);
+            return false;
+        }
+        if self.last_updated > 0 {
+            self.cumulative_price += (self.last_price as u128) * ((now - self.last_updated) as u128);
+        }
+
+        let latest = (self.observation_index as usize + TWAP_OBSERVATIONS - 1) % TWAP_OBSERVATIONS;
+        if now - self.observations[latest].timestamp >= TWAP_OBSERVATION_INTERVAL {
+            self.observations[self.observation_index as usize] = TwapObservation { timestamp: now, cumulative_price: self.cumulative_price };
+            self.observation_index = ((self.observation_index as usize + 1) % TWAP_OBSERVATIONS) as u8;
+        }
+
+        self.last_price = spot_price;
+        self.last_updated = now;
+        self.last_updated_slot = slot;
+        msg!("pool twap: spot_price {}, cumulative_price {}", spot_price, self.cumulative_price);
+        true
+    }
+
+    // TWAP since the oldest observation, None if the window is shorter than TWAP_MIN_WINDOW.
+    fn get_twap(&self, now: i64) -> Option<u64> {
+        if self.last_updated == 0 {
+            return None;
+        }
+        let cumulative_price = self.cumulative_price + (self.last_price as u128) * ((now - self.last_updated) as u128);
+
+        let oldest = if self.observations[self.observation_index as usize].timestamp != 0 { self.observations[self.observation_index as usize] } else { self.observations[0] };
+        if oldest.timestamp == 0 || now - oldest.timestamp < TWAP_MIN_WINDOW {
+            return None;
+        }
+
+        TryFrom::try_from((cumulative_price - oldest.cumulative_price) / ((now - oldest.timestamp) as u128)).ok()
+    }
+}
+
+#[account]
+pub struct Config {
+    pub swap_backend: SwapBackend,
+    pub swap_program: Pubkey,
+    pub swap_pool: Pubkey,
+    pub route_pools: Vec<SwapPool>,  // max MAX_ROUTE_POOLS
+    pub keep_wsol: bool,
+    pub whirlpool_slippage_bps: u16,  // allowance below the spot price output of Whirlpool hops
+}
+
+impl Config {
+    fn is_allowed_pool(&self, swap_pool: &SwapPool) -> bool {
+        let primary = SwapPool { swap_backend: self.swap_backend, swap_program: self.swap_program, swap_pool: self.swap_pool };
+        *swap_pool == primary || self.route_pools.contains(swap_pool)
+    }
 }
 
 #[error]
 pub enum ErrorCode {
-    #[msg("PYTH price is unavailable at the moment.")]
-    PythPriceUnavailable,
     #[msg("diff_lamports exceeds MAX_ACCEPTABLE_DIFF_LAMPORTS.")]
     ExceedMaxAcceptableDiffLamports,
     #[msg("Invalid price is requested.")]
     InvalidPrice,
+    #[msg("Swap pool accounts do not match the configuration.")]
+    InvalidSwapAccounts,
+    #[msg("Arithmetic overflow.")]
+    MathOverflow,
+    #[msg("Swap curve of the pool is not supported.")]
+    UnsupportedSwapCurve,
+    #[msg("Swap route is invalid.")]
+    InvalidSwapRoute,
+    #[msg("Swap output is below the minimum output amount.")]
+    SwapOutputBelowMinimum,
+    #[msg("temporary_deposit_wsol is not a WSOL account of deposit_sol.")]
+    InvalidTemporaryAccount,
+    #[msg("Oracle sources are invalid.")]
+    InvalidOracleSources,
+    #[msg("Not enough oracle sources are available at the moment.")]
+    OraclePriceUnavailable,
+    #[msg("Manual price is out of bounds of the last oracle price.")]
+    ManualPriceOutOfBounds,
+    #[msg("Oracle price deviates from the TWAP of the swap pool.")]
+    OraclePriceDeviation,
+    #[msg("Current price exceeds max_price.")]
+    MaxPriceExceeded,
+    #[msg("Price has not changed enough since the last update.")]
+    PriceUpdateTooFrequent,
+    #[msg("Integrator accounts are invalid.")]
+    InvalidIntegratorAccounts,
+    #[msg("Pool cursor is invalid.")]
+    InvalidPoolCursor,
+    #[msg("Pool target is invalid.")]
+    InvalidPoolTarget,
+    #[msg("Pool cannot pay without losing rent exemption.")]
+    PoolDepleted,
+    #[msg("Fallback pool accounts are invalid.")]
+    InvalidFallbackPoolAccounts,
+    #[msg("price_info cannot be migrated.")]
+    InvalidPriceInfo,
+    #[msg("Swap slippage exceeds 100%.")]
+    InvalidSwapSlippage,
+}
+
+fn div_ceiling(numerator: u128, denominator: u128) -> u128 {
+    let quotient = numerator / denominator;
+    if quotient * denominator == numerator { quotient } else { quotient + 1 }
 }
 
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
-struct OrcaSwapInstructionData {
-    instruction: u8,
-    pub input_amount: u64,
-    pub minimum_output_amount: u64,
+fn update_pool_twap<'info>(
+    pool_twap: &AccountInfo<'info>,
+    program_id: &Pubkey,
+    swap_program: &AccountInfo<'info>,
+    pool_accounts: &[AccountInfo<'info>],
+    clock: &Clock,
+) -> ProgramResult {
+    let mut twap = Account::<PoolTwap>::try_from(pool_twap)?;
+    if swap_program.key() != twap.swap_program || pool_accounts.is_empty() || pool_accounts[0].key() != twap.swap_pool {
+        return Err(ErrorCode::InvalidSwapAccounts.into());
+    }
+
+    let pool = SwapPoolAccounts {
+        swap_program,
+        source_mint: twap.usdc_mint,
+        destination_mint: WSOL_MINT,
+        pool_accounts,
+    };
+    let spot_price = twap.swap_backend.get_spot_price(&pool)?;
+    if !twap.update(spot_price, clock.unix_timestamp, clock.slot) {
+        return Ok(());
+    }
+    twap.exit(program_id)
 }
 
-fn get_pyth_current_price( price: &Price ) -> Option<(i64, u64, i32)> {
-    if !matches!(price.agg.status, PriceStatus::Trading) {
-        None
-    } else {
-        Some((price.agg.price, price.agg.conf, price.expo))
+fn record_price_history<'info>(
+    price_history: &AccountInfo<'info>,
+    program_id: &Pubkey,
+    entry: PriceHistoryEntry,
+) -> ProgramResult {
+    if price_history.data_is_empty() {
+        return Ok(());
     }
+
+    let mut history = Account::<PriceHistory>::try_from(price_history)?;
+    let sequence = history.push(entry);
+    msg!("price_history_sequence: {}", sequence);
+    history.exit(program_id)
 }
 
-fn div_ceiling(numerator: u128, denominator: u128) -> u128 {
-    if numerator % denominator == 0 { numerator / denominator } else { numerator / denominator + 1 }
+// lamports transferred to each pool of a page: the emptiest pool is refilled first, and the last one
+// partially (skipped if the partial amount would leave the pool below min_balance).
+fn allocate_refill(current_lamports: [u64; 4], refill_lamports: [u64; 4], allocatable_lamports: u64, min_balance: u64) -> [u64; 4] {
+    let mut order = [0usize, 1, 2, 3];
+    order.sort_by_key(|&i| (current_lamports[i], i));
+
+    let mut allocated_lamports = [0u64; 4];
+    let mut remaining_lamports = allocatable_lamports;
+    for &i in order.iter() {
+        let amount = std::cmp::min(refill_lamports[i], remaining_lamports);
+        if amount == 0 || current_lamports[i] + amount < min_balance {
+            continue;
+        }
+        allocated_lamports[i] = amount;
+        remaining_lamports -= amount;
+    }
+    allocated_lamports
+}
+
+// (target_lamports, low_water_lamports) of an optional PoolTarget.
+fn read_pool_target(pool_target: &AccountInfo) -> std::result::Result<(u64, u64), ProgramError> {
+    if pool_target.data_is_empty() {
+        return Ok((POOL_DEPOSIT_SOL_MAX_BALANCE, POOL_DEPOSIT_SOL_MAX_BALANCE));
+    }
+
+    let pool_target = Account::<PoolTarget>::try_from(pool_target)?;
+    Ok((pool_target.target_lamports, pool_target.low_water_lamports))
 }
 
-fn get_expected_output_amount(
-    input_pool_balance: u64,
-    output_pool_balance: u64,
-    input_amount: u64,
-) -> u64 {
-    let ib: u128 = From::from(input_pool_balance);
-    let ob: u128 = From::from(output_pool_balance);
-    let ia: u128 = From::from(input_amount);
+// updates an optional account (TreasuryStats, PoolStats) if it is initialized.
+fn update_stats<'info, T: AccountSerialize + AccountDeserialize + Owner + Clone>(
+    stats_info: &AccountInfo<'info>,
+    program_id: &Pubkey,
+    update: impl FnOnce(&mut T) -> ProgramResult,
+) -> ProgramResult {
+    if stats_info.data_is_empty() {
+        return Ok(());
+    }
 
-    let orca_fee = ORCA_SWAP_FEE;
+    let mut stats = Account::<T>::try_from(stats_info)?;
+    update(&mut stats)?;
+    stats.exit(program_id)
+}
 
-    let of = div_ceiling(ia * orca_fee.0, orca_fee.1);
-    let mia = ia - of;
+fn create_temporary_deposit_wsol<'info>(
+    deposit_sol: &SystemAccount<'info>,
+    temporary_deposit_wsol: &AccountInfo<'info>,
+    wsol_mint: &Account<'info, Mint>,
+    system_program: &Program<'info, System>,
+    token_program: &Program<'info, Token>,
+    rent: &Sysvar<'info, Rent>,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    // reuse the WSOL account left at the address (it is swept into deposit_sol on close).
+    if temporary_deposit_wsol.owner == &token_program.key() {
+        let wsol_account = Account::<TokenAccount>::try_from(temporary_deposit_wsol)?;
+        if wsol_account.owner != deposit_sol.key() || wsol_account.mint != WSOL_MINT {
+            return Err(ErrorCode::InvalidTemporaryAccount.into());
+        }
+        msg!("reuse account");
+        return Ok(());
+    }
 
-    let invariant = ib * ob;
-    let next_ib = ib + mia;
-    let next_ob = div_ceiling(invariant, next_ib);
+    if temporary_deposit_wsol.lamports() == 0 {
+        msg!("create account");
+        let create_wsol_account_ix = solana_program::system_instruction::create_account(
+            &deposit_sol.key(),
+            &temporary_deposit_wsol.key(),
+            rent.minimum_balance(165),
+            165,
+            &token_program.key());
+        solana_program::program::invoke_signed(
+            &create_wsol_account_ix,
+            &[
+                system_program.to_account_info(),
+                deposit_sol.to_account_info(),
+                temporary_deposit_wsol.to_account_info(),
+                token_program.to_account_info(),
+            ],
+            signer_seeds,
+        )?;
+    }
+    else {
+        // someone has transferred lamports to the address, so create_account fails.
+        // top up, allocate and assign instead (the lamports are swept into deposit_sol on close).
+        msg!("recover account, {} lamports", temporary_deposit_wsol.lamports());
+        let required_lamports = rent.minimum_balance(165).saturating_sub(temporary_deposit_wsol.lamports());
+        if required_lamports > 0 {
+            let transfer_ix = solana_program::system_instruction::transfer(
+                &deposit_sol.key(),
+                &temporary_deposit_wsol.key(),
+                required_lamports);
+            solana_program::program::invoke_signed(
+                &transfer_ix,
+                &[
+                    system_program.to_account_info(),
+                    deposit_sol.to_account_info(),
+                    temporary_deposit_wsol.to_account_info(),
+                ],
+                signer_seeds,
+            )?;
+        }
 
-    let expected_oa = ob - next_ob;
+        let allocate_ix = solana_program::system_instruction::allocate(&temporary_deposit_wsol.key(), 165);
+        solana_program::program::invoke_signed(
+            &allocate_ix,
+            &[
+                system_program.to_account_info(),
+                temporary_deposit_wsol.to_account_info(),
+            ],
+            signer_seeds,
+        )?;
 
-    let expected_oa_u64: u64 = TryFrom::try_from(expected_oa).unwrap();
-    expected_oa_u64
-}
\ No newline at end of file
+        let assign_ix = solana_program::system_instruction::assign(&temporary_deposit_wsol.key(), &token_program.key());
+        solana_program::program::invoke_signed(
+            &assign_ix,
+            &[
+                system_program.to_account_info(),
+                temporary_deposit_wsol.to_account_info(),
+            ],
+            signer_seeds,
+        )?;
+    }
+
+    msg!("initialize account");
+    let init_account_ix = spl_token::instruction::initialize_account(
+        &token_program.key(),
+        &temporary_deposit_wsol.key(),
+        &wsol_mint.key(),
+        &deposit_sol.key())?;
+    solana_program::program::invoke(
+        &init_account_ix,
+        &[
+            token_program.to_account_info(),
+            temporary_deposit_wsol.to_account_info(),
+            wsol_mint.to_account_info(),
+            deposit_sol.to_account_info(),
+            rent.to_account_info(),
+        ],
+    )
+}
+
+fn close_temporary_deposit_wsol<'info>(
+    deposit_sol: &SystemAccount<'info>,
+    temporary_deposit_wsol: &AccountInfo<'info>,
+    token_program: &Program<'info, Token>,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    msg!("close account");
+    let close_account_ix = spl_token::instruction::close_account(
+        &token_program.key(),
+        &temporary_deposit_wsol.key(),
+        &deposit_sol.key(),
+        &deposit_sol.key(),
+        &[],
+    )?;
+    solana_program::program::invoke_signed(
+        &close_account_ix,
+        &[
+            token_program.to_account_info(),
+            temporary_deposit_wsol.to_account_info(),
+            deposit_sol.to_account_info(),
+        ],
+        signer_seeds,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn treasury_stats(lamports_reimbursed: u64, usdc_received: u64, integrator_fees: u64) -> TreasuryStats {
+        TreasuryStats {
+            usdc_mint: Pubkey::new_unique(),
+            lamports_reimbursed,
+            usdc_received,
+            integrator_fees,
+            usdc_collected: usdc_received,
+            usdc_swapped: 0,
+            sol_received: 0,
+            lamports_distributed: 0,
+            realized_spread: 0,
+        }
+    }
+
+    // a fresh price_info
+    fn price_info() -> PriceInfo {
+        PriceInfo {
+            current_usdc_per_sol_price: 0,
+            current_usdc_per_sol_price_updated: 0,
+            old_usdc_per_sol_price: 0,
+            old_usdc_per_sol_price_grace_period: 0,
+            last_oracle_usdc_per_sol_price: 0,
+            manual_price_expiry: 0,
+        }
+    }
+
+    // regression: neutralize must not transfer SOL for free with the zero price of a fresh price_info.
+    #[test]
+    fn quote_neutralize_rejects_a_zero_price() {
+        assert_eq!(price_info().quote_neutralize(5_000, 0).err(), Some(ErrorCode::InvalidPrice.into()));
+    }
+
+    #[test]
+    fn rotate_does_not_start_a_grace_period_for_a_zero_old_price() {
+        let mut price_info = price_info();
+        price_info.rotate(200_000_000, 1_000, 0);
+        assert_eq!((price_info.old_usdc_per_sol_price, price_info.old_usdc_per_sol_price_grace_period), (0, 0));
+
+        price_info.rotate(210_000_000, 2_000, 0);
+        assert_eq!((price_info.old_usdc_per_sol_price, price_info.old_usdc_per_sol_price_grace_period), (200_000_000, 2_000 + GRACE_PERIOD));
+    }
+
+    // regression: a swap and its reversal around update_price in the same slot replaced the sampled spot price.
+    #[test]
+    fn pool_twap_samples_the_first_spot_price_of_a_slot() {
+        let mut pool_twap = PoolTwap {
+            swap_backend: SwapBackend::TokenSwap,
+            swap_program: Pubkey::new_unique(),
+            swap_pool: Pubkey::new_unique(),
+            usdc_mint: Pubkey::new_unique(),
+            max_deviation_bps: 0,
+            last_price: 0,
+            last_updated: 0,
+            last_updated_slot: 0,
+            cumulative_price: 0,
+            observation_index: 0,
+            observations: [TwapObservation::default(); TWAP_OBSERVATIONS],
+        };
+        assert!(pool_twap.update(100_000_000, 1_000, 10));
+        assert!(!pool_twap.update(500_000_000, 1_000, 10));
+        assert_eq!((pool_twap.last_price, pool_twap.last_updated_slot), (100_000_000, 10));
+
+        assert!(pool_twap.update(101_000_000, 1_000 + TWAP_MIN_WINDOW, 11));
+        assert_eq!(pool_twap.get_twap(1_000 + TWAP_MIN_WINDOW), Some(100_000_000));
+    }
+
+    #[test]
+    fn pool_cursor_spreads_users_and_rotates_every_window() {
+        let cursor = PoolCursor { num_pools: 8, next_index: 3 };
+        let user = |offset: u8| Pubkey::new_from_array([offset; 32]);
+
+        // users whose keys differ start at different pools in the same slot.
+        assert_eq!((cursor.index_for(&user(0), 0), cursor.index_for(&user(1), 0), cursor.index_for(&user(2), 0)), (3, 4, 5));
+        // a user keeps the pool within the window, and moves to the next pool in the next window.
+        assert_eq!(cursor.index_for(&user(1), POOL_ROTATION_SLOTS - 1), 4);
+        assert_eq!(cursor.index_for(&user(1), POOL_ROTATION_SLOTS), 5);
+        assert_eq!(cursor.index_for(&user(1), POOL_ROTATION_SLOTS * 4), 0);
+    }
+
+    #[test]
+    fn record_swap_costs_usdc_at_the_rate_charged_including_integrator_fees() {
+        // 0.001 SOL reimbursed for 200 micro USDC (200 USDC/SOL), 20 of which went to integrators.
+        let mut stats = treasury_stats(1_000_000, 180, 20);
+        let usdc_mint = stats.usdc_mint;
+
+        // 180 micro USDC cost 900_000 lamports, and are swapped for 950_000.
+        stats.record_swap(usdc_mint, 180, 950_000).unwrap();
+        assert_eq!(stats.usdc_swapped, 180);
+        assert_eq!(stats.sol_received, 950_000);
+        assert_eq!(stats.realized_spread, 50_000);
+
+        stats.record_swap(usdc_mint, 100, 480_000).unwrap();
+        assert_eq!(stats.usdc_swapped, 280);
+        assert_eq!(stats.realized_spread, 50_000 - 20_000);
+    }
+
+    #[test]
+    fn record_swap_realizes_no_spread_for_other_tokens() {
+        let mut stats = treasury_stats(1_000_000, 180, 20);
+        stats.record_swap(Pubkey::new_unique(), 180, 950_000).unwrap();
+        assert_eq!(stats.usdc_swapped, 0);
+        assert_eq!(stats.sol_received, 950_000);
+        assert_eq!(stats.realized_spread, 0);
+    }
+
+    #[test]
+    fn record_swap_rejects_a_spread_out_of_range() {
+        let mut stats = treasury_stats(u64::MAX, 1, 0);
+        let usdc_mint = stats.usdc_mint;
+        assert!(stats.record_swap(usdc_mint, 2, 0).is_err());
+        assert!(stats.record_swap(usdc_mint, 0, u64::MAX).is_err());
+        assert_eq!(stats.sol_received, 0);
+        assert_eq!(stats.usdc_swapped, 0);
+        assert_eq!(stats.realized_spread, 0);
+    }
+}
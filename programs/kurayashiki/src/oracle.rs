@@ -0,0 +1,349 @@
+use anchor_lang::prelude::*;
+#[cfg(not(feature = "legacy-pyth"))]
+use anchor_lang::solana_program::clock::DEFAULT_MS_PER_SLOT;
+#[cfg(feature = "legacy-pyth")]
+use pyth_client::{
+    Price,
+    PriceStatus,
+    AccountType,
+    VERSION_2,
+    MAGIC,
+    cast
+};
+#[cfg(not(feature = "legacy-pyth"))]
+use pyth_sdk_solana::load_price_feed_from_account_info;
+use crate::{PostedPrice, PoolTwap, ErrorCode};
+
+// pyth-sdk-solana is the default, the "legacy-pyth" feature selects pyth-client instead.
+#[cfg(not(any(feature = "pyth-sdk", feature = "legacy-pyth")))]
+compile_error!("either the \"pyth-sdk\" or the \"legacy-pyth\" feature must be enabled");
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Debug)]
+pub enum OracleSourceType {
+    // PYTH price account (SOL/USD or SOL/USDC)
+    Pyth,
+    // PostedPrice account posted by the creator
+    Posted,
+    // PoolTwap account (TWAP of the swap pool)
+    PoolTwap,
+    // EMA price of the PYTH price account (not supported with the "legacy-pyth" feature)
+    PythEma,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Debug)]
+pub struct OracleSource {
+    pub source_type: OracleSourceType,
+    pub address: Pubkey,
+    pub weight: u8,                 // 0 disables the source
+    pub max_staleness_slots: u64,
+}
+
+impl OracleSource {
+    // returns None if the source is unavailable or stale.
+    pub fn read_price(&self, account: &AccountInfo, clock: &Clock) -> Result<Option<OraclePrice>, ProgramError> {
+        // a price that rounds down to 0 micro USDC is unavailable.
+        let maybe_price = self.reader()?.read_price(account, clock, self.max_staleness_slots)?
+            .filter(|oracle_price| oracle_price.micro_usdc > 0);
+        if maybe_price.is_none() {
+            msg!("oracle {} is skipped", self.address);
+        }
+        Ok(maybe_price)
+    }
+
+    fn reader(&self) -> Result<&'static dyn OracleReader, ProgramError> {
+        Ok(match self.source_type {
+            #[cfg(not(feature = "legacy-pyth"))]
+            OracleSourceType::Pyth => &PythReader { ema: false },
+            #[cfg(not(feature = "legacy-pyth"))]
+            OracleSourceType::PythEma => &PythReader { ema: true },
+            #[cfg(feature = "legacy-pyth")]
+            OracleSourceType::Pyth => &LegacyPythReader,
+            #[cfg(feature = "legacy-pyth")]
+            OracleSourceType::PythEma => {
+                msg!("PythEma is not supported with the legacy-pyth feature");
+                return Err(ErrorCode::InvalidOracleSources.into());
+            },
+            OracleSourceType::Posted => &PostedPriceReader,
+            OracleSourceType::PoolTwap => &PoolTwapReader,
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct OraclePrice {
+    pub micro_usdc: u64,            // micro USDC per SOL
+    pub conf: u64,                  // confidence interval in micro USDC (0 if the source has none)
+}
+
+pub trait OracleReader {
+    // returns None if the price is unavailable or older than max_staleness_slots.
+    fn read_price(&self, account: &AccountInfo, clock: &Clock, max_staleness_slots: u64) -> Result<Option<OraclePrice>, ProgramError>;
+}
+
+// reads the price and the EMA price with pyth-sdk-solana.
+// staleness is checked on the publish time of the price.
+#[cfg(not(feature = "legacy-pyth"))]
+pub struct PythReader {
+    pub ema: bool,
+}
+
+#[cfg(not(feature = "legacy-pyth"))]
+impl OracleReader for PythReader {
+    fn read_price(&self, account: &AccountInfo, clock: &Clock, max_staleness_slots: u64) -> Result<Option<OraclePrice>, ProgramError> {
+        let price_feed = load_price_feed_from_account_info(account).map_err(|_| {
+            msg!("not a valid pyth price account");
+            ErrorCode::InvalidOracleSources
+        })?;
+
+        let now = clock.unix_timestamp;
+        let max_age = (max_staleness_slots * DEFAULT_MS_PER_SLOT / 1000) as i64;
+        if now - price_feed.publish_time > max_age {
+            msg!("pyth price is stale, publish_time: {}, now: {}, max_age: {}", price_feed.publish_time, now, max_age);
+            return Ok(None);
+        }
+        // the EMA price is used only while the aggregate price is trading.
+        if price_feed.get_current_price().is_none() {
+            return Ok(None);
+        }
+        let price = match if self.ema { price_feed.get_ema_price() } else { price_feed.get_current_price() } {
+            Some(price) => price,
+            None => return Ok(None),
+        };
+        if price.price <= 0 {
+            return Ok(None);
+        }
+
+        msg!("pyth {} ...... {} x 10^{}", if self.ema { "ema  " } else { "price" }, price.price, price.expo);
+        msg!("pyth conf ......... {} x 10^{}", price.conf, price.expo);
+        msg!("pyth publish time . {}", price_feed.publish_time);
+
+        Ok(Some(OraclePrice { micro_usdc: to_micro_usdc(price.price, price.expo), conf: to_micro_usdc(price.conf as i64, price.expo) }))
+    }
+}
+
+// reads the aggregate price with pyth-client 0.2 (the "legacy-pyth" feature).
+#[cfg(feature = "legacy-pyth")]
+pub struct LegacyPythReader;
+
+#[cfg(feature = "legacy-pyth")]
+impl OracleReader for LegacyPythReader {
+    fn read_price(&self, account: &AccountInfo, clock: &Clock, max_staleness_slots: u64) -> Result<Option<OraclePrice>, ProgramError> {
+        let data = account.data.borrow();
+        if data.len() < std::mem::size_of::<Price>() {
+            msg!("not a valid pyth account: {}", account.key);
+            return Err(ErrorCode::InvalidOracleSources.into());
+        }
+        let price = cast::<Price>( &data );
+
+        if price.magic != MAGIC || price.atype != AccountType::Price as u32 || price.ver != VERSION_2 {
+            msg!("not a valid pyth price account: {}", account.key);
+            return Err(ErrorCode::InvalidOracleSources.into());
+        }
+
+        if !matches!(price.agg.status, PriceStatus::Trading) || price.agg.price <= 0 {
+            return Ok(None);
+        }
+
+        msg!("pyth price ........ {} x 10^{}", price.agg.price, price.expo);
+        msg!("pyth conf ......... {} x 10^{}", price.agg.conf, price.expo);
+
+        let oracle_price = OraclePrice { micro_usdc: to_micro_usdc(price.agg.price, price.expo), conf: to_micro_usdc(price.agg.conf as i64, price.expo) };
+        Ok(check_staleness(account, oracle_price, price.agg.pub_slot, clock.slot, max_staleness_slots))
+    }
+}
+
+pub struct PostedPriceReader;
+
+impl OracleReader for PostedPriceReader {
+    fn read_price(&self, account: &AccountInfo, clock: &Clock, max_staleness_slots: u64) -> Result<Option<OraclePrice>, ProgramError> {
+        let posted_price = Account::<PostedPrice>::try_from(account)?;
+        if posted_price.usdc_per_sol_price == 0 {
+            return Ok(None);
+        }
+
+        msg!("posted price ...... {}", posted_price.usdc_per_sol_price);
+        let oracle_price = OraclePrice { micro_usdc: posted_price.usdc_per_sol_price, conf: 0 };
+        Ok(check_staleness(account, oracle_price, posted_price.updated_slot, clock.slot, max_staleness_slots))
+    }
+}
+
+pub struct PoolTwapReader;
+
+impl OracleReader for PoolTwapReader {
+    fn read_price(&self, account: &AccountInfo, clock: &Clock, max_staleness_slots: u64) -> Result<Option<OraclePrice>, ProgramError> {
+        let pool_twap = Account::<PoolTwap>::try_from(account)?;
+        Ok(pool_twap.get_twap(clock.unix_timestamp).and_then(|twap| {
+            msg!("pool twap ......... {}", twap);
+            check_staleness(account, OraclePrice { micro_usdc: twap, conf: 0 }, pool_twap.last_updated_slot, clock.slot, max_staleness_slots)
+        }))
+    }
+}
+
+fn check_staleness(account: &AccountInfo, oracle_price: OraclePrice, updated_slot: u64, slot: u64, max_staleness_slots: u64) -> Option<OraclePrice> {
+    if slot.saturating_sub(updated_slot) > max_staleness_slots {
+        msg!("oracle {} is stale, updated_slot: {}, slot: {}", account.key, updated_slot, slot);
+        return None;
+    }
+    Some(oracle_price)
+}
+
+// price x 10^expo -> micro USDC
+fn to_micro_usdc(price: i64, expo: i32) -> u64 {
+    let mut micro_usdc = price;
+    let mut e = expo;
+    while e < -6 { micro_usdc /= 10; e += 1; }
+    while e > -6 { micro_usdc *= 10; e -= 1; }
+    micro_usdc as u64
+}
+
+// the TWAP of the swap pool can be skewed within a transaction, so PoolTwap sources must weigh less than
+// the other enabled sources together (the weighted median then stays within the range of the other sources).
+pub fn is_pool_twap_outweighed(sources: &[OracleSource]) -> bool {
+    let (pool_twap_weight, other_weight) = sources.iter().fold((0u64, 0u64), |(pool_twap, other), source| {
+        match source.source_type {
+            OracleSourceType::PoolTwap => (pool_twap + source.weight as u64, other),
+            _ => (pool_twap, other + source.weight as u64),
+        }
+    });
+    pool_twap_weight == 0 || pool_twap_weight < other_weight
+}
+
+// weighted median of (price, weight)
+pub fn weighted_median(prices: &mut Vec<(u64, u8)>) -> Option<u64> {
+    prices.retain(|(_, weight)| *weight > 0);
+    prices.sort_unstable_by_key(|(price, _)| *price);
+
+    let total_weight: u64 = prices.iter().map(|(_, weight)| *weight as u64).sum();
+    let mut cumulative_weight = 0u64;
+    for (price, weight) in prices.iter() {
+        cumulative_weight += *weight as u64;
+        if cumulative_weight * 2 >= total_weight {
+            return Some(*price);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(source_type: OracleSourceType, key: &Pubkey, data: &mut [u8], clock: &Clock) -> Result<Option<OraclePrice>, ProgramError> {
+        let owner = Pubkey::default();
+        let mut lamports = 0;
+        let account = AccountInfo::new(key, false, false, &mut lamports, data, &owner, false, 0);
+        let source = OracleSource { source_type, address: *key, weight: 1, max_staleness_slots: 150 };
+        source.read_price(&account, clock)
+    }
+
+    fn clock(unix_timestamp: i64) -> Clock {
+        Clock { slot: 1_000, unix_timestamp, ..Clock::default() }
+    }
+
+    #[test]
+    fn weighted_median_ignores_disabled_sources() {
+        assert_eq!(weighted_median(&mut vec![(100, 1), (300, 0), (200, 1)]), Some(100));
+        assert_eq!(weighted_median(&mut vec![(100, 1), (200, 2), (300, 1)]), Some(200));
+        assert_eq!(weighted_median(&mut vec![(100, 0)]), None);
+    }
+
+    fn source(source_type: OracleSourceType, weight: u8) -> OracleSource {
+        OracleSource { source_type, address: Pubkey::new_unique(), weight, max_staleness_slots: 150 }
+    }
+
+    #[test]
+    fn pool_twap_must_weigh_less_than_the_other_sources() {
+        assert!(is_pool_twap_outweighed(&[source(OracleSourceType::Posted, 1)]));
+        assert!(is_pool_twap_outweighed(&[source(OracleSourceType::PoolTwap, 1), source(OracleSourceType::Posted, 1), source(OracleSourceType::Pyth, 1)]));
+        assert!(!is_pool_twap_outweighed(&[source(OracleSourceType::PoolTwap, 1), source(OracleSourceType::Posted, 1)]));
+        assert!(!is_pool_twap_outweighed(&[source(OracleSourceType::PoolTwap, 2), source(OracleSourceType::Posted, 1), source(OracleSourceType::Pyth, 1)]));
+        assert!(!is_pool_twap_outweighed(&[source(OracleSourceType::PoolTwap, 1)]));
+    }
+
+    #[cfg(not(feature = "legacy-pyth"))]
+    fn pyth_price_account(price: i64, ema_price: i64, timestamp: i64) -> Vec<u8> {
+        use pyth_sdk_solana::state::{AccountType, PriceAccount, PriceInfo, PriceType, Rational, MAGIC, VERSION_2};
+        use pyth_sdk_solana::PriceStatus;
+
+        let price_account = PriceAccount {
+            magic: MAGIC,
+            ver: VERSION_2,
+            atype: AccountType::Price as u32,
+            ptype: PriceType::Price,
+            expo: -8,
+            timestamp,
+            ema_price: Rational { val: ema_price, numer: ema_price, denom: 1 },
+            agg: PriceInfo { price, conf: 5_000_000, status: PriceStatus::Trading, ..PriceInfo::default() },
+            ..PriceAccount::default()
+        };
+        bytemuck::bytes_of(&price_account).to_vec()
+    }
+
+    // 100 USD per SOL (EMA 98 USD per SOL), published at 1_000.
+    #[cfg(not(feature = "legacy-pyth"))]
+    #[test]
+    fn pyth_sdk_reads_price_and_ema_price() {
+        let key = Pubkey::new_unique();
+        let mut data = pyth_price_account(10_000_000_000, 9_800_000_000, 1_000);
+
+        let price = read(OracleSourceType::Pyth, &key, &mut data, &clock(1_010)).unwrap();
+        assert_eq!(price, Some(OraclePrice { micro_usdc: 100_000_000, conf: 50_000 }));
+        let ema_price = read(OracleSourceType::PythEma, &key, &mut data, &clock(1_010)).unwrap();
+        assert_eq!(ema_price.map(|price| price.micro_usdc), Some(98_000_000));
+    }
+
+    // max_staleness_slots 150 = 60 seconds
+    #[cfg(not(feature = "legacy-pyth"))]
+    #[test]
+    fn pyth_sdk_skips_a_stale_price() {
+        let key = Pubkey::new_unique();
+        let mut data = pyth_price_account(10_000_000_000, 9_800_000_000, 1_000);
+
+        assert!(read(OracleSourceType::Pyth, &key, &mut data, &clock(1_060)).unwrap().is_some());
+        assert_eq!(read(OracleSourceType::Pyth, &key, &mut data, &clock(1_061)).unwrap(), None);
+        assert_eq!(read(OracleSourceType::PythEma, &key, &mut data, &clock(1_061)).unwrap(), None);
+    }
+
+    #[cfg(not(feature = "legacy-pyth"))]
+    #[test]
+    fn pyth_sdk_rejects_a_non_pyth_account() {
+        let key = Pubkey::new_unique();
+        let mut data = vec![0u8; 3312];
+        assert!(read(OracleSourceType::Pyth, &key, &mut data, &clock(1_000)).is_err());
+    }
+
+    // magic, ver and atype of a pyth-client 0.2 price account
+    #[cfg(feature = "legacy-pyth")]
+    fn legacy_pyth_price_account(magic: u32, ver: u32, atype: u32) -> Vec<u8> {
+        let mut data = vec![0u8; std::mem::size_of::<Price>()];
+        data[0..4].copy_from_slice(&magic.to_le_bytes());
+        data[4..8].copy_from_slice(&ver.to_le_bytes());
+        data[8..12].copy_from_slice(&atype.to_le_bytes());
+        data
+    }
+
+    #[cfg(feature = "legacy-pyth")]
+    #[test]
+    fn legacy_pyth_rejects_a_non_pyth_account() {
+        let key = Pubkey::new_unique();
+        let invalid_accounts = [
+            vec![0u8; 16],
+            legacy_pyth_price_account(0, VERSION_2, AccountType::Price as u32),
+            legacy_pyth_price_account(MAGIC, VERSION_2, AccountType::Product as u32),
+            legacy_pyth_price_account(MAGIC, VERSION_2 + 1, AccountType::Price as u32),
+        ];
+        for mut data in invalid_accounts {
+            let result = read(OracleSourceType::Pyth, &key, &mut data, &clock(1_000));
+            assert_eq!(result, Err(ErrorCode::InvalidOracleSources.into()));
+        }
+    }
+
+    // the status of the aggregate price is Unknown
+    #[cfg(feature = "legacy-pyth")]
+    #[test]
+    fn legacy_pyth_skips_a_price_that_is_not_trading() {
+        let key = Pubkey::new_unique();
+        let mut data = legacy_pyth_price_account(MAGIC, VERSION_2, AccountType::Price as u32);
+        assert_eq!(read(OracleSourceType::Pyth, &key, &mut data, &clock(1_000)), Ok(None));
+    }
+}
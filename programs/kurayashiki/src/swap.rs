@@ -0,0 +1,539 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, TokenAccount};
+use solana_program::instruction::{Instruction, AccountMeta};
+use std::convert::TryFrom;
+use borsh::{BorshDeserialize, BorshSerialize};
+use crate::{
+    ErrorCode,
+    div_ceiling,
+};
+
+// Whirlpool parameters
+const WHIRLPOOL_SWAP_DISCRIMINATOR: [u8; 8] = [248, 198, 158, 145, 225, 117, 135, 200]; // sighash("global:swap")
+const WHIRLPOOL_FEE_RATE_DENOMINATOR: u128 = 1_000_000; // fee_rate is hundredths of a basis point
+const WHIRLPOOL_MIN_SQRT_PRICE: u128 = 4_295_048_016;
+const WHIRLPOOL_MAX_SQRT_PRICE: u128 = 79_226_673_515_401_279_992_447_579_055;
+
+// spot price is the source amount per 10^9 destination amount (micro USDC per SOL for USDC -> WSOL)
+const SPOT_PRICE_SCALE: u128 = 1_000_000_000;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Debug)]
+pub enum SwapBackend {
+    // pool accounts: [token_swap, swap_authority, pool_source, pool_destination, pool_mint, pool_fee_account]
+    TokenSwap,
+    // pool accounts: [whirlpool, token_vault_a, token_vault_b, oracle, tick_array_0, tick_array_1, tick_array_2]
+    Whirlpool,
+}
+
+// a pool that convert_to_sol is allowed to route through
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Debug)]
+pub struct SwapPool {
+    pub swap_backend: SwapBackend,
+    pub swap_program: Pubkey,
+    pub swap_pool: Pubkey,
+}
+
+// a hop of the convert_to_sol route
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Debug)]
+pub struct SwapHop {
+    pub swap_backend: SwapBackend,
+    pub minimum_output_amount: u64,
+}
+
+// accounts to read the state of a pool (no token accounts of the swapper)
+pub struct SwapPoolAccounts<'a, 'info> {
+    pub swap_program: &'a AccountInfo<'info>,
+    pub source_mint: Pubkey,
+    pub destination_mint: Pubkey,
+    pub pool_accounts: &'a [AccountInfo<'info>],
+}
+
+pub struct SwapAccounts<'a, 'info> {
+    pub swap_program: &'a AccountInfo<'info>,
+    pub authority: &'a AccountInfo<'info>,
+    pub source: &'a AccountInfo<'info>,
+    pub destination: &'a AccountInfo<'info>,
+    pub source_mint: Pubkey,
+    pub destination_mint: Pubkey,
+    pub token_program: &'a AccountInfo<'info>,
+    pub pool_accounts: &'a [AccountInfo<'info>],
+}
+
+impl<'a, 'info> SwapAccounts<'a, 'info> {
+    fn pool(&self) -> SwapPoolAccounts<'a, 'info> {
+        SwapPoolAccounts {
+            swap_program: self.swap_program,
+            source_mint: self.source_mint,
+            destination_mint: self.destination_mint,
+            pool_accounts: self.pool_accounts,
+        }
+    }
+}
+
+impl SwapBackend {
+    pub fn pool_accounts_len(&self) -> usize {
+        match self {
+            SwapBackend::TokenSwap => 6,
+            SwapBackend::Whirlpool => 7,
+        }
+    }
+
+    // whirlpool_slippage_bps: allowance below the spot price output of Whirlpool (Config::whirlpool_slippage_bps)
+    pub fn get_expected_output_amount(&self, accounts: &SwapAccounts, input_amount: u64, whirlpool_slippage_bps: u16) -> Result<u64, ProgramError> {
+        match self {
+            SwapBackend::TokenSwap => token_swap_expected_output_amount(accounts, input_amount),
+            SwapBackend::Whirlpool => whirlpool_expected_output_amount(accounts, input_amount, whirlpool_slippage_bps),
+        }
+    }
+
+    pub fn get_spot_price(&self, pool: &SwapPoolAccounts) -> Result<u64, ProgramError> {
+        match self {
+            SwapBackend::TokenSwap => token_swap_spot_price(pool),
+            SwapBackend::Whirlpool => whirlpool_spot_price(pool),
+        }
+    }
+
+    pub fn swap(&self, accounts: &SwapAccounts, input_amount: u64, minimum_output_amount: u64, signer_seeds: &[&[&[u8]]]) -> ProgramResult {
+        match self {
+            SwapBackend::TokenSwap => token_swap(accounts, input_amount, minimum_output_amount, signer_seeds),
+            SwapBackend::Whirlpool => whirlpool_swap(accounts, input_amount, minimum_output_amount, signer_seeds),
+        }
+    }
+}
+
+// SPL token swap (ORCA legacy pools are SPL token swap deployments)
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+struct TokenSwapInstructionData {
+    instruction: u8,
+    pub input_amount: u64,
+    pub minimum_output_amount: u64,
+}
+
+// SwapCurve::curve_type of SPL token swap
+const CURVE_TYPE_CONSTANT_PRODUCT: u8 = 0;
+const CURVE_TYPE_CONSTANT_PRICE: u8 = 1;
+const CURVE_TYPE_OFFSET: u8 = 3;
+
+// fields of the SwapV1 account used by kurayashiki (offsets include the 1 byte version)
+struct TokenSwapState {
+    is_initialized: bool,           // offset 1
+    bump_seed: u8,                  // offset 2
+    token_program_id: Pubkey,       // offset 3
+    token_a: Pubkey,                // offset 35
+    token_b: Pubkey,                // offset 67
+    pool_mint: Pubkey,              // offset 99
+    token_a_mint: Pubkey,           // offset 131
+    token_b_mint: Pubkey,           // offset 163
+    pool_fee_account: Pubkey,       // offset 195
+    trade_fee: (u128, u128),        // offset 227
+    owner_trade_fee: (u128, u128),  // offset 243
+    curve_type: u8,                 // offset 291
+    curve_parameter: u128,          // offset 292 (token_b_price or token_b_offset)
+}
+
+impl TokenSwapState {
+    const LEN: usize = 324;
+
+    fn load(token_swap: &AccountInfo, swap_program: &Pubkey) -> Result<Self, ProgramError> {
+        if token_swap.owner != swap_program {
+            return Err(ErrorCode::InvalidSwapAccounts.into());
+        }
+
+        let data = token_swap.data.borrow();
+        if data.len() < TokenSwapState::LEN || data[0] != 1 /* SwapV1 */ {
+            return Err(ErrorCode::InvalidSwapAccounts.into());
+        }
+
+        let pubkey_at = |offset: usize| Pubkey::new(&data[offset..offset + 32]);
+        let u64_at = |offset: usize| u64::from_le_bytes(<[u8; 8]>::try_from(&data[offset..offset + 8]).unwrap()) as u128;
+        Ok(TokenSwapState {
+            is_initialized: data[1] == 1,
+            bump_seed: data[2],
+            token_program_id: pubkey_at(3),
+            token_a: pubkey_at(35),
+            token_b: pubkey_at(67),
+            pool_mint: pubkey_at(99),
+            token_a_mint: pubkey_at(131),
+            token_b_mint: pubkey_at(163),
+            pool_fee_account: pubkey_at(195),
+            trade_fee: (u64_at(227), u64_at(235)),
+            owner_trade_fee: (u64_at(243), u64_at(251)),
+            curve_type: data[291],
+            curve_parameter: u64_at(292),
+        })
+    }
+}
+
+struct TokenSwapAccounts<'a, 'info> {
+    token_swap: &'a AccountInfo<'info>,
+    swap_authority: &'a AccountInfo<'info>,
+    pool_source: &'a AccountInfo<'info>,
+    pool_destination: &'a AccountInfo<'info>,
+    pool_mint: &'a AccountInfo<'info>,
+    pool_fee_account: &'a AccountInfo<'info>,
+    state: TokenSwapState,
+    a_to_b: bool,
+}
+
+fn load_token_swap_accounts<'a, 'info>(accounts: &SwapPoolAccounts<'a, 'info>) -> Result<TokenSwapAccounts<'a, 'info>, ProgramError> {
+    if accounts.pool_accounts.len() < SwapBackend::TokenSwap.pool_accounts_len() {
+        return Err(ErrorCode::InvalidSwapAccounts.into());
+    }
+
+    let pool = TokenSwapAccounts {
+        token_swap: &accounts.pool_accounts[0],
+        swap_authority: &accounts.pool_accounts[1],
+        pool_source: &accounts.pool_accounts[2],
+        pool_destination: &accounts.pool_accounts[3],
+        pool_mint: &accounts.pool_accounts[4],
+        pool_fee_account: &accounts.pool_accounts[5],
+        state: TokenSwapState::load(&accounts.pool_accounts[0], &accounts.swap_program.key())?,
+        a_to_b: false,
+    };
+    let state = &pool.state;
+
+    if !state.is_initialized
+       || state.token_program_id != token::ID
+       || pool.pool_mint.key() != state.pool_mint
+       || pool.pool_fee_account.key() != state.pool_fee_account {
+        return Err(ErrorCode::InvalidSwapAccounts.into());
+    }
+
+    let swap_authority = Pubkey::create_program_address(&[&pool.token_swap.key().to_bytes(), &[state.bump_seed]], &accounts.swap_program.key())?;
+    if pool.swap_authority.key() != swap_authority {
+        return Err(ErrorCode::InvalidSwapAccounts.into());
+    }
+
+    // source_mint -> destination_mint
+    let a_to_b = if pool.pool_source.key() == state.token_a && pool.pool_destination.key() == state.token_b
+       && state.token_a_mint == accounts.source_mint && state.token_b_mint == accounts.destination_mint {
+        true
+    } else if pool.pool_source.key() == state.token_b && pool.pool_destination.key() == state.token_a
+       && state.token_b_mint == accounts.source_mint && state.token_a_mint == accounts.destination_mint {
+        false
+    } else {
+        return Err(ErrorCode::InvalidSwapAccounts.into());
+    };
+
+    Ok(TokenSwapAccounts { a_to_b, ..pool })
+}
+
+fn token_swap_expected_output_amount(accounts: &SwapAccounts, input_amount: u64) -> Result<u64, ProgramError> {
+    let pool = load_token_swap_accounts(&accounts.pool())?;
+    let pool_source = Account::<TokenAccount>::try_from(pool.pool_source)?;
+    let pool_destination = Account::<TokenAccount>::try_from(pool.pool_destination)?;
+
+    get_expected_output_amount(&pool.state, pool.a_to_b, pool_source.amount, pool_destination.amount, input_amount)
+}
+
+fn token_swap_spot_price(accounts: &SwapPoolAccounts) -> Result<u64, ProgramError> {
+    let pool = load_token_swap_accounts(accounts)?;
+    let ib: u128 = From::from(Account::<TokenAccount>::try_from(pool.pool_source)?.amount);
+    let ob: u128 = From::from(Account::<TokenAccount>::try_from(pool.pool_destination)?.amount);
+
+    let spot_price = match pool.state.curve_type {
+        CURVE_TYPE_CONSTANT_PRODUCT | CURVE_TYPE_OFFSET => {
+            let offset = if pool.state.curve_type == CURVE_TYPE_OFFSET { pool.state.curve_parameter } else { 0 };
+            let (ib, ob) = if pool.a_to_b { (ib, ob + offset) } else { (ib + offset, ob) };
+            if ob == 0 {
+                return Err(ErrorCode::InvalidSwapAccounts.into());
+            }
+            ib.checked_mul(SPOT_PRICE_SCALE).ok_or(ErrorCode::MathOverflow)? / ob
+        },
+        CURVE_TYPE_CONSTANT_PRICE => {
+            // token_b_price is token A amount per token B amount
+            let token_b_price = pool.state.curve_parameter;
+            if pool.a_to_b { token_b_price * SPOT_PRICE_SCALE } else { SPOT_PRICE_SCALE / token_b_price }
+        },
+        _ => return Err(ErrorCode::UnsupportedSwapCurve.into()),
+    };
+
+    let spot_price_u64: u64 = TryFrom::try_from(spot_price).map_err(|_| ErrorCode::MathOverflow)?;
+    Ok(spot_price_u64)
+}
+
+fn token_swap(accounts: &SwapAccounts, input_amount: u64, minimum_output_amount: u64, signer_seeds: &[&[&[u8]]]) -> ProgramResult {
+    let pool = load_token_swap_accounts(&accounts.pool())?;
+
+    let swap_ix = Instruction {
+        program_id: accounts.swap_program.key(),
+        accounts: vec![
+            AccountMeta::new_readonly(pool.token_swap.key(), false),
+            AccountMeta::new_readonly(pool.swap_authority.key(), false),
+            AccountMeta::new_readonly(accounts.authority.key(), true),
+            AccountMeta::new(accounts.source.key(), false),
+            AccountMeta::new(pool.pool_source.key(), false),
+            AccountMeta::new(pool.pool_destination.key(), false),
+            AccountMeta::new(accounts.destination.key(), false),
+            AccountMeta::new(pool.pool_mint.key(), false),
+            AccountMeta::new(pool.pool_fee_account.key(), false),
+            AccountMeta::new_readonly(accounts.token_program.key(), false),
+        ],
+        data: TokenSwapInstructionData {
+            instruction: 1, // Swap instruction
+            input_amount,
+            minimum_output_amount,
+        }.try_to_vec()?
+    };
+    solana_program::program::invoke_signed(
+        &swap_ix,
+        &[
+            accounts.swap_program.clone(),
+            pool.token_swap.clone(),
+            pool.swap_authority.clone(),
+            accounts.authority.clone(),
+            accounts.source.clone(),
+            pool.pool_source.clone(),
+            pool.pool_destination.clone(),
+            accounts.destination.clone(),
+            pool.pool_mint.clone(),
+            pool.pool_fee_account.clone(),
+            accounts.token_program.clone(),
+        ],
+        signer_seeds,
+    )
+}
+
+// same as Fees::trading_fee of SPL token swap (minimum fee of one token)
+fn calculate_fee(amount: u128, fee: (u128, u128)) -> u128 {
+    if fee.0 == 0 || amount == 0 { return 0; }
+    let f = amount * fee.0 / fee.1;
+    if f == 0 { 1 } else { f }
+}
+
+fn get_expected_output_amount(
+    state: &TokenSwapState,
+    a_to_b: bool,
+    input_pool_balance: u64,
+    output_pool_balance: u64,
+    input_amount: u64,
+) -> Result<u64, ProgramError> {
+    let ib: u128 = From::from(input_pool_balance);
+    let ob: u128 = From::from(output_pool_balance);
+    let ia: u128 = From::from(input_amount);
+
+    let fees = calculate_fee(ia, state.trade_fee) + calculate_fee(ia, state.owner_trade_fee);
+    if fees >= ia {
+        return Ok(0);
+    }
+    let mia = ia - fees;
+
+    let expected_oa = match state.curve_type {
+        CURVE_TYPE_CONSTANT_PRODUCT => get_constant_product_output_amount(ib, ob, mia),
+        CURVE_TYPE_OFFSET => {
+            let offset = state.curve_parameter;
+            if a_to_b { get_constant_product_output_amount(ib, ob + offset, mia) } else { get_constant_product_output_amount(ib + offset, ob, mia) }
+        },
+        CURVE_TYPE_CONSTANT_PRICE => {
+            let token_b_price = state.curve_parameter;
+            if a_to_b { mia / token_b_price } else { mia * token_b_price }
+        },
+        _ => return Err(ErrorCode::UnsupportedSwapCurve.into()),
+    };
+
+    // the virtual offset is not withdrawable.
+    let expected_oa = std::cmp::min(expected_oa, ob);
+
+    let expected_oa_u64: u64 = TryFrom::try_from(expected_oa).map_err(|_| ErrorCode::MathOverflow)?;
+    Ok(expected_oa_u64)
+}
+
+fn get_constant_product_output_amount(ib: u128, ob: u128, mia: u128) -> u128 {
+    let invariant = ib * ob;
+    let next_ib = ib + mia;
+    let next_ob = div_ceiling(invariant, next_ib);
+
+    ob - next_ob
+}
+
+// Whirlpool (concentrated liquidity)
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+struct WhirlpoolSwapInstructionData {
+    pub amount: u64,
+    pub other_amount_threshold: u64,
+    pub sqrt_price_limit: u128,
+    pub amount_specified_is_input: bool,
+    pub a_to_b: bool,
+}
+
+// fields of the Whirlpool account used by kurayashiki (offsets include the 8 bytes discriminator)
+struct WhirlpoolState {
+    fee_rate: u16,          // offset 45
+    sqrt_price: u128,       // offset 65
+    token_mint_a: Pubkey,   // offset 101
+    token_vault_a: Pubkey,  // offset 133
+    token_mint_b: Pubkey,   // offset 181
+    token_vault_b: Pubkey,  // offset 213
+}
+
+impl WhirlpoolState {
+    const LEN: usize = 245;
+
+    fn load(whirlpool: &AccountInfo, swap_program: &Pubkey) -> Result<Self, ProgramError> {
+        if whirlpool.owner != swap_program {
+            return Err(ErrorCode::InvalidSwapAccounts.into());
+        }
+
+        let data = whirlpool.data.borrow();
+        if data.len() < WhirlpoolState::LEN {
+            return Err(ErrorCode::InvalidSwapAccounts.into());
+        }
+
+        let pubkey_at = |offset: usize| Pubkey::new(&data[offset..offset + 32]);
+        Ok(WhirlpoolState {
+            fee_rate: u16::from_le_bytes(<[u8; 2]>::try_from(&data[45..47]).unwrap()),
+            sqrt_price: u128::from_le_bytes(<[u8; 16]>::try_from(&data[65..81]).unwrap()),
+            token_mint_a: pubkey_at(101),
+            token_vault_a: pubkey_at(133),
+            token_mint_b: pubkey_at(181),
+            token_vault_b: pubkey_at(213),
+        })
+    }
+}
+
+struct WhirlpoolAccounts<'a, 'info> {
+    whirlpool: &'a AccountInfo<'info>,
+    token_vault_a: &'a AccountInfo<'info>,
+    token_vault_b: &'a AccountInfo<'info>,
+    oracle: &'a AccountInfo<'info>,
+    tick_arrays: &'a [AccountInfo<'info>],
+    state: WhirlpoolState,
+    a_to_b: bool,
+}
+
+fn load_whirlpool_accounts<'a, 'info>(accounts: &SwapPoolAccounts<'a, 'info>) -> Result<WhirlpoolAccounts<'a, 'info>, ProgramError> {
+    if accounts.pool_accounts.len() < SwapBackend::Whirlpool.pool_accounts_len() {
+        return Err(ErrorCode::InvalidSwapAccounts.into());
+    }
+
+    let whirlpool = &accounts.pool_accounts[0];
+    let state = WhirlpoolState::load(whirlpool, &accounts.swap_program.key())?;
+    if accounts.pool_accounts[1].key() != state.token_vault_a
+       || accounts.pool_accounts[2].key() != state.token_vault_b {
+        return Err(ErrorCode::InvalidSwapAccounts.into());
+    }
+
+    // source_mint -> destination_mint
+    let a_to_b = if state.token_mint_a == accounts.source_mint && state.token_mint_b == accounts.destination_mint {
+        true
+    } else if state.token_mint_a == accounts.destination_mint && state.token_mint_b == accounts.source_mint {
+        false
+    } else {
+        return Err(ErrorCode::InvalidSwapAccounts.into());
+    };
+
+    Ok(WhirlpoolAccounts {
+        whirlpool,
+        token_vault_a: &accounts.pool_accounts[1],
+        token_vault_b: &accounts.pool_accounts[2],
+        oracle: &accounts.pool_accounts[3],
+        tick_arrays: &accounts.pool_accounts[4..7],
+        state,
+        a_to_b,
+    })
+}
+
+fn whirlpool_expected_output_amount(accounts: &SwapAccounts, input_amount: u64, slippage_bps: u16) -> Result<u64, ProgramError> {
+    let pool = load_whirlpool_accounts(&accounts.pool())?;
+
+    let ia: u128 = From::from(input_amount);
+    let fee = div_ceiling(ia * pool.state.fee_rate as u128, WHIRLPOOL_FEE_RATE_DENOMINATOR);
+    let mia = ia - fee;
+
+    // price = (sqrt_price / 2^64)^2 (token B per token A)
+    let sqrt_price = pool.state.sqrt_price;
+    if sqrt_price == 0 {
+        return Err(ErrorCode::InvalidSwapAccounts.into());
+    }
+    let expected_oa = if pool.a_to_b {
+        let x = mia.checked_mul(sqrt_price).ok_or(ErrorCode::MathOverflow)? >> 64;
+        x.checked_mul(sqrt_price).ok_or(ErrorCode::MathOverflow)? >> 64
+    } else {
+        let x = (mia << 64) / sqrt_price;
+        x.checked_mul(1u128 << 64).ok_or(ErrorCode::MathOverflow)? / sqrt_price
+    };
+
+    // spot price only (tick traversal is not simulated on-chain), allow slippage_bps for tick crossing.
+    let minimum_oa = expected_oa * (10_000 - slippage_bps.min(10_000) as u128) / 10_000;
+
+    let minimum_oa_u64: u64 = TryFrom::try_from(minimum_oa).map_err(|_| ErrorCode::MathOverflow)?;
+    Ok(minimum_oa_u64)
+}
+
+fn whirlpool_spot_price(accounts: &SwapPoolAccounts) -> Result<u64, ProgramError> {
+    let pool = load_whirlpool_accounts(accounts)?;
+
+    // price = (sqrt_price / 2^64)^2 (token B per token A)
+    let sqrt_price = pool.state.sqrt_price;
+    if sqrt_price == 0 {
+        return Err(ErrorCode::InvalidSwapAccounts.into());
+    }
+    let spot_price = if pool.a_to_b {
+        // token A per token B
+        let x = (SPOT_PRICE_SCALE << 64) / sqrt_price;
+        x.checked_mul(1u128 << 64).ok_or(ErrorCode::MathOverflow)? / sqrt_price
+    } else {
+        let x = SPOT_PRICE_SCALE.checked_mul(sqrt_price).ok_or(ErrorCode::MathOverflow)? >> 64;
+        x.checked_mul(sqrt_price).ok_or(ErrorCode::MathOverflow)? >> 64
+    };
+
+    let spot_price_u64: u64 = TryFrom::try_from(spot_price).map_err(|_| ErrorCode::MathOverflow)?;
+    Ok(spot_price_u64)
+}
+
+fn whirlpool_swap(accounts: &SwapAccounts, input_amount: u64, minimum_output_amount: u64, signer_seeds: &[&[&[u8]]]) -> ProgramResult {
+    let pool = load_whirlpool_accounts(&accounts.pool())?;
+
+    let (token_owner_account_a, token_owner_account_b, sqrt_price_limit) = if pool.a_to_b {
+        (accounts.source, accounts.destination, WHIRLPOOL_MIN_SQRT_PRICE)
+    } else {
+        (accounts.destination, accounts.source, WHIRLPOOL_MAX_SQRT_PRICE)
+    };
+
+    let mut data = WHIRLPOOL_SWAP_DISCRIMINATOR.to_vec();
+    data.extend(WhirlpoolSwapInstructionData {
+        amount: input_amount,
+        other_amount_threshold: minimum_output_amount,
+        sqrt_price_limit,
+        amount_specified_is_input: true,
+        a_to_b: pool.a_to_b,
+    }.try_to_vec()?);
+
+    let swap_ix = Instruction {
+        program_id: accounts.swap_program.key(),
+        accounts: vec![
+            AccountMeta::new_readonly(accounts.token_program.key(), false),
+            AccountMeta::new_readonly(accounts.authority.key(), true),
+            AccountMeta::new(pool.whirlpool.key(), false),
+            AccountMeta::new(token_owner_account_a.key(), false),
+            AccountMeta::new(pool.token_vault_a.key(), false),
+            AccountMeta::new(token_owner_account_b.key(), false),
+            AccountMeta::new(pool.token_vault_b.key(), false),
+            AccountMeta::new(pool.tick_arrays[0].key(), false),
+            AccountMeta::new(pool.tick_arrays[1].key(), false),
+            AccountMeta::new(pool.tick_arrays[2].key(), false),
+            AccountMeta::new_readonly(pool.oracle.key(), false),
+        ],
+        data,
+    };
+    solana_program::program::invoke_signed(
+        &swap_ix,
+        &[
+            accounts.swap_program.clone(),
+            accounts.token_program.clone(),
+            accounts.authority.clone(),
+            pool.whirlpool.clone(),
+            token_owner_account_a.clone(),
+            pool.token_vault_a.clone(),
+            token_owner_account_b.clone(),
+            pool.token_vault_b.clone(),
+            pool.tick_arrays[0].clone(),
+            pool.tick_arrays[1].clone(),
+            pool.tick_arrays[2].clone(),
+            pool.oracle.clone(),
+        ],
+        signer_seeds,
+    )
+}
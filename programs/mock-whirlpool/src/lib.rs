@@ -0,0 +1,175 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, TokenAccount, Mint, Token, Transfer};
+use std::convert::TryFrom;
+
+declare_id!("AVQUep75JEzLj6ut56GvWgPaoLdu8KyaZ2MpSeDSr1sy");
+
+// This program only mimics the account layout and the swap instruction of Whirlpool.
+// Swaps are executed at the spot price (sqrt_price) without moving it.
+
+#[program]
+pub mod mock_whirlpool {
+    use super::*;
+
+    pub fn initialize_pool(ctx: Context<InitializePool>, bump: u8, sqrt_price: u128, fee_rate: u16) -> ProgramResult {
+        let whirlpool = &mut ctx.accounts.whirlpool;
+        whirlpool.whirlpool_bump = [bump];
+        whirlpool.tick_spacing = 64;
+        whirlpool.fee_rate = fee_rate;
+        whirlpool.sqrt_price = sqrt_price;
+        whirlpool.token_mint_a = ctx.accounts.token_mint_a.key();
+        whirlpool.token_vault_a = ctx.accounts.token_vault_a.key();
+        whirlpool.token_mint_b = ctx.accounts.token_mint_b.key();
+        whirlpool.token_vault_b = ctx.accounts.token_vault_b.key();
+
+        Ok(())
+    }
+
+    pub fn set_sqrt_price(ctx: Context<SetSqrtPrice>, sqrt_price: u128) -> ProgramResult {
+        ctx.accounts.whirlpool.sqrt_price = sqrt_price;
+        Ok(())
+    }
+
+    pub fn swap(
+        ctx: Context<Swap>,
+        amount: u64,
+        other_amount_threshold: u64,
+        _sqrt_price_limit: u128,
+        amount_specified_is_input: bool,
+        a_to_b: bool,
+    ) -> ProgramResult {
+        assert!(amount_specified_is_input, "mock supports exact input only");
+
+        let whirlpool = &ctx.accounts.whirlpool;
+        let ia = amount as u128;
+        let fee_numerator = ia * whirlpool.fee_rate as u128;
+        let fee_floor = fee_numerator / 1_000_000;
+        let fee = if fee_floor * 1_000_000 < fee_numerator { fee_floor + 1 } else { fee_floor };
+        let mia = ia - fee;
+        let sqrt_price = whirlpool.sqrt_price;
+        let output = if a_to_b {
+            (((mia * sqrt_price) >> 64) * sqrt_price) >> 64
+        } else {
+            (((mia << 64) / sqrt_price) << 64) / sqrt_price
+        };
+        let output = u64::try_from(output).unwrap();
+        msg!("mock swap: input {}, output {}, a_to_b {}", amount, output, a_to_b);
+
+        if output < other_amount_threshold {
+            return Err(ErrorCode::AmountOutBelowMinimum.into());
+        }
+
+        let (owner_input, vault_input, owner_output, vault_output) = if a_to_b {
+            (&ctx.accounts.token_owner_account_a, &ctx.accounts.token_vault_a, &ctx.accounts.token_owner_account_b, &ctx.accounts.token_vault_b)
+        } else {
+            (&ctx.accounts.token_owner_account_b, &ctx.accounts.token_vault_b, &ctx.accounts.token_owner_account_a, &ctx.accounts.token_vault_a)
+        };
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: owner_input.to_account_info(),
+                    to: vault_input.to_account_info(),
+                    authority: ctx.accounts.token_authority.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        let seeds = [
+            b"whirlpool".as_ref(),
+            whirlpool.token_mint_a.as_ref(),
+            whirlpool.token_mint_b.as_ref(),
+            whirlpool.whirlpool_bump.as_ref(),
+        ];
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: vault_output.to_account_info(),
+                    to: owner_output.to_account_info(),
+                    authority: whirlpool.to_account_info(),
+                },
+                &[&seeds],
+            ),
+            output,
+        )?;
+
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8)]
+pub struct InitializePool<'info> {
+    #[account(mut)]
+    pub funder: Signer<'info>,
+
+    #[account(init, payer = funder, space = 8 + 237, seeds = [b"whirlpool".as_ref(), token_mint_a.key().as_ref(), token_mint_b.key().as_ref()], bump = bump)]
+    pub whirlpool: Box<Account<'info, Whirlpool>>,
+    pub token_mint_a: Box<Account<'info, Mint>>,
+    pub token_mint_b: Box<Account<'info, Mint>>,
+    #[account(constraint = token_vault_a.mint == token_mint_a.key())]
+    pub token_vault_a: Box<Account<'info, TokenAccount>>,
+    #[account(constraint = token_vault_b.mint == token_mint_b.key())]
+    pub token_vault_b: Box<Account<'info, TokenAccount>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetSqrtPrice<'info> {
+    #[account(mut)]
+    pub whirlpool: Box<Account<'info, Whirlpool>>,
+}
+
+#[derive(Accounts)]
+pub struct Swap<'info> {
+    pub token_program: Program<'info, Token>,
+    pub token_authority: Signer<'info>,
+    #[account(mut)]
+    pub whirlpool: Box<Account<'info, Whirlpool>>,
+    #[account(mut, constraint = token_owner_account_a.mint == whirlpool.token_mint_a)]
+    pub token_owner_account_a: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = whirlpool.token_vault_a)]
+    pub token_vault_a: Box<Account<'info, TokenAccount>>,
+    #[account(mut, constraint = token_owner_account_b.mint == whirlpool.token_mint_b)]
+    pub token_owner_account_b: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = whirlpool.token_vault_b)]
+    pub token_vault_b: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub tick_array_0: AccountInfo<'info>,
+    #[account(mut)]
+    pub tick_array_1: AccountInfo<'info>,
+    #[account(mut)]
+    pub tick_array_2: AccountInfo<'info>,
+    pub oracle: AccountInfo<'info>,
+}
+
+// same layout as the head of the Whirlpool account (up to token_vault_b)
+#[account]
+pub struct Whirlpool {
+    pub whirlpools_config: Pubkey,
+    pub whirlpool_bump: [u8; 1],
+    pub tick_spacing: u16,
+    pub tick_spacing_seed: [u8; 2],
+    pub fee_rate: u16,
+    pub protocol_fee_rate: u16,
+    pub liquidity: u128,
+    pub sqrt_price: u128,
+    pub tick_current_index: i32,
+    pub protocol_fee_owed_a: u64,
+    pub protocol_fee_owed_b: u64,
+    pub token_mint_a: Pubkey,
+    pub token_vault_a: Pubkey,
+    pub fee_growth_global_a: u128,
+    pub token_mint_b: Pubkey,
+    pub token_vault_b: Pubkey,
+}
+
+#[error]
+pub enum ErrorCode {
+    #[msg("Amount out below minimum threshold")]
+    AmountOutBelowMinimum,
+}
@@ -0,0 +1,24 @@
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    program_error::PrintProgramError,
+    pubkey::Pubkey,
+};
+use spl_token_swap::{error::SwapError, processor::Processor};
+
+// SPL token swap without the "production" constraints, so that tests can create pools freely.
+
+#[cfg(not(feature = "no-entrypoint"))]
+solana_program::entrypoint!(process_instruction);
+
+pub fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    if let Err(error) = Processor::process(program_id, accounts, instruction_data) {
+        error.print::<SwapError>();
+        return Err(error);
+    }
+    Ok(())
+}
@@ -0,0 +1,169 @@
+import * as anchor from '@project-serum/anchor';
+import { Program, BN } from '@project-serum/anchor';
+import { PublicKey, Keypair, SystemProgram, SYSVAR_RENT_PUBKEY, SYSVAR_CLOCK_PUBKEY, AccountMeta } from '@solana/web3.js';
+import { TOKEN_PROGRAM_ID, NATIVE_MINT, Token } from '@solana/spl-token';
+import { assert } from 'chai';
+import { Kurayashiki } from '../target/types/kurayashiki';
+import { MockWhirlpool } from '../target/types/mock_whirlpool';
+import { KurayashikiAccounts, initializeKurayashiki } from './utils';
+
+describe('convert_to_sol (multi-hop route)', () => {
+  const provider = anchor.Provider.env();
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.Kurayashiki as Program<Kurayashiki>;
+  const mockWhirlpool = anchor.workspace.MockWhirlpool as Program<MockWhirlpool>;
+  const payer = (provider.wallet as anchor.Wallet).payer;
+
+  const FEE_RATE = 3000; // 0.3%
+
+  let k: KurayashikiAccounts;
+  let usdtToken: Token;
+  let depositUsdt: PublicKey;
+  let usdtUsdcPool: PoolAccounts;
+  let solUsdcPool: PoolAccounts;
+
+  interface PoolAccounts {
+    whirlpool: PublicKey;
+    tokenVaultA: PublicKey;
+    tokenVaultB: PublicKey;
+  }
+
+  const createPool = async (tokenA: Token, tokenB: Token, sqrtPrice: BN): Promise<PoolAccounts> => {
+    const [whirlpool, bump] = await PublicKey.findProgramAddress(
+      [Buffer.from("whirlpool"), tokenA.publicKey.toBuffer(), tokenB.publicKey.toBuffer()],
+      mockWhirlpool.programId);
+    const tokenVaultA = tokenA.publicKey.equals(NATIVE_MINT)
+      ? await Token.createWrappedNativeAccount(provider.connection, TOKEN_PROGRAM_ID, whirlpool, payer, 10 * anchor.web3.LAMPORTS_PER_SOL)
+      : await tokenA.createAccount(whirlpool);
+    const tokenVaultB = await tokenB.createAccount(whirlpool);
+    if (!tokenA.publicKey.equals(NATIVE_MINT)) {
+      await tokenA.mintTo(tokenVaultA, payer, [], 10_000_000_000);
+    }
+    await tokenB.mintTo(tokenVaultB, payer, [], 10_000_000_000);
+
+    await mockWhirlpool.rpc.initializePool(bump, sqrtPrice, FEE_RATE, {
+      accounts: {
+        funder: provider.wallet.publicKey,
+        whirlpool,
+        tokenMintA: tokenA.publicKey,
+        tokenMintB: tokenB.publicKey,
+        tokenVaultA,
+        tokenVaultB,
+        systemProgram: SystemProgram.programId,
+      },
+    });
+    return { whirlpool, tokenVaultA, tokenVaultB };
+  };
+
+  const poolAccounts = (pool: PoolAccounts): AccountMeta[] => [
+    { pubkey: pool.whirlpool, isWritable: true, isSigner: false },
+    { pubkey: pool.tokenVaultA, isWritable: true, isSigner: false },
+    { pubkey: pool.tokenVaultB, isWritable: true, isSigner: false },
+    { pubkey: Keypair.generate().publicKey, isWritable: false, isSigner: false }, // oracle
+    ...[0, 1, 2].map(() => ({ pubkey: Keypair.generate().publicKey, isWritable: true, isSigner: false })), // tick arrays
+  ];
+
+  before(async () => {
+    k = await initializeKurayashiki(program);
+
+    usdtToken = await Token.createMint(provider.connection, payer, payer.publicKey, null, 6, TOKEN_PROGRAM_ID);
+    depositUsdt = await usdtToken.createAccount(k.depositSol);
+
+    // 1 USDT = 1 USDC: sqrt_price = 2^64
+    usdtUsdcPool = await createPool(usdtToken, k.usdcToken, new BN(1).shln(64));
+    // 1 SOL = 250 USDC: sqrt_price = 0.5 * 2^64
+    const wsolToken = new Token(provider.connection, NATIVE_MINT, TOKEN_PROGRAM_ID, payer);
+    solUsdcPool = await createPool(wsolToken, k.usdcToken, new BN(1).shln(63));
+
+    await program.rpc.setSwapBackend({ whirlpool: {} }, mockWhirlpool.programId, solUsdcPool.whirlpool, {
+      accounts: {
+        creator: k.creator.publicKey,
+        config: k.config,
+      },
+      signers: [k.creator],
+    });
+
+    await usdtToken.mintTo(depositUsdt, payer, [], 10_000_000);
+  });
+
+  // USDT -> USDC (deposit_usdc) -> SOL
+  const convertToSol = async (usdcMinimum: BN, minimumOutputAmount: BN) => {
+    const route = [
+      { swapBackend: { whirlpool: {} }, minimumOutputAmount: usdcMinimum },
+      { swapBackend: { whirlpool: {} }, minimumOutputAmount: new BN(0) },
+    ];
+    await program.rpc.convertToSol(route, minimumOutputAmount, {
+      accounts: {
+        creator: k.creator.publicKey,
+        config: k.config,
+        depositSol: k.depositSol,
+        depositToken: depositUsdt,
+        temporaryDepositWsol: k.temporaryDepositWsol,
+        depositWsol: k.depositWsol,
+        poolTwap: k.poolTwap,
+        treasuryStats: k.treasuryStats,
+        wsolMint: NATIVE_MINT,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        rent: SYSVAR_RENT_PUBKEY,
+        clock: SYSVAR_CLOCK_PUBKEY,
+      },
+      remainingAccounts: [
+        { pubkey: mockWhirlpool.programId, isWritable: false, isSigner: false },
+        { pubkey: k.depositUsdc, isWritable: true, isSigner: false },
+        ...poolAccounts(usdtUsdcPool),
+        { pubkey: mockWhirlpool.programId, isWritable: false, isSigner: false },
+        ...poolAccounts(solUsdcPool),
+      ],
+    });
+  };
+
+  it('rejects a hop through a pool that is not in route_pools', async () => {
+    try {
+      await convertToSol(new BN(0), new BN(0));
+      assert.fail("convert_to_sol should fail");
+    } catch (e) {
+      assert.equal(e.msg, "Swap pool accounts do not match the configuration.");
+    }
+  });
+
+  it('rejects a route whose output is below the per-hop minimum', async () => {
+    await program.rpc.setRoutePools([{ swapBackend: { whirlpool: {} }, swapProgram: mockWhirlpool.programId, swapPool: usdtUsdcPool.whirlpool }], {
+      accounts: {
+        creator: k.creator.publicKey,
+        config: k.config,
+      },
+      signers: [k.creator],
+    });
+
+    try {
+      // the USDT -> USDC hop produces 9.97 USDC
+      await convertToSol(new BN(9_980_000), new BN(0));
+      assert.fail("convert_to_sol should fail");
+    } catch (e) {
+      assert.notEqual(e.message, "convert_to_sol should fail");
+    }
+  });
+
+  it('rejects a route whose output is below the overall minimum', async () => {
+    try {
+      await convertToSol(new BN(0), new BN(40_000_000));
+      assert.fail("convert_to_sol should fail");
+    } catch (e) {
+      assert.equal(e.msg, "Swap output is below the minimum output amount.");
+    }
+  });
+
+  it('swaps USDT into deposit_sol through USDC', async () => {
+    const preLamports = await provider.connection.getBalance(k.depositSol);
+    await convertToSol(new BN(9_970_000), new BN(39_760_360));
+    const postLamports = await provider.connection.getBalance(k.depositSol);
+
+    // 10 USDT - 0.3% fee = 9.97 USDC, 9.97 USDC - 0.3% fee = 9.94009 USDC -> 0.03976036 SOL
+    assert.equal(postLamports - preLamports, 39_760_360);
+    assert.equal((await usdtToken.getAccountInfo(depositUsdt)).amount.toNumber(), 0);
+    assert.equal((await k.usdcToken.getAccountInfo(k.depositUsdc)).amount.toNumber(), 0);
+    assert.isNull(await provider.connection.getAccountInfo(k.temporaryDepositWsol));
+  });
+});
@@ -0,0 +1,131 @@
+import * as anchor from '@project-serum/anchor';
+import { Program, BN } from '@project-serum/anchor';
+import { PublicKey, Keypair, SystemProgram, Transaction, TransactionInstruction, SYSVAR_RENT_PUBKEY, SYSVAR_CLOCK_PUBKEY } from '@solana/web3.js';
+import { TOKEN_PROGRAM_ID, NATIVE_MINT, Token } from '@solana/spl-token';
+import { assert } from 'chai';
+import { Kurayashiki } from '../target/types/kurayashiki';
+import { KurayashikiAccounts, initializeKurayashiki } from './utils';
+
+// SPL token swap deployed from programs/token-swap (see Anchor.toml)
+const TOKEN_SWAP_PROGRAM_ID = new PublicKey("39R8tVzxhyFNpx7fd9TDdUXpCLZfFFfMQ759fdFUF2Ja");
+const TOKEN_SWAP_ACCOUNT_SIZE = 324;
+
+describe('convert_to_sol (token swap backend)', () => {
+  const provider = anchor.Provider.env();
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.Kurayashiki as Program<Kurayashiki>;
+  const payer = (provider.wallet as anchor.Wallet).payer;
+
+  let k: KurayashikiAccounts;
+  const tokenSwap = Keypair.generate();
+  let swapAuthority: PublicKey;
+  let poolUsdc: PublicKey;
+  let poolWsol: PublicKey;
+  let poolMint: Token;
+  let poolFeeAccount: PublicKey;
+
+  before(async () => {
+    k = await initializeKurayashiki(program);
+
+    // token A = USDC (25,000 USDC), token B = WSOL (100 SOL)
+    let nonce: number;
+    [swapAuthority, nonce] = await PublicKey.findProgramAddress([tokenSwap.publicKey.toBuffer()], TOKEN_SWAP_PROGRAM_ID);
+    poolMint = await Token.createMint(provider.connection, payer, swapAuthority, null, 2, TOKEN_PROGRAM_ID);
+    poolFeeAccount = await poolMint.createAccount(payer.publicKey);
+    const poolTokenDestination = await poolMint.createAccount(payer.publicKey);
+    poolUsdc = await k.usdcToken.createAccount(swapAuthority);
+    await k.usdcToken.mintTo(poolUsdc, payer, [], 25_000_000_000);
+    poolWsol = await Token.createWrappedNativeAccount(provider.connection, TOKEN_PROGRAM_ID, swapAuthority, payer, 100 * anchor.web3.LAMPORTS_PER_SOL);
+
+    // Initialize: trade fee 0.4%, owner trade fee 0.1%, constant product curve
+    const data = Buffer.alloc(1 + 1 + 64 + 33);
+    data.writeUInt8(0, 0);
+    data.writeUInt8(nonce, 1);
+    const fees = [40, 10000, 10, 10000, 0, 0, 0, 0];
+    fees.forEach((f, i) => new BN(f).toArrayLike(Buffer, 'le', 8).copy(data, 2 + i * 8));
+    data.writeUInt8(0, 66); // ConstantProduct
+
+    const transaction = new Transaction();
+    transaction.add(SystemProgram.createAccount({
+      fromPubkey: payer.publicKey,
+      newAccountPubkey: tokenSwap.publicKey,
+      lamports: await provider.connection.getMinimumBalanceForRentExemption(TOKEN_SWAP_ACCOUNT_SIZE),
+      space: TOKEN_SWAP_ACCOUNT_SIZE,
+      programId: TOKEN_SWAP_PROGRAM_ID,
+    }));
+    transaction.add(new TransactionInstruction({
+      programId: TOKEN_SWAP_PROGRAM_ID,
+      keys: [
+        { pubkey: tokenSwap.publicKey, isWritable: true, isSigner: true },
+        { pubkey: swapAuthority, isWritable: false, isSigner: false },
+        { pubkey: poolUsdc, isWritable: false, isSigner: false },
+        { pubkey: poolWsol, isWritable: false, isSigner: false },
+        { pubkey: poolMint.publicKey, isWritable: true, isSigner: false },
+        { pubkey: poolFeeAccount, isWritable: false, isSigner: false },
+        { pubkey: poolTokenDestination, isWritable: true, isSigner: false },
+        { pubkey: TOKEN_PROGRAM_ID, isWritable: false, isSigner: false },
+      ],
+      data,
+    }));
+    await provider.send(transaction, [tokenSwap]);
+
+    await program.rpc.setSwapBackend({ tokenSwap: {} }, TOKEN_SWAP_PROGRAM_ID, tokenSwap.publicKey, {
+      accounts: {
+        creator: k.creator.publicKey,
+        config: k.config,
+      },
+      signers: [k.creator],
+    });
+  });
+
+  const convertToSol = async (feeAccount: PublicKey) => {
+    await program.rpc.convertToSol([], new BN(0), {
+      accounts: {
+        creator: k.creator.publicKey,
+        config: k.config,
+        depositSol: k.depositSol,
+        depositToken: k.depositUsdc,
+        temporaryDepositWsol: k.temporaryDepositWsol,
+        depositWsol: k.depositWsol,
+        poolTwap: k.poolTwap,
+        treasuryStats: k.treasuryStats,
+        wsolMint: NATIVE_MINT,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        rent: SYSVAR_RENT_PUBKEY,
+        clock: SYSVAR_CLOCK_PUBKEY,
+      },
+      remainingAccounts: [
+        { pubkey: TOKEN_SWAP_PROGRAM_ID, isWritable: false, isSigner: false },
+        { pubkey: tokenSwap.publicKey, isWritable: false, isSigner: false },
+        { pubkey: swapAuthority, isWritable: false, isSigner: false },
+        { pubkey: poolUsdc, isWritable: true, isSigner: false },
+        { pubkey: poolWsol, isWritable: true, isSigner: false },
+        { pubkey: poolMint.publicKey, isWritable: true, isSigner: false },
+        { pubkey: feeAccount, isWritable: true, isSigner: false },
+      ],
+    });
+  };
+
+  it('rejects pool accounts that do not match the SwapV1 state', async () => {
+    await k.usdcToken.mintTo(k.depositUsdc, payer, [], 25_000_000);
+    const otherFeeAccount = await poolMint.createAccount(payer.publicKey);
+    try {
+      await convertToSol(otherFeeAccount);
+      assert.fail("convert_to_sol should fail");
+    } catch (e) {
+      assert.equal(e.msg, "Swap pool accounts do not match the configuration.");
+    }
+  });
+
+  it('quotes with the fees of the pool and swaps', async () => {
+    const preLamports = await provider.connection.getBalance(k.depositSol);
+    await convertToSol(poolFeeAccount);
+    const postLamports = await provider.connection.getBalance(k.depositSol);
+
+    // 25 USDC - 0.5% fees = 24.875 USDC on a 25,000 USDC / 100 SOL constant product pool
+    assert.equal(postLamports - preLamports, 99_401_095);
+    assert.equal((await k.usdcToken.getAccountInfo(k.depositUsdc)).amount.toNumber(), 0);
+  });
+});
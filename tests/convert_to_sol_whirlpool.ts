@@ -0,0 +1,168 @@
+import * as anchor from '@project-serum/anchor';
+import { Program, BN } from '@project-serum/anchor';
+import { PublicKey, Keypair, SystemProgram, Transaction, SYSVAR_RENT_PUBKEY, SYSVAR_CLOCK_PUBKEY } from '@solana/web3.js';
+import { TOKEN_PROGRAM_ID, NATIVE_MINT, Token } from '@solana/spl-token';
+import { assert } from 'chai';
+import { Kurayashiki } from '../target/types/kurayashiki';
+import { MockWhirlpool } from '../target/types/mock_whirlpool';
+import { KurayashikiAccounts, initializeKurayashiki } from './utils';
+
+describe('convert_to_sol (whirlpool backend)', () => {
+  const provider = anchor.Provider.env();
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.Kurayashiki as Program<Kurayashiki>;
+  const mockWhirlpool = anchor.workspace.MockWhirlpool as Program<MockWhirlpool>;
+  const payer = (provider.wallet as anchor.Wallet).payer;
+
+  // 1 SOL = 250 USDC: price (B per A) = 250 * 10^6 / 10^9 = 0.25, sqrt_price = 0.5 * 2^64
+  const SQRT_PRICE = new BN(1).shln(63);
+  const FEE_RATE = 3000; // 0.3%
+
+  let k: KurayashikiAccounts;
+  let whirlpool: PublicKey;
+  let tokenVaultA: PublicKey;
+  let tokenVaultB: PublicKey;
+
+  before(async () => {
+    k = await initializeKurayashiki(program);
+
+    // mock whirlpool: token A = WSOL, token B = USDC
+    let bump: number;
+    [whirlpool, bump] = await PublicKey.findProgramAddress(
+      [Buffer.from("whirlpool"), NATIVE_MINT.toBuffer(), k.usdcToken.publicKey.toBuffer()],
+      mockWhirlpool.programId);
+    tokenVaultA = await Token.createWrappedNativeAccount(provider.connection, TOKEN_PROGRAM_ID, whirlpool, payer, 10 * anchor.web3.LAMPORTS_PER_SOL);
+    tokenVaultB = await k.usdcToken.createAccount(whirlpool);
+    await k.usdcToken.mintTo(tokenVaultB, payer, [], 10_000_000_000);
+
+    await mockWhirlpool.rpc.initializePool(bump, SQRT_PRICE, FEE_RATE, {
+      accounts: {
+        funder: provider.wallet.publicKey,
+        whirlpool,
+        tokenMintA: NATIVE_MINT,
+        tokenMintB: k.usdcToken.publicKey,
+        tokenVaultA,
+        tokenVaultB,
+        systemProgram: SystemProgram.programId,
+      },
+    });
+  });
+
+  const convertToSol = async () => {
+    const tickArrays = [Keypair.generate(), Keypair.generate(), Keypair.generate()];
+    const oracle = Keypair.generate();
+    await program.rpc.convertToSol([], new BN(0), {
+      accounts: {
+        creator: k.creator.publicKey,
+        config: k.config,
+        depositSol: k.depositSol,
+        depositToken: k.depositUsdc,
+        temporaryDepositWsol: k.temporaryDepositWsol,
+        depositWsol: k.depositWsol,
+        poolTwap: k.poolTwap,
+        treasuryStats: k.treasuryStats,
+        wsolMint: NATIVE_MINT,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        rent: SYSVAR_RENT_PUBKEY,
+        clock: SYSVAR_CLOCK_PUBKEY,
+      },
+      remainingAccounts: [
+        { pubkey: mockWhirlpool.programId, isWritable: false, isSigner: false },
+        { pubkey: whirlpool, isWritable: true, isSigner: false },
+        { pubkey: tokenVaultA, isWritable: true, isSigner: false },
+        { pubkey: tokenVaultB, isWritable: true, isSigner: false },
+        { pubkey: oracle.publicKey, isWritable: false, isSigner: false },
+        ...tickArrays.map((t) => ({ pubkey: t.publicKey, isWritable: true, isSigner: false })),
+      ],
+    });
+  };
+
+  it('rejects the whirlpool pool while the default backend is configured', async () => {
+    await k.usdcToken.mintTo(k.depositUsdc, payer, [], 25_000_000);
+    try {
+      await convertToSol();
+      assert.fail("convert_to_sol should fail");
+    } catch (e) {
+      // config.swap_program is still the ORCA token swap program.
+      assert.notEqual(e.message, "convert_to_sol should fail");
+    }
+  });
+
+  it('swaps deposit_usdc into deposit_sol through the whirlpool backend', async () => {
+    await program.rpc.setSwapBackend({ whirlpool: {} }, mockWhirlpool.programId, whirlpool, {
+      accounts: {
+        creator: k.creator.publicKey,
+        config: k.config,
+      },
+      signers: [k.creator],
+    });
+
+    const preLamports = await provider.connection.getBalance(k.depositSol);
+    await convertToSol();
+    const postLamports = await provider.connection.getBalance(k.depositSol);
+
+    // 25 USDC - 0.3% fee = 24.925 USDC -> 0.0997 SOL
+    assert.equal(postLamports - preLamports, 99_700_000);
+    assert.equal((await k.usdcToken.getAccountInfo(k.depositUsdc)).amount.toNumber(), 0);
+    assert.isNull(await provider.connection.getAccountInfo(k.temporaryDepositWsol));
+  });
+
+  it('sets the whirlpool slippage of the config', async () => {
+    assert.equal((await program.account.config.fetch(k.config)).whirlpoolSlippageBps, 100);
+
+    // the mock whirlpool swaps at the spot price, so no slippage is needed.
+    await program.rpc.setWhirlpoolSlippage(0, {
+      accounts: {
+        creator: k.creator.publicKey,
+        config: k.config,
+      },
+      signers: [k.creator],
+    });
+    assert.equal((await program.account.config.fetch(k.config)).whirlpoolSlippageBps, 0);
+
+    await k.usdcToken.mintTo(k.depositUsdc, payer, [], 25_000_000);
+    const preLamports = await provider.connection.getBalance(k.depositSol);
+    await convertToSol();
+    assert.equal(await provider.connection.getBalance(k.depositSol) - preLamports, 99_700_000);
+
+    try {
+      await program.rpc.setWhirlpoolSlippage(10_001, {
+        accounts: {
+          creator: k.creator.publicKey,
+          config: k.config,
+        },
+        signers: [k.creator],
+      });
+      assert.fail("set_whirlpool_slippage should fail");
+    } catch (e) {
+      assert.notEqual(e.message, "set_whirlpool_slippage should fail");
+    }
+  });
+
+  it('does nothing when deposit_usdc is empty', async () => {
+    const preLamports = await provider.connection.getBalance(k.depositSol);
+    await convertToSol();
+    assert.equal(await provider.connection.getBalance(k.depositSol), preLamports);
+  });
+
+  it('recovers when temporary_deposit_wsol has been funded by someone else', async () => {
+    // create_account fails if the address already has lamports.
+    await provider.send(new Transaction().add(SystemProgram.transfer({
+      fromPubkey: payer.publicKey,
+      toPubkey: k.temporaryDepositWsol,
+      lamports: 1_000_000,
+    })));
+    await k.usdcToken.mintTo(k.depositUsdc, payer, [], 25_000_000);
+
+    const preLamports = await provider.connection.getBalance(k.depositSol);
+    await convertToSol();
+    const postLamports = await provider.connection.getBalance(k.depositSol);
+
+    // the funded lamports are swept into deposit_sol with the output.
+    assert.equal(postLamports - preLamports, 99_700_000 + 1_000_000);
+    assert.equal((await k.usdcToken.getAccountInfo(k.depositUsdc)).amount.toNumber(), 0);
+    assert.isNull(await provider.connection.getAccountInfo(k.temporaryDepositWsol));
+  });
+});
@@ -0,0 +1,267 @@
+import * as anchor from '@project-serum/anchor';
+import { Program, BN } from '@project-serum/anchor';
+import { PublicKey, Keypair, SystemProgram, SYSVAR_RENT_PUBKEY, SYSVAR_CLOCK_PUBKEY } from '@solana/web3.js';
+import { TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID, NATIVE_MINT, Token } from '@solana/spl-token';
+import { assert } from 'chai';
+import { Kurayashiki } from '../target/types/kurayashiki';
+import { MockWhirlpool } from '../target/types/mock_whirlpool';
+import { KurayashikiAccounts, initializeKurayashiki } from './utils';
+
+describe('keep_wsol', () => {
+  const provider = anchor.Provider.env();
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.Kurayashiki as Program<Kurayashiki>;
+  const mockWhirlpool = anchor.workspace.MockWhirlpool as Program<MockWhirlpool>;
+  const payer = (provider.wallet as anchor.Wallet).payer;
+
+  let k: KurayashikiAccounts;
+  let wsolToken: Token;
+  let whirlpool: PublicKey;
+  let tokenVaultA: PublicKey;
+  let tokenVaultB: PublicKey;
+
+  before(async () => {
+    k = await initializeKurayashiki(program);
+    wsolToken = new Token(provider.connection, NATIVE_MINT, TOKEN_PROGRAM_ID, payer);
+
+    // mock whirlpool: token A = WSOL, token B = USDC, 1 SOL = 250 USDC, 0.3% fee
+    let bump: number;
+    [whirlpool, bump] = await PublicKey.findProgramAddress(
+      [Buffer.from("whirlpool"), NATIVE_MINT.toBuffer(), k.usdcToken.publicKey.toBuffer()],
+      mockWhirlpool.programId);
+    tokenVaultA = await Token.createWrappedNativeAccount(provider.connection, TOKEN_PROGRAM_ID, whirlpool, payer, 10 * anchor.web3.LAMPORTS_PER_SOL);
+    tokenVaultB = await k.usdcToken.createAccount(whirlpool);
+    await mockWhirlpool.rpc.initializePool(bump, new BN(1).shln(63), 3000, {
+      accounts: {
+        funder: provider.wallet.publicKey,
+        whirlpool,
+        tokenMintA: NATIVE_MINT,
+        tokenMintB: k.usdcToken.publicKey,
+        tokenVaultA,
+        tokenVaultB,
+        systemProgram: SystemProgram.programId,
+      },
+    });
+
+    await program.rpc.setSwapBackend({ whirlpool: {} }, mockWhirlpool.programId, whirlpool, {
+      accounts: {
+        creator: k.creator.publicKey,
+        config: k.config,
+      },
+      signers: [k.creator],
+    });
+
+    await program.rpc.initializeDepositWsol({
+      accounts: {
+        creator: k.creator.publicKey,
+        depositSol: k.depositSol,
+        depositWsol: k.depositWsol,
+        wsolMint: NATIVE_MINT,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        rent: SYSVAR_RENT_PUBKEY,
+      },
+      signers: [k.creator],
+    });
+
+    await program.rpc.setKeepWsol(true, {
+      accounts: {
+        creator: k.creator.publicKey,
+        config: k.config,
+      },
+      signers: [k.creator],
+    });
+  });
+
+  const findPoolAddress = async (name: string, index: number) => {
+    const indexBytes = Buffer.alloc(4);
+    indexBytes.writeUInt32LE(index);
+    const [address,] = await PublicKey.findProgramAddress(
+      [Buffer.from("kurayashiki"), Buffer.from("nano_swap"), Buffer.from(name), indexBytes, k.creator.publicKey.toBuffer()],
+      program.programId);
+    return address;
+  };
+
+  const distributeToPool = async (indexes: number[]) => {
+    const poolDepositSols = await Promise.all(indexes.map((index) => findPoolAddress("deposit_sol", index)));
+    const poolTargets = await Promise.all(indexes.map((index) => findPoolAddress("pool_target", index)));
+    await program.rpc.distributeToPool(indexes[0], indexes[1], indexes[2], indexes[3], {
+      accounts: {
+        creator: k.creator.publicKey,
+        depositSol: k.depositSol,
+        poolDepositSol1: poolDepositSols[0],
+        poolDepositSol2: poolDepositSols[1],
+        poolDepositSol3: poolDepositSols[2],
+        poolDepositSol4: poolDepositSols[3],
+        poolTarget1: poolTargets[0],
+        poolTarget2: poolTargets[1],
+        poolTarget3: poolTargets[2],
+        poolTarget4: poolTargets[3],
+        depositWsol: k.depositWsol,
+        temporaryDepositWsol: k.temporaryDepositWsol,
+        treasuryStats: k.treasuryStats,
+        wsolMint: NATIVE_MINT,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        rent: SYSVAR_RENT_PUBKEY,
+      },
+    });
+    return poolDepositSols;
+  };
+
+  it('keeps the output of convert_to_sol in deposit_wsol', async () => {
+    await k.usdcToken.mintTo(k.depositUsdc, payer, [], 250_000_000);
+
+    const preLamports = await provider.connection.getBalance(k.depositSol);
+    await program.rpc.convertToSol([], new BN(0), {
+      accounts: {
+        creator: k.creator.publicKey,
+        config: k.config,
+        depositSol: k.depositSol,
+        depositToken: k.depositUsdc,
+        temporaryDepositWsol: k.temporaryDepositWsol,
+        depositWsol: k.depositWsol,
+        poolTwap: k.poolTwap,
+        treasuryStats: k.treasuryStats,
+        wsolMint: NATIVE_MINT,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        rent: SYSVAR_RENT_PUBKEY,
+        clock: SYSVAR_CLOCK_PUBKEY,
+      },
+      remainingAccounts: [
+        { pubkey: mockWhirlpool.programId, isWritable: false, isSigner: false },
+        { pubkey: whirlpool, isWritable: true, isSigner: false },
+        { pubkey: tokenVaultA, isWritable: true, isSigner: false },
+        { pubkey: tokenVaultB, isWritable: true, isSigner: false },
+        { pubkey: Keypair.generate().publicKey, isWritable: false, isSigner: false },
+        ...[0, 1, 2].map(() => ({ pubkey: Keypair.generate().publicKey, isWritable: true, isSigner: false })),
+      ],
+    });
+
+    // 250 USDC - 0.3% fee = 249.25 USDC -> 0.997 SOL
+    assert.equal(await provider.connection.getBalance(k.depositSol), preLamports);
+    assert.equal((await wsolToken.getAccountInfo(k.depositWsol)).amount.toNumber(), 997_000_000);
+    assert.isNull(await provider.connection.getAccountInfo(k.temporaryDepositWsol));
+  });
+
+  it('unwraps only the shortfall in distribute_to_pool', async () => {
+    // pools that have not been created yet need 0.1 SOL each.
+    const preLamports = await provider.connection.getBalance(k.depositSol);
+    const poolDepositSols = await distributeToPool([100, 101, 102, 103]);
+
+    // deposit_sol holds DEPOSIT_SOL_MIN_BALANCE only, so all 0.4 SOL comes from deposit_wsol.
+    assert.equal(await provider.connection.getBalance(k.depositSol), preLamports);
+    assert.equal((await wsolToken.getAccountInfo(k.depositWsol)).amount.toNumber(), 597_000_000);
+    for (const poolDepositSol of poolDepositSols) {
+      assert.equal(await provider.connection.getBalance(poolDepositSol), 100_000_000);
+    }
+    assert.isNull(await provider.connection.getAccountInfo(k.temporaryDepositWsol));
+  });
+
+  it('refills a repeated index of a padded page once', async () => {
+    // a page of 2 pools padded by repeating the last index.
+    const poolDepositSols = await distributeToPool([104, 105, 105, 105]);
+
+    // 0.2 SOL for 2 pools
+    assert.equal((await wsolToken.getAccountInfo(k.depositWsol)).amount.toNumber(), 397_000_000);
+    assert.equal(await provider.connection.getBalance(poolDepositSols[0]), 100_000_000);
+    assert.equal(await provider.connection.getBalance(poolDepositSols[1]), 100_000_000);
+  });
+
+  it('tops a pool up to its target below its low-water mark', async () => {
+    // pool 106: busy (0.2 SOL, refill below 0.15 SOL), pool 107: idle (0.05 SOL, refill below 0.02 SOL)
+    for (const [index, target, lowWater] of [[106, 200_000_000, 150_000_000], [107, 50_000_000, 20_000_000]]) {
+      await program.rpc.initializePoolTarget(index, new BN(target), new BN(lowWater), {
+        accounts: {
+          creator: k.creator.publicKey,
+          poolTarget: await findPoolAddress("pool_target", index),
+          systemProgram: SystemProgram.programId,
+        },
+        signers: [k.creator],
+      });
+    }
+
+    const poolDepositSols = await distributeToPool([106, 107, 107, 107]);
+    assert.equal((await wsolToken.getAccountInfo(k.depositWsol)).amount.toNumber(), 147_000_000);
+    assert.equal(await provider.connection.getBalance(poolDepositSols[0]), 200_000_000);
+    assert.equal(await provider.connection.getBalance(poolDepositSols[1]), 50_000_000);
+
+    // above the low-water mark after the target is raised: no refill
+    await program.rpc.setPoolTarget(106, new BN(300_000_000), new BN(150_000_000), {
+      accounts: {
+        creator: k.creator.publicKey,
+        poolTarget: await findPoolAddress("pool_target", 106),
+      },
+      signers: [k.creator],
+    });
+    await distributeToPool([106, 107, 107, 107]);
+    assert.equal((await wsolToken.getAccountInfo(k.depositWsol)).amount.toNumber(), 147_000_000);
+    assert.equal(await provider.connection.getBalance(poolDepositSols[0]), 200_000_000);
+  });
+
+  it('rejects a low-water mark above the target', async () => {
+    try {
+      await program.rpc.setPoolTarget(106, new BN(100_000_000), new BN(150_000_000), {
+        accounts: {
+          creator: k.creator.publicKey,
+          poolTarget: await findPoolAddress("pool_target", 106),
+        },
+        signers: [k.creator],
+      });
+      assert.fail("set_pool_target should fail");
+    } catch (e) {
+      assert.equal(e.msg, "Pool target is invalid.");
+    }
+  });
+
+  it('refills the emptiest pools first and reports the shortfall', async () => {
+    // 0.4 SOL is needed, but only the 0.147 SOL left in deposit_wsol is allocatable.
+    let listener: number;
+    const event = new Promise<any>((resolve) => {
+      listener = program.addEventListener("PoolRefillShortfall", (event) => resolve(event));
+    });
+    const poolDepositSols = await distributeToPool([108, 109, 110, 111]);
+    const shortfall = await event;
+    await program.removeEventListener(listener);
+
+    assert.equal((await wsolToken.getAccountInfo(k.depositWsol)).amount.toNumber(), 0);
+    assert.equal(await provider.connection.getBalance(poolDepositSols[0]), 100_000_000);
+    assert.equal(await provider.connection.getBalance(poolDepositSols[1]), 47_000_000);
+    assert.equal(await provider.connection.getBalance(poolDepositSols[2]), 0);
+    assert.equal(await provider.connection.getBalance(poolDepositSols[3]), 0);
+    assert.deepEqual(shortfall.indexes, [108, 109, 110, 111]);
+    assert.equal(shortfall.refillLamports.toNumber(), 400_000_000);
+    assert.equal(shortfall.distributedLamports.toNumber(), 147_000_000);
+    assert.equal(shortfall.shortfallLamports.toNumber(), 253_000_000);
+  });
+
+  it('refills the padded index once when the emptiest pool is repeated', async () => {
+    // pool 112 holds 0.06 SOL, pool 113 (the emptiest, repeated by the padding) none.
+    // deposit_wsol is empty, 0.12 SOL above DEPOSIT_SOL_MIN_BALANCE is allocatable.
+    const preLamports = await provider.connection.getBalance(k.depositSol);
+    const transaction = new anchor.web3.Transaction()
+      .add(SystemProgram.transfer({ fromPubkey: payer.publicKey, toPubkey: k.depositSol, lamports: 120_000_000 }))
+      .add(SystemProgram.transfer({ fromPubkey: payer.publicKey, toPubkey: await findPoolAddress("deposit_sol", 112), lamports: 60_000_000 }));
+    await provider.send(transaction);
+
+    let listener: number;
+    const event = new Promise<any>((resolve) => {
+      listener = program.addEventListener("PoolRefillShortfall", (event) => resolve(event));
+    });
+    const poolDepositSols = await distributeToPool([112, 113, 113, 113]);
+    const shortfall = await event;
+    await program.removeEventListener(listener);
+
+    // pool 113 is refilled first and only once, pool 112 gets the rest.
+    assert.equal(await provider.connection.getBalance(poolDepositSols[1]), 100_000_000);
+    assert.equal(await provider.connection.getBalance(poolDepositSols[0]), 80_000_000);
+    assert.equal(await provider.connection.getBalance(k.depositSol), preLamports);
+    assert.deepEqual(shortfall.indexes, [112, 113, 113, 113]);
+    assert.equal(shortfall.refillLamports.toNumber(), 140_000_000);
+    assert.equal(shortfall.distributedLamports.toNumber(), 120_000_000);
+    assert.equal(shortfall.shortfallLamports.toNumber(), 20_000_000);
+  });
+});
@@ -0,0 +1,513 @@
+import * as anchor from '@project-serum/anchor';
+import { Program, BN } from '@project-serum/anchor';
+import { PublicKey, Keypair, SystemProgram, Transaction, SYSVAR_RENT_PUBKEY, SYSVAR_CLOCK_PUBKEY, AccountMeta } from '@solana/web3.js';
+import { TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID, Token } from '@solana/spl-token';
+import { assert } from 'chai';
+import { Kurayashiki } from '../target/types/kurayashiki';
+import { KurayashikiAccounts, initializeKurayashiki, findAddress } from './utils';
+
+describe('neutralize', () => {
+  const provider = anchor.Provider.env();
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.Kurayashiki as Program<Kurayashiki>;
+  const payer = (provider.wallet as anchor.Wallet).payer;
+
+  // the user does not pay the network fee in these tests, so diff_lamports is given by pre_tx_lamports.
+  const DIFF_LAMPORTS = 5_000;
+
+  let k: KurayashikiAccounts;
+  let oracleSources: PublicKey;
+  let postedPrice: PublicKey;
+  let poolDepositSol: PublicKey;
+  let poolDepositUsdc: PublicKey;
+  let poolStats: PublicKey;
+  let user: Keypair;
+  let userUsdc: PublicKey;
+
+  const setPrice = async (usdcPerSolPrice: number) => {
+    await program.rpc.postPrice(0, new BN(usdcPerSolPrice), {
+      accounts: {
+        creator: k.creator.publicKey,
+        postedPrice,
+        clock: SYSVAR_CLOCK_PUBKEY,
+      },
+      signers: [k.creator],
+    });
+    await program.rpc.updatePrice({
+      accounts: {
+        creator: k.creator.publicKey,
+        priceInfo: k.priceInfo,
+        oracleSources,
+        poolTwap: k.poolTwap,
+        priceHistory: k.priceHistory,
+        clock: SYSVAR_CLOCK_PUBKEY,
+      },
+      remainingAccounts: [{ pubkey: postedPrice, isWritable: false, isSigner: false }],
+    });
+  };
+
+  // pool 0 unless another pool is given.
+  const neutralize = async (maxPrice: number, remainingAccounts: AccountMeta[] = [], pool?: { index: number, poolDepositSol: PublicKey, poolDepositUsdc: PublicKey, poolStats: PublicKey }) => {
+    pool = pool ?? { index: 0, poolDepositSol, poolDepositUsdc, poolStats };
+    const preTxLamports = (await provider.connection.getBalance(user.publicKey)) + DIFF_LAMPORTS;
+    await program.rpc.neutralize(pool.index, new BN(maxPrice), new BN(preTxLamports), {
+      accounts: {
+        creator: k.creator.publicKey,
+        priceInfo: k.priceInfo,
+        priceHistory: k.priceHistory,
+        poolDepositSol: pool.poolDepositSol,
+        poolDepositUsdc: pool.poolDepositUsdc,
+        user: user.publicKey,
+        userUsdc,
+        poolStats: pool.poolStats,
+        usdcMint: k.usdcToken.publicKey,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        clock: SYSVAR_CLOCK_PUBKEY,
+      },
+      remainingAccounts,
+      signers: [user],
+    });
+  };
+
+  // simulates quote_neutralize and decodes NeutralizeQuote from "Program return: <program id> <base64>".
+  const quoteNeutralize = async (lamports: number) => {
+    const tx = program.transaction.quoteNeutralize(new BN(lamports), {
+      accounts: {
+        creator: k.creator.publicKey,
+        priceInfo: k.priceInfo,
+        clock: SYSVAR_CLOCK_PUBKEY,
+      },
+    });
+    tx.feePayer = payer.publicKey;
+    tx.recentBlockhash = (await provider.connection.getRecentBlockhash()).blockhash;
+    const { value } = await provider.connection.simulateTransaction(tx);
+    assert.isNull(value.err);
+
+    const prefix = `Program return: ${program.programId.toBase58()} `;
+    const data = Buffer.from(value.logs.find((log) => log.startsWith(prefix)).slice(prefix.length), 'base64');
+    return {
+      requiredUsdc: new BN(data.slice(0, 8), 'le').toNumber(),
+      usdcPerSolPrice: new BN(data.slice(8, 16), 'le').toNumber(),
+      expiry: new BN(data.slice(16, 24), 'le').toNumber(),
+    };
+  };
+
+  const usdcBalance = async (address: PublicKey): Promise<number> => {
+    return (await k.usdcToken.getAccountInfo(address)).amount.toNumber();
+  };
+
+  before(async () => {
+    k = await initializeKurayashiki(program);
+
+    // a posted price source
+    oracleSources = await findAddress(program, "oracle_sources", k.creator.publicKey);
+    const indexBytes = Buffer.alloc(4);
+    [postedPrice,] = await PublicKey.findProgramAddress(
+      [Buffer.from("kurayashiki"), Buffer.from("nano_swap"), Buffer.from("posted_price"), indexBytes, k.creator.publicKey.toBuffer()],
+      program.programId);
+    await program.rpc.initializeOracleSources({
+      accounts: {
+        creator: k.creator.publicKey,
+        oracleSources,
+        systemProgram: SystemProgram.programId,
+      },
+      signers: [k.creator],
+    });
+    await program.rpc.initializePostedPrice(0, {
+      accounts: {
+        creator: k.creator.publicKey,
+        postedPrice,
+        systemProgram: SystemProgram.programId,
+      },
+      signers: [k.creator],
+    });
+    await program.rpc.setOracleSources([{ sourceType: { posted: {} }, address: postedPrice, weight: 1, maxStalenessSlots: new BN(1000) }], 1, {
+      accounts: {
+        creator: k.creator.publicKey,
+        oracleSources,
+      },
+      signers: [k.creator],
+    });
+
+    // pool 0
+    [poolDepositSol,] = await PublicKey.findProgramAddress(
+      [Buffer.from("kurayashiki"), Buffer.from("nano_swap"), Buffer.from("deposit_sol"), indexBytes, k.creator.publicKey.toBuffer()],
+      program.programId);
+    poolDepositUsdc = await Token.getAssociatedTokenAddress(ASSOCIATED_TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, k.usdcToken.publicKey, poolDepositSol, true);
+    [poolStats,] = await PublicKey.findProgramAddress(
+      [Buffer.from("kurayashiki"), Buffer.from("nano_swap"), Buffer.from("pool_stats"), indexBytes, k.creator.publicKey.toBuffer()],
+      program.programId);
+    await program.rpc.createPool(0, {
+      accounts: {
+        creator: k.creator.publicKey,
+        poolDepositSol,
+        poolDepositUsdc,
+        poolStats,
+        usdcMint: k.usdcToken.publicKey,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        rent: SYSVAR_RENT_PUBKEY,
+      },
+      signers: [k.creator],
+    });
+
+    // a user with USDC
+    user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, anchor.web3.LAMPORTS_PER_SOL));
+    userUsdc = await k.usdcToken.createAccount(user.publicKey);
+    await k.usdcToken.mintTo(userUsdc, payer, [], 1_000_000);
+
+    // 100 USDC per SOL (doubled)
+    await setPrice(100_000_000);
+  });
+
+  // regression: a zero price must not be accepted (SOL for free).
+  it('rejects a zero price', async () => {
+    try {
+      await setPrice(0);
+      assert.fail("post_price should fail");
+    } catch (e) {
+      assert.equal(e.msg, "Invalid price is requested.");
+    }
+    assert.equal(await provider.connection.getBalance(poolDepositSol), 100_000_000);
+  });
+
+  // the first price of price_info is set in before.
+  it('does not start a grace period for a zero old price', async () => {
+    const priceInfo = await program.account.priceInfo.fetch(k.priceInfo);
+    assert.equal(priceInfo.oldUsdcPerSolPrice.toNumber(), 0);
+    assert.equal(priceInfo.oldUsdcPerSolPriceGracePeriod.toNumber(), 0);
+  });
+
+  it('charges the current price if it is below max_price', async () => {
+    const preUsdc = await usdcBalance(userUsdc);
+    await neutralize(250_000_000);
+
+    // 5000 lamports * 200 USDC per SOL = 1000 micro USDC
+    assert.equal(preUsdc - await usdcBalance(userUsdc), 1_000);
+    assert.equal(await usdcBalance(poolDepositUsdc), 1_000);
+  });
+
+  it('counts the neutralize in pool_stats', async () => {
+    const stats = await program.account.poolStats.fetch(poolStats);
+    assert.equal(stats.index, 0);
+    assert.equal(stats.neutralizeCount.toNumber(), 1);
+    assert.equal(stats.lamportsPaid.toNumber(), DIFF_LAMPORTS);
+    assert.equal(stats.usdcReceived.toNumber(), 1_000);
+    assert.isAbove(stats.lastUsedSlot.toNumber(), 0);
+  });
+
+  it('advances the pool cursor from the crank', async () => {
+    await program.rpc.initializePoolCursor(2, {
+      accounts: {
+        creator: k.creator.publicKey,
+        poolCursor: k.poolCursor,
+        systemProgram: SystemProgram.programId,
+      },
+      signers: [k.creator],
+    });
+    assert.equal((await program.account.poolCursor.fetch(k.poolCursor)).nextIndex, 0);
+
+    // neutralize does not lock the cursor.
+    await neutralize(250_000_000);
+    assert.equal((await program.account.poolCursor.fetch(k.poolCursor)).nextIndex, 0);
+
+    await program.rpc.advancePoolCursor({
+      accounts: {
+        creator: k.creator.publicKey,
+        poolCursor: k.poolCursor,
+      },
+    });
+    const poolCursor = await program.account.poolCursor.fetch(k.poolCursor);
+    assert.equal(poolCursor.numPools, 2);
+    assert.equal(poolCursor.nextIndex, 1);
+  });
+
+  it('quotes the charge of neutralize', async () => {
+    const quote = await quoteNeutralize(DIFF_LAMPORTS);
+    const priceInfo = await program.account.priceInfo.fetch(k.priceInfo);
+    assert.equal(quote.usdcPerSolPrice, 200_000_000);
+    assert.equal(quote.expiry, priceInfo.currentUsdcPerSolPriceUpdated.toNumber() + 60 * 60 * 72);
+
+    const preUsdc = await usdcBalance(userUsdc);
+    await neutralize(quote.usdcPerSolPrice);
+    assert.equal(preUsdc - await usdcBalance(userUsdc), quote.requiredUsdc);
+
+    // rounded up
+    assert.equal((await quoteNeutralize(1)).requiredUsdc, 1);
+  });
+
+  it('rejects the current price above max_price', async () => {
+    try {
+      await neutralize(199_999_999);
+      assert.fail("neutralize should fail");
+    } catch (e) {
+      assert.equal(e.msg, "Current price exceeds max_price.");
+    }
+  });
+
+  it('charges the updated price if the cached price is still acceptable', async () => {
+    // the client cached 200 USDC per SOL, then the price drops.
+    await setPrice(90_000_000);
+
+    const preUsdc = await usdcBalance(userUsdc);
+    await neutralize(200_000_000);
+
+    // 5000 lamports * 180 USDC per SOL = 900 micro USDC
+    assert.equal(preUsdc - await usdcBalance(userUsdc), 900);
+  });
+
+  // regression: the old price within the grace period was accepted, but required_usdc was computed from the current price.
+  it('applies the price it validated in the grace period', async () => {
+    // current 180 USDC per SOL, old 200 USDC per SOL (within the grace period)
+    const priceInfo = await program.account.priceInfo.fetch(k.priceInfo);
+    assert.equal(priceInfo.oldUsdcPerSolPrice.toNumber(), 200_000_000);
+    assert.isAbove(priceInfo.oldUsdcPerSolPriceGracePeriod.toNumber(), priceInfo.currentUsdcPerSolPriceUpdated.toNumber());
+
+    let listener: number;
+    const event = new Promise<any>((resolve) => {
+      listener = program.addEventListener("Neutralized", (event) => resolve(event));
+    });
+    const preUsdc = await usdcBalance(userUsdc);
+    await neutralize(priceInfo.oldUsdcPerSolPrice.toNumber());
+    const neutralized = await event;
+    await program.removeEventListener(listener);
+
+    assert.equal(neutralized.usdcPerSolPrice.toNumber(), 180_000_000);
+    assert.equal(neutralized.maxPrice.toNumber(), 200_000_000);
+    assert.equal(neutralized.requiredUsdc.toNumber(), 900);
+    assert.equal(preUsdc - await usdcBalance(userUsdc), neutralized.requiredUsdc.toNumber());
+  });
+
+  it('does not charge a higher current price for the old price within the grace period', async () => {
+    // current 220 USDC per SOL, old 180 USDC per SOL (within the grace period)
+    await setPrice(110_000_000);
+
+    const preUsdc = await usdcBalance(userUsdc);
+    try {
+      await neutralize(180_000_000);
+      assert.fail("neutralize should fail");
+    } catch (e) {
+      assert.equal(e.msg, "Current price exceeds max_price.");
+    }
+    assert.equal(await usdcBalance(userUsdc), preUsdc);
+  });
+
+  describe('integrator fee', () => {
+    const integrator = Keypair.generate();
+    let integratorFee: PublicKey;
+    let integratorFeeUsdc: PublicKey;
+    let integratorUsdc: PublicKey;
+
+    before(async () => {
+      [integratorFee,] = await PublicKey.findProgramAddress(
+        [Buffer.from("kurayashiki"), Buffer.from("nano_swap"), Buffer.from("integrator_fee"), integrator.publicKey.toBuffer(), k.creator.publicKey.toBuffer()],
+        program.programId);
+      integratorFeeUsdc = await Token.getAssociatedTokenAddress(ASSOCIATED_TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, k.usdcToken.publicKey, integratorFee, true);
+      integratorUsdc = await k.usdcToken.createAccount(integrator.publicKey);
+
+      // 20%
+      await program.rpc.registerIntegrator(integrator.publicKey, 2_000, {
+        accounts: {
+          creator: k.creator.publicKey,
+          integratorFee,
+          integratorFeeUsdc,
+          usdcMint: k.usdcToken.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          rent: SYSVAR_RENT_PUBKEY,
+        },
+        signers: [k.creator],
+      });
+    });
+
+    it('pays a share of the charge to the integrator', async () => {
+      const preUsdc = await usdcBalance(userUsdc);
+      const prePoolUsdc = await usdcBalance(poolDepositUsdc);
+      await neutralize(220_000_000, [
+        { pubkey: integratorFee, isWritable: true, isSigner: false },
+        { pubkey: integratorFeeUsdc, isWritable: true, isSigner: false },
+      ]);
+
+      // 5000 lamports * 220 USDC per SOL = 1100 micro USDC, 20% to the integrator
+      assert.equal(preUsdc - await usdcBalance(userUsdc), 1_100);
+      assert.equal(await usdcBalance(poolDepositUsdc) - prePoolUsdc, 880);
+      assert.equal(await usdcBalance(integratorFeeUsdc), 220);
+      assert.equal((await program.account.integratorFee.fetch(integratorFee)).accrued.toNumber(), 220);
+    });
+
+    it('rejects an integrator_fee_usdc that is not of integrator_fee', async () => {
+      try {
+        await neutralize(220_000_000, [
+          { pubkey: integratorFee, isWritable: true, isSigner: false },
+          { pubkey: integratorUsdc, isWritable: true, isSigner: false },
+        ]);
+        assert.fail("neutralize should fail");
+      } catch (e) {
+        assert.equal(e.msg, "Integrator accounts are invalid.");
+      }
+    });
+
+    it('lets the integrator claim the accrued fee', async () => {
+      await program.rpc.claimIntegratorFee({
+        accounts: {
+          creator: k.creator.publicKey,
+          integrator: integrator.publicKey,
+          integratorFee,
+          integratorFeeUsdc,
+          integratorUsdc,
+          usdcMint: k.usdcToken.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        },
+        signers: [integrator],
+      });
+
+      assert.equal(await usdcBalance(integratorUsdc), 220);
+      assert.equal(await usdcBalance(integratorFeeUsdc), 0);
+      assert.equal((await program.account.integratorFee.fetch(integratorFee)).claimed.toNumber(), 220);
+    });
+  });
+
+  describe('treasury stats', () => {
+    before(async () => {
+      await program.rpc.initializeTreasuryStats({
+        accounts: {
+          creator: k.creator.publicKey,
+          treasuryStats: k.treasuryStats,
+          usdcMint: k.usdcToken.publicKey,
+          systemProgram: SystemProgram.programId,
+        },
+        signers: [k.creator],
+      });
+    });
+
+    it('accumulates the SOL reimbursed and the USDC received', async () => {
+      await neutralize(220_000_000);
+      await neutralize(220_000_000);
+
+      // neutralize leaves the totals in pool_stats until collect_from_pool (all neutralize of pool 0 so far).
+      const preStats = await program.account.poolStats.fetch(poolStats);
+      assert.equal(preStats.unreportedLamportsPaid.toNumber(), preStats.lamportsPaid.toNumber());
+      assert.equal(preStats.unreportedUsdcReceived.toNumber(), preStats.usdcReceived.toNumber());
+      assert.equal(preStats.unreportedIntegratorFees.toNumber(), preStats.integratorFees.toNumber());
+      assert.equal((await program.account.treasuryStats.fetch(k.treasuryStats)).lamportsReimbursed.toNumber(), 0);
+
+      // a page of pool 0 only (padded by repeating the index)
+      await program.rpc.collectFromPool(0, 0, 0, 0, {
+        accounts: {
+          creator: k.creator.publicKey,
+          depositSol: k.depositSol,
+          depositUsdc: k.depositUsdc,
+          poolDepositSol1: poolDepositSol,
+          poolDepositUsdc1: poolDepositUsdc,
+          poolDepositSol2: poolDepositSol,
+          poolDepositUsdc2: poolDepositUsdc,
+          poolDepositSol3: poolDepositSol,
+          poolDepositUsdc3: poolDepositUsdc,
+          poolDepositSol4: poolDepositSol,
+          poolDepositUsdc4: poolDepositUsdc,
+          poolStats1: poolStats,
+          poolStats2: poolStats,
+          poolStats3: poolStats,
+          poolStats4: poolStats,
+          treasuryStats: k.treasuryStats,
+          usdcMint: k.usdcToken.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        },
+      });
+      const postStats = await program.account.poolStats.fetch(poolStats);
+      assert.equal(postStats.unreportedLamportsPaid.toNumber(), 0);
+      assert.equal(postStats.unreportedUsdcReceived.toNumber(), 0);
+      assert.equal(postStats.unreportedIntegratorFees.toNumber(), 0);
+      assert.equal(postStats.lamportsPaid.toNumber(), preStats.lamportsPaid.toNumber());
+
+      // the last 2 are 2 x (5000 lamports * 220 USDC per SOL = 1100 micro USDC)
+      const treasuryStats = await program.account.treasuryStats.fetch(k.treasuryStats);
+      assert.isTrue(treasuryStats.usdcMint.equals(k.usdcToken.publicKey));
+      assert.isAtLeast(treasuryStats.lamportsReimbursed.toNumber(), 2 * DIFF_LAMPORTS);
+      assert.equal(treasuryStats.lamportsReimbursed.toNumber(), preStats.lamportsPaid.toNumber());
+      assert.equal(treasuryStats.usdcReceived.toNumber(), preStats.usdcReceived.toNumber());
+      assert.equal(treasuryStats.integratorFees.toNumber(), preStats.integratorFees.toNumber());
+      assert.equal(treasuryStats.usdcCollected.toNumber(), preStats.usdcReceived.toNumber());
+      assert.equal(treasuryStats.realizedSpread.toNumber(), 0);
+    });
+  });
+
+  describe('depleted pool', () => {
+    // pool 1 without SOL (pool_deposit_usdc and pool_stats only)
+    const index = 1;
+    let depletedPool: { index: number, poolDepositSol: PublicKey, poolDepositUsdc: PublicKey, poolStats: PublicKey };
+    let fallbackAccounts: AccountMeta[];
+
+    before(async () => {
+      const indexBytes = Buffer.alloc(4);
+      indexBytes.writeUInt32LE(index);
+      const [depletedPoolDepositSol,] = await PublicKey.findProgramAddress(
+        [Buffer.from("kurayashiki"), Buffer.from("nano_swap"), Buffer.from("deposit_sol"), indexBytes, k.creator.publicKey.toBuffer()],
+        program.programId);
+      const [depletedPoolStats,] = await PublicKey.findProgramAddress(
+        [Buffer.from("kurayashiki"), Buffer.from("nano_swap"), Buffer.from("pool_stats"), indexBytes, k.creator.publicKey.toBuffer()],
+        program.programId);
+      const depletedPoolDepositUsdc = await Token.getAssociatedTokenAddress(ASSOCIATED_TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, k.usdcToken.publicKey, depletedPoolDepositSol, true);
+      await provider.send(new Transaction().add(Token.createAssociatedTokenAccountInstruction(
+        ASSOCIATED_TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, k.usdcToken.publicKey, depletedPoolDepositUsdc, depletedPoolDepositSol, payer.publicKey)));
+      depletedPool = {
+        index,
+        poolDepositSol: depletedPoolDepositSol,
+        poolDepositUsdc: depletedPoolDepositUsdc,
+        poolStats: depletedPoolStats,
+      };
+      await program.rpc.initializePoolStats(index, {
+        accounts: {
+          creator: k.creator.publicKey,
+          poolStats: depletedPoolStats,
+          systemProgram: SystemProgram.programId,
+        },
+        signers: [k.creator],
+      });
+
+      // pool 0 as the fallback pool
+      fallbackAccounts = [
+        { pubkey: poolStats, isWritable: true, isSigner: false },
+        { pubkey: poolDepositSol, isWritable: true, isSigner: false },
+        { pubkey: poolDepositUsdc, isWritable: true, isSigner: false },
+      ];
+    });
+
+    it('rejects a pool that cannot stay rent exempt', async () => {
+      try {
+        await neutralize(220_000_000, [], depletedPool);
+        assert.fail("neutralize should fail");
+      } catch (e) {
+        assert.equal(e.msg, "Pool cannot pay without losing rent exemption.");
+      }
+    });
+
+    it('falls back to the pool passed in remaining accounts', async () => {
+      const preLamports = await provider.connection.getBalance(poolDepositSol);
+      const preCount = (await program.account.poolStats.fetch(poolStats)).neutralizeCount.toNumber();
+      const prePoolUsdc = await usdcBalance(poolDepositUsdc);
+      await neutralize(220_000_000, fallbackAccounts, depletedPool);
+
+      assert.equal(preLamports - await provider.connection.getBalance(poolDepositSol), DIFF_LAMPORTS);
+      assert.equal(await usdcBalance(poolDepositUsdc) - prePoolUsdc, 1_100);
+      assert.equal(await usdcBalance(depletedPool.poolDepositUsdc), 0);
+      assert.equal((await program.account.poolStats.fetch(poolStats)).neutralizeCount.toNumber(), preCount + 1);
+      assert.equal((await program.account.poolStats.fetch(depletedPool.poolStats)).neutralizeCount.toNumber(), 0);
+    });
+
+    it('rejects a fallback pool that does not match its pool_stats', async () => {
+      try {
+        await neutralize(220_000_000, [fallbackAccounts[0], { pubkey: depletedPool.poolDepositSol, isWritable: true, isSigner: false }, fallbackAccounts[2]], depletedPool);
+        assert.fail("neutralize should fail");
+      } catch (e) {
+        assert.equal(e.msg, "Fallback pool accounts are invalid.");
+      }
+    });
+  });
+});
@@ -0,0 +1,276 @@
+import * as anchor from '@project-serum/anchor';
+import { Program, BN } from '@project-serum/anchor';
+import { PublicKey, SystemProgram, SYSVAR_CLOCK_PUBKEY } from '@solana/web3.js';
+import { assert } from 'chai';
+import { Kurayashiki } from '../target/types/kurayashiki';
+import { KurayashikiAccounts, initializeKurayashiki, findAddress } from './utils';
+
+describe('oracle sources', () => {
+  const provider = anchor.Provider.env();
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.Kurayashiki as Program<Kurayashiki>;
+
+  let k: KurayashikiAccounts;
+  let oracleSources: PublicKey;
+  const postedPrices: PublicKey[] = [];
+
+  const findPostedPrice = async (index: number): Promise<PublicKey> => {
+    const indexBytes = Buffer.alloc(4);
+    indexBytes.writeUInt32LE(index);
+    const [address,] = await PublicKey.findProgramAddress(
+      [Buffer.from("kurayashiki"), Buffer.from("nano_swap"), Buffer.from("posted_price"), indexBytes, k.creator.publicKey.toBuffer()],
+      program.programId);
+    return address;
+  };
+
+  const postedSource = (address: PublicKey) => ({
+    sourceType: { posted: {} },
+    address,
+    weight: 1,
+    maxStalenessSlots: new BN(1000),
+  });
+
+  const setOracleSources = async (addresses: PublicKey[], minValidSources: number) => {
+    await program.rpc.setOracleSources(addresses.map(postedSource), minValidSources, {
+      accounts: {
+        creator: k.creator.publicKey,
+        oracleSources,
+      },
+      signers: [k.creator],
+    });
+  };
+
+  const updatePrice = async (addresses: PublicKey[]) => {
+    await program.rpc.updatePrice({
+      accounts: {
+        creator: k.creator.publicKey,
+        priceInfo: k.priceInfo,
+        oracleSources,
+        poolTwap: k.poolTwap,
+        priceHistory: k.priceHistory,
+        clock: SYSVAR_CLOCK_PUBKEY,
+      },
+      remainingAccounts: addresses.map((pubkey) => ({ pubkey, isWritable: false, isSigner: false })),
+    });
+  };
+
+  before(async () => {
+    k = await initializeKurayashiki(program);
+    oracleSources = await findAddress(program, "oracle_sources", k.creator.publicKey);
+
+    await program.rpc.initializeOracleSources({
+      accounts: {
+        creator: k.creator.publicKey,
+        oracleSources,
+        systemProgram: SystemProgram.programId,
+      },
+      signers: [k.creator],
+    });
+
+    // index 3 is never posted (unavailable)
+    const prices = [100_000_000, 102_000_000, 250_000_000];
+    for (let index = 0; index < 4; index++) {
+      const postedPrice = await findPostedPrice(index);
+      postedPrices.push(postedPrice);
+      await program.rpc.initializePostedPrice(index, {
+        accounts: {
+          creator: k.creator.publicKey,
+          postedPrice,
+          systemProgram: SystemProgram.programId,
+        },
+        signers: [k.creator],
+      });
+      if (index < prices.length) {
+        await program.rpc.postPrice(index, new BN(prices[index]), {
+          accounts: {
+            creator: k.creator.publicKey,
+            postedPrice,
+            clock: SYSVAR_CLOCK_PUBKEY,
+          },
+          signers: [k.creator],
+        });
+      }
+    }
+  });
+
+  it('uses the median of the sources', async () => {
+    await setOracleSources(postedPrices.slice(0, 3), 3);
+    await updatePrice(postedPrices.slice(0, 3));
+
+    // median 102 USDC, doubled
+    const priceInfo = await program.account.priceInfo.fetch(k.priceInfo);
+    assert.equal(priceInfo.currentUsdcPerSolPrice.toNumber(), 204_000_000);
+  });
+
+  it('rejects source accounts that do not match oracle_sources', async () => {
+    try {
+      await updatePrice([postedPrices[1], postedPrices[0], postedPrices[2]]);
+      assert.fail("update_price should fail");
+    } catch (e) {
+      assert.equal(e.msg, "Oracle sources are invalid.");
+    }
+  });
+
+  it('fails if fewer than min_valid_sources are available', async () => {
+    const addresses = [postedPrices[0], postedPrices[1], postedPrices[3]];
+    await setOracleSources(addresses, 3);
+    try {
+      await updatePrice(addresses);
+      assert.fail("update_price should fail");
+    } catch (e) {
+      assert.equal(e.msg, "Not enough oracle sources are available at the moment.");
+    }
+  });
+
+  const setOracleSourcesWithWeights = async (addresses: PublicKey[], weights: number[], minValidSources: number) => {
+    const sources = addresses.map(postedSource);
+    sources.forEach((source, i) => source.weight = weights[i]);
+    await program.rpc.setOracleSources(sources, minValidSources, {
+      accounts: {
+        creator: k.creator.publicKey,
+        oracleSources,
+      },
+      signers: [k.creator],
+    });
+  };
+
+  it('rejects min_valid_sources above the number of enabled sources', async () => {
+    try {
+      await setOracleSourcesWithWeights(postedPrices.slice(0, 3), [1, 1, 0], 3);
+      assert.fail("set_oracle_sources should fail");
+    } catch (e) {
+      assert.equal(e.msg, "Oracle sources are invalid.");
+    }
+  });
+
+  it('does not count a disabled source toward min_valid_sources', async () => {
+    // index 1 is disabled and index 3 is unavailable
+    const addresses = [postedPrices[0], postedPrices[1], postedPrices[3]];
+    await setOracleSourcesWithWeights(addresses, [1, 0, 1], 2);
+    try {
+      await updatePrice(addresses);
+      assert.fail("update_price should fail");
+    } catch (e) {
+      assert.equal(e.msg, "Not enough oracle sources are available at the moment.");
+    }
+  });
+
+  it('falls back to the available sources', async () => {
+    const addresses = [postedPrices[0], postedPrices[1], postedPrices[3]];
+    await setOracleSources(addresses, 2);
+    await updatePrice(addresses);
+
+    // lower median of 100 and 102 USDC, doubled
+    const priceInfo = await program.account.priceInfo.fetch(k.priceInfo);
+    assert.equal(priceInfo.currentUsdcPerSolPrice.toNumber(), 200_000_000);
+  });
+
+  const setPriceManual = async (usdcPerSolPrice: number) => {
+    await program.rpc.setPriceManual(new BN(usdcPerSolPrice), {
+      accounts: {
+        creator: k.creator.publicKey,
+        priceInfo: k.priceInfo,
+        oracleSources,
+        priceHistory: k.priceHistory,
+        clock: SYSVAR_CLOCK_PUBKEY,
+      },
+      signers: [k.creator],
+    });
+  };
+
+  it('rejects a manual price outside of the bounds', async () => {
+    // the last oracle price is 100 USDC, the default bound is 5%.
+    try {
+      await setPriceManual(106_000_000);
+      assert.fail("set_price_manual should fail");
+    } catch (e) {
+      assert.equal(e.msg, "Manual price is out of bounds of the last oracle price.");
+    }
+  });
+
+  it('sets a manual price that expires', async () => {
+    await setPriceManual(104_000_000);
+
+    const priceInfo = await program.account.priceInfo.fetch(k.priceInfo);
+    assert.equal(priceInfo.currentUsdcPerSolPrice.toNumber(), 208_000_000);
+    assert.equal(priceInfo.oldUsdcPerSolPrice.toNumber(), 200_000_000);
+    assert.equal(priceInfo.lastOracleUsdcPerSolPrice.toNumber(), 200_000_000);
+    assert.equal(priceInfo.manualPriceExpiry.toNumber(), priceInfo.currentUsdcPerSolPriceUpdated.toNumber() + 60 * 60 * 6);
+  });
+
+  it('clears the manual price on the next oracle update', async () => {
+    const addresses = [postedPrices[0], postedPrices[1], postedPrices[3]];
+    await updatePrice(addresses);
+
+    const priceInfo = await program.account.priceInfo.fetch(k.priceInfo);
+    assert.equal(priceInfo.currentUsdcPerSolPrice.toNumber(), 200_000_000);
+    assert.equal(priceInfo.manualPriceExpiry.toNumber(), 0);
+  });
+
+  it('leaves a price_info of the current layout unchanged on migrate_price_info', async () => {
+    const preAccount = await provider.connection.getAccountInfo(k.priceInfo);
+    await program.rpc.migratePriceInfo({
+      accounts: {
+        creator: k.creator.publicKey,
+        priceInfo: k.priceInfo,
+        systemProgram: SystemProgram.programId,
+      },
+      signers: [k.creator],
+    });
+
+    const account = await provider.connection.getAccountInfo(k.priceInfo);
+    assert.equal(account.data.length, 48 + 8);
+    assert.ok(account.data.equals(preAccount.data));
+    assert.equal(account.lamports, preAccount.lamports);
+  });
+
+  const postPrice = async (index: number, usdcPerSolPrice: number) => {
+    await program.rpc.postPrice(index, new BN(usdcPerSolPrice), {
+      accounts: {
+        creator: k.creator.publicKey,
+        postedPrice: postedPrices[index],
+        clock: SYSVAR_CLOCK_PUBKEY,
+      },
+      signers: [k.creator],
+    });
+  };
+
+  it('keeps the current price and the grace period within min_update_interval', async () => {
+    // 1 hour or 1%
+    await program.rpc.setUpdateRules(new BN(60 * 60), 100, {
+      accounts: {
+        creator: k.creator.publicKey,
+        oracleSources,
+      },
+      signers: [k.creator],
+    });
+
+    const prePriceInfo = await program.account.priceInfo.fetch(k.priceInfo);
+    const addresses = [postedPrices[0], postedPrices[1], postedPrices[3]];
+    try {
+      // 101 USDC (doubled) moves 0.5% only
+      await postPrice(0, 100_500_000);
+      await postPrice(1, 101_000_000);
+      await updatePrice(addresses);
+      assert.fail("update_price should fail");
+    } catch (e) {
+      assert.equal(e.msg, "Price has not changed enough since the last update.");
+    }
+
+    const priceInfo = await program.account.priceInfo.fetch(k.priceInfo);
+    assert.equal(priceInfo.currentUsdcPerSolPrice.toNumber(), prePriceInfo.currentUsdcPerSolPrice.toNumber());
+    assert.equal(priceInfo.oldUsdcPerSolPriceGracePeriod.toNumber(), prePriceInfo.oldUsdcPerSolPriceGracePeriod.toNumber());
+  });
+
+  it('accepts an update if the price moves beyond min_update_deviation_bps', async () => {
+    // lower median 103 USDC (doubled) moves 3%
+    await postPrice(0, 103_000_000);
+    await postPrice(1, 104_000_000);
+    await updatePrice([postedPrices[0], postedPrices[1], postedPrices[3]]);
+
+    const priceInfo = await program.account.priceInfo.fetch(k.priceInfo);
+    assert.equal(priceInfo.currentUsdcPerSolPrice.toNumber(), 206_000_000);
+    assert.equal(priceInfo.oldUsdcPerSolPrice.toNumber(), 200_000_000);
+  });
+});
@@ -0,0 +1,176 @@
+import * as anchor from '@project-serum/anchor';
+import { Program, BN } from '@project-serum/anchor';
+import { PublicKey, Keypair, SystemProgram, SYSVAR_RENT_PUBKEY, SYSVAR_CLOCK_PUBKEY, AccountMeta } from '@solana/web3.js';
+import { TOKEN_PROGRAM_ID, NATIVE_MINT, Token } from '@solana/spl-token';
+import { assert } from 'chai';
+import { Kurayashiki } from '../target/types/kurayashiki';
+import { MockWhirlpool } from '../target/types/mock_whirlpool';
+import { KurayashikiAccounts, initializeKurayashiki, findAddress } from './utils';
+
+describe('pool_twap', () => {
+  const provider = anchor.Provider.env();
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.Kurayashiki as Program<Kurayashiki>;
+  const mockWhirlpool = anchor.workspace.MockWhirlpool as Program<MockWhirlpool>;
+  const payer = (provider.wallet as anchor.Wallet).payer;
+
+  let k: KurayashikiAccounts;
+  let oracleSources: PublicKey;
+  let postedPrice: PublicKey;
+  let whirlpool: PublicKey;
+  let tokenVaultA: PublicKey;
+  let tokenVaultB: PublicKey;
+
+  const swapAccounts = (): AccountMeta[] => [
+    { pubkey: mockWhirlpool.programId, isWritable: false, isSigner: false },
+    { pubkey: whirlpool, isWritable: true, isSigner: false },
+    { pubkey: tokenVaultA, isWritable: true, isSigner: false },
+    { pubkey: tokenVaultB, isWritable: true, isSigner: false },
+    { pubkey: Keypair.generate().publicKey, isWritable: false, isSigner: false },
+    ...[0, 1, 2].map(() => ({ pubkey: Keypair.generate().publicKey, isWritable: true, isSigner: false })),
+  ];
+
+  const updatePrice = async (remainingAccounts: AccountMeta[]) => {
+    await program.rpc.updatePrice({
+      accounts: {
+        creator: k.creator.publicKey,
+        priceInfo: k.priceInfo,
+        oracleSources,
+        poolTwap: k.poolTwap,
+        priceHistory: k.priceHistory,
+        clock: SYSVAR_CLOCK_PUBKEY,
+      },
+      remainingAccounts: [
+        { pubkey: postedPrice, isWritable: false, isSigner: false },
+        ...remainingAccounts,
+      ],
+    });
+  };
+
+  before(async () => {
+    k = await initializeKurayashiki(program);
+
+    // mock whirlpool: token A = WSOL, token B = USDC, 1 SOL = 250 USDC
+    let bump: number;
+    [whirlpool, bump] = await PublicKey.findProgramAddress(
+      [Buffer.from("whirlpool"), NATIVE_MINT.toBuffer(), k.usdcToken.publicKey.toBuffer()],
+      mockWhirlpool.programId);
+    tokenVaultA = await Token.createWrappedNativeAccount(provider.connection, TOKEN_PROGRAM_ID, whirlpool, payer, 10 * anchor.web3.LAMPORTS_PER_SOL);
+    tokenVaultB = await k.usdcToken.createAccount(whirlpool);
+    await mockWhirlpool.rpc.initializePool(bump, new BN(1).shln(63), 3000, {
+      accounts: {
+        funder: provider.wallet.publicKey,
+        whirlpool,
+        tokenMintA: NATIVE_MINT,
+        tokenMintB: k.usdcToken.publicKey,
+        tokenVaultA,
+        tokenVaultB,
+        systemProgram: SystemProgram.programId,
+      },
+    });
+    await program.rpc.setSwapBackend({ whirlpool: {} }, mockWhirlpool.programId, whirlpool, {
+      accounts: {
+        creator: k.creator.publicKey,
+        config: k.config,
+      },
+      signers: [k.creator],
+    });
+
+    await program.rpc.initializePoolTwap({
+      accounts: {
+        creator: k.creator.publicKey,
+        config: k.config,
+        poolTwap: k.poolTwap,
+        usdcMint: k.usdcToken.publicKey,
+        systemProgram: SystemProgram.programId,
+      },
+      signers: [k.creator],
+    });
+
+    // a posted price source
+    oracleSources = await findAddress(program, "oracle_sources", k.creator.publicKey);
+    const indexBytes = Buffer.alloc(4);
+    [postedPrice,] = await PublicKey.findProgramAddress(
+      [Buffer.from("kurayashiki"), Buffer.from("nano_swap"), Buffer.from("posted_price"), indexBytes, k.creator.publicKey.toBuffer()],
+      program.programId);
+    await program.rpc.initializeOracleSources({
+      accounts: {
+        creator: k.creator.publicKey,
+        oracleSources,
+        systemProgram: SystemProgram.programId,
+      },
+      signers: [k.creator],
+    });
+    await program.rpc.initializePostedPrice(0, {
+      accounts: {
+        creator: k.creator.publicKey,
+        postedPrice,
+        systemProgram: SystemProgram.programId,
+      },
+      signers: [k.creator],
+    });
+    await program.rpc.postPrice(0, new BN(250_000_000), {
+      accounts: {
+        creator: k.creator.publicKey,
+        postedPrice,
+        clock: SYSVAR_CLOCK_PUBKEY,
+      },
+      signers: [k.creator],
+    });
+    await program.rpc.setOracleSources([{ sourceType: { posted: {} }, address: postedPrice, weight: 1, maxStalenessSlots: new BN(1000) }], 1, {
+      accounts: {
+        creator: k.creator.publicKey,
+        oracleSources,
+      },
+      signers: [k.creator],
+    });
+  });
+
+  it('records the spot price of the pool in convert_to_sol', async () => {
+    await k.usdcToken.mintTo(k.depositUsdc, payer, [], 25_000_000);
+    await program.rpc.convertToSol([], new BN(0), {
+      accounts: {
+        creator: k.creator.publicKey,
+        config: k.config,
+        depositSol: k.depositSol,
+        depositToken: k.depositUsdc,
+        temporaryDepositWsol: k.temporaryDepositWsol,
+        depositWsol: k.depositWsol,
+        poolTwap: k.poolTwap,
+        treasuryStats: k.treasuryStats,
+        wsolMint: NATIVE_MINT,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        rent: SYSVAR_RENT_PUBKEY,
+        clock: SYSVAR_CLOCK_PUBKEY,
+      },
+      remainingAccounts: swapAccounts(),
+    });
+
+    const poolTwap = await program.account.poolTwap.fetch(k.poolTwap);
+    assert.equal(poolTwap.lastPrice.toNumber(), 250_000_000);
+    assert.isAbove(poolTwap.observations[0].timestamp.toNumber(), 0);
+  });
+
+  it('requires the swap accounts in update_price once pool_twap is initialized', async () => {
+    try {
+      await updatePrice([]);
+      assert.fail("update_price should fail");
+    } catch (e) {
+      assert.equal(e.msg, "Swap pool accounts do not match the configuration.");
+    }
+  });
+
+  it('updates pool_twap in update_price', async () => {
+    const preCumulativePrice = (await program.account.poolTwap.fetch(k.poolTwap)).cumulativePrice;
+    await updatePrice(swapAccounts());
+
+    const poolTwap = await program.account.poolTwap.fetch(k.poolTwap);
+    assert.isTrue(poolTwap.cumulativePrice.gte(preCumulativePrice));
+    assert.equal(poolTwap.lastPrice.toNumber(), 250_000_000);
+
+    const priceInfo = await program.account.priceInfo.fetch(k.priceInfo);
+    assert.equal(priceInfo.currentUsdcPerSolPrice.toNumber(), 500_000_000);
+  });
+});
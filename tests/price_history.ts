@@ -0,0 +1,138 @@
+import * as anchor from '@project-serum/anchor';
+import { Program, BN } from '@project-serum/anchor';
+import { PublicKey, SystemProgram, SYSVAR_CLOCK_PUBKEY } from '@solana/web3.js';
+import { assert } from 'chai';
+import { Kurayashiki } from '../target/types/kurayashiki';
+import { KurayashikiAccounts, initializeKurayashiki, findAddress } from './utils';
+
+describe('price_history', () => {
+  const provider = anchor.Provider.env();
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.Kurayashiki as Program<Kurayashiki>;
+
+  let k: KurayashikiAccounts;
+  let oracleSources: PublicKey;
+  let postedPrice: PublicKey;
+
+  const postPrice = async (usdcPerSolPrice: number) => {
+    await program.rpc.postPrice(0, new BN(usdcPerSolPrice), {
+      accounts: {
+        creator: k.creator.publicKey,
+        postedPrice,
+        clock: SYSVAR_CLOCK_PUBKEY,
+      },
+      signers: [k.creator],
+    });
+  };
+
+  const updatePrice = async () => {
+    await program.rpc.updatePrice({
+      accounts: {
+        creator: k.creator.publicKey,
+        priceInfo: k.priceInfo,
+        oracleSources,
+        poolTwap: k.poolTwap,
+        priceHistory: k.priceHistory,
+        clock: SYSVAR_CLOCK_PUBKEY,
+      },
+      remainingAccounts: [{ pubkey: postedPrice, isWritable: false, isSigner: false }],
+    });
+  };
+
+  before(async () => {
+    k = await initializeKurayashiki(program);
+    oracleSources = await findAddress(program, "oracle_sources", k.creator.publicKey);
+    const indexBytes = Buffer.alloc(4);
+    [postedPrice,] = await PublicKey.findProgramAddress(
+      [Buffer.from("kurayashiki"), Buffer.from("nano_swap"), Buffer.from("posted_price"), indexBytes, k.creator.publicKey.toBuffer()],
+      program.programId);
+
+    await program.rpc.initializeOracleSources({
+      accounts: {
+        creator: k.creator.publicKey,
+        oracleSources,
+        systemProgram: SystemProgram.programId,
+      },
+      signers: [k.creator],
+    });
+    await program.rpc.initializePostedPrice(0, {
+      accounts: {
+        creator: k.creator.publicKey,
+        postedPrice,
+        systemProgram: SystemProgram.programId,
+      },
+      signers: [k.creator],
+    });
+    await program.rpc.setOracleSources([{ sourceType: { posted: {} }, address: postedPrice, weight: 1, maxStalenessSlots: new BN(1000) }], 1, {
+      accounts: {
+        creator: k.creator.publicKey,
+        oracleSources,
+      },
+      signers: [k.creator],
+    });
+  });
+
+  it('does not require price_history', async () => {
+    await postPrice(100_000_000);
+    await updatePrice();
+
+    const priceInfo = await program.account.priceInfo.fetch(k.priceInfo);
+    assert.equal(priceInfo.currentUsdcPerSolPrice.toNumber(), 200_000_000);
+  });
+
+  it('records accepted prices', async () => {
+    await program.rpc.initializePriceHistory({
+      accounts: {
+        creator: k.creator.publicKey,
+        priceHistory: k.priceHistory,
+        systemProgram: SystemProgram.programId,
+      },
+      signers: [k.creator],
+    });
+
+    await postPrice(101_000_000);
+    await updatePrice();
+    await program.rpc.setPriceManual(new BN(103_000_000), {
+      accounts: {
+        creator: k.creator.publicKey,
+        priceInfo: k.priceInfo,
+        oracleSources,
+        priceHistory: k.priceHistory,
+        clock: SYSVAR_CLOCK_PUBKEY,
+      },
+      signers: [k.creator],
+    });
+
+    const priceHistory = await program.account.priceHistory.fetch(k.priceHistory);
+    assert.equal(priceHistory.sequence.toNumber(), 2);
+
+    const oracleEntry = priceHistory.entries[0];
+    assert.equal(oracleEntry.sequence.toNumber(), 1);
+    assert.equal(oracleEntry.usdcPerSolPrice.toNumber(), 202_000_000);
+    assert.isFalse(oracleEntry.manual);
+    assert.equal(oracleEntry.oraclePrices[0].toNumber(), 101_000_000);
+    assert.equal(oracleEntry.oracleConfs[0].toNumber(), 0);
+    assert.isAbove(oracleEntry.updated.toNumber(), 0);
+
+    const manualEntry = priceHistory.entries[1];
+    assert.equal(manualEntry.sequence.toNumber(), 2);
+    assert.equal(manualEntry.usdcPerSolPrice.toNumber(), 206_000_000);
+    assert.isTrue(manualEntry.manual);
+    assert.equal(manualEntry.oraclePrices[0].toNumber(), 0);
+  });
+
+  it('wraps around after PRICE_HISTORY_ENTRIES entries', async () => {
+    for (let i = 0; i < 15; i++) {
+      await postPrice(110_000_000 + i);
+      await updatePrice();
+    }
+
+    // sequence 17 overwrites sequence 1
+    const priceHistory = await program.account.priceHistory.fetch(k.priceHistory);
+    assert.equal(priceHistory.sequence.toNumber(), 17);
+    assert.equal(priceHistory.entries[0].sequence.toNumber(), 17);
+    assert.equal(priceHistory.entries[0].oraclePrices[0].toNumber(), 110_000_014);
+    assert.equal(priceHistory.entries[1].sequence.toNumber(), 2);
+  });
+});
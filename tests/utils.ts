@@ -0,0 +1,76 @@
+import * as anchor from '@project-serum/anchor';
+import { Program } from '@project-serum/anchor';
+import { PublicKey, Keypair, SystemProgram, SYSVAR_RENT_PUBKEY } from '@solana/web3.js';
+import { TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID, NATIVE_MINT, Token } from '@solana/spl-token';
+import { Kurayashiki } from '../target/types/kurayashiki';
+
+export interface KurayashikiAccounts {
+  creator: Keypair;
+  usdcToken: Token;
+  priceInfo: PublicKey;
+  config: PublicKey;
+  depositSol: PublicKey;
+  depositUsdc: PublicKey;
+  depositWsol: PublicKey;
+  temporaryDepositWsol: PublicKey;
+  poolTwap: PublicKey;
+  priceHistory: PublicKey;
+  treasuryStats: PublicKey;
+  poolCursor: PublicKey;
+}
+
+export async function findAddress(program: Program<Kurayashiki>, name: string, creator: PublicKey): Promise<PublicKey> {
+  const [address,] = await PublicKey.findProgramAddress(
+    [Buffer.from("kurayashiki"), Buffer.from("nano_swap"), Buffer.from(name), creator.toBuffer()],
+    program.programId);
+  return address;
+}
+
+// every test file uses its own creator, because all kurayashiki PDAs are derived from the creator.
+export async function initializeKurayashiki(program: Program<Kurayashiki>): Promise<KurayashikiAccounts> {
+  const provider = program.provider;
+  const payer = (provider.wallet as anchor.Wallet).payer;
+
+  const creator = Keypair.generate();
+  await provider.connection.confirmTransaction(
+    await provider.connection.requestAirdrop(creator.publicKey, 100 * anchor.web3.LAMPORTS_PER_SOL));
+
+  const usdcToken = await Token.createMint(provider.connection, payer, payer.publicKey, null, 6, TOKEN_PROGRAM_ID);
+
+  const priceInfo = await findAddress(program, "price_info", creator.publicKey);
+  const config = await findAddress(program, "config", creator.publicKey);
+  const depositSol = await findAddress(program, "deposit_sol", creator.publicKey);
+  const temporaryDepositWsol = await findAddress(program, "temporary_deposit_wsol", creator.publicKey);
+  const poolTwap = await findAddress(program, "pool_twap", creator.publicKey);
+  const priceHistory = await findAddress(program, "price_history", creator.publicKey);
+  const treasuryStats = await findAddress(program, "treasury_stats", creator.publicKey);
+  const poolCursor = await findAddress(program, "pool_cursor", creator.publicKey);
+  const depositUsdc = await Token.getAssociatedTokenAddress(ASSOCIATED_TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, usdcToken.publicKey, depositSol, true);
+  const depositWsol = await Token.getAssociatedTokenAddress(ASSOCIATED_TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, NATIVE_MINT, depositSol, true);
+
+  await program.rpc.initialize({
+    accounts: {
+      creator: creator.publicKey,
+      priceInfo,
+      depositSol,
+      depositUsdc,
+      usdcMint: usdcToken.publicKey,
+      systemProgram: SystemProgram.programId,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      rent: SYSVAR_RENT_PUBKEY,
+    },
+    signers: [creator],
+  });
+
+  await program.rpc.initializeConfig({
+    accounts: {
+      creator: creator.publicKey,
+      config,
+      systemProgram: SystemProgram.programId,
+    },
+    signers: [creator],
+  });
+
+  return { creator, usdcToken, priceInfo, config, depositSol, depositUsdc, depositWsol, temporaryDepositWsol, poolTwap, priceHistory, treasuryStats, poolCursor };
+}